@@ -0,0 +1,557 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+use async_trait::async_trait;
+use futures_util::{Stream, TryStreamExt};
+use sqlparser::dialect::Dialect;
+use sqlx::{Column as _, Database, Describe, Row, TypeInfo};
+
+use crate::{
+    error::CommandError,
+    model::{Column, QueryResult, QuerySummary, StatementResult, Value},
+    mysql, postgres, sqlite,
+};
+
+// 結果の行を受け取る。カラム情報は毎回同じものを渡す
+pub type OnRows<'a> = &'a mut (dyn FnMut(&[Column], Vec<Vec<Value>>) + Send);
+
+// 一度に on_rows に渡す行数
+const ROW_BATCH_SIZE: usize = 1000;
+
+// データベースごとの差異はこのトレイトの実装に閉じ込める
+#[async_trait]
+pub trait DatabaseBackend: Send + Sync {
+    async fn connect(
+        url: String,
+        db: String,
+        user: String,
+        password: String,
+        read_only: bool,
+    ) -> Result<Self, CommandError>
+    where
+        Self: Sized;
+
+    // 読み取り専用の接続。問い合わせ以外の文は実行しない
+    fn read_only(&self) -> bool;
+
+    async fn close(&self) -> Result<(), CommandError>;
+
+    // query のプレースホルダーは placeholder で作ったもので、parameters はその順に並ぶ
+    // query_id は cancel で実行中のクエリを指すのに使う
+    // 結果の行は on_rows に少しずつ渡し、戻り値には含めない
+    // max_rows を超える行は読まずに、打ち切ったことを返す
+    async fn query(
+        &self,
+        query_id: &str,
+        query: String,
+        parameters: Vec<Value>,
+        max_rows: Option<usize>,
+        on_rows: OnRows<'_>,
+    ) -> Result<QuerySummary, sqlx::Error>;
+
+    // statements は (文, バインドする値) の並び。1 つの接続で順に実行するので、
+    // SET や一時テーブルは後の文に引き継がれる。失敗した文があれば、そこで止める
+    async fn execute_script(
+        &self,
+        query_id: &str,
+        statements: Vec<(String, Vec<Value>)>,
+        max_rows: Option<usize>,
+    ) -> Result<Vec<StatementResult>, sqlx::Error>;
+
+    // 実行中のクエリを止める。実行中でなければ何もしない
+    async fn cancel(&self, query_id: &str) -> Result<(), CommandError>;
+
+    // cancel で止めたクエリかを返す。結果を受け取った後に 1 度だけ呼ぶ
+    fn take_cancelled(&self, query_id: &str) -> bool;
+
+    // index は 1 始まり
+    fn placeholder(&self, index: usize) -> String;
+
+    // query(placeholder で作ったプレースホルダーを持つ SQL)のパラメーターごとに、
+    // プレースホルダーの後に付けるキャスト。値をそのままバインドできない型は、文字列で渡してサーバーで変換する
+    // キャストが要らなければ、続けて query で同じ SQL を実行する
+    async fn parameter_casts(&self, _query: &str) -> Result<Vec<Option<String>>, sqlx::Error> {
+        Ok(vec![])
+    }
+
+    fn dialect(&self) -> Box<dyn Dialect>;
+}
+
+// プールの接続の、データベース側の id。接続を作ったときに after_connect で 1 度だけ調べる
+// プールの接続は 1 本なので、最後に作った接続の id が、acquire で受け取る接続の id になる
+#[derive(Default, Clone)]
+pub struct ConnectionId(Arc<AtomicU64>);
+
+impl ConnectionId {
+    pub fn set(&self, id: u64) {
+        self.0.store(id, Ordering::SeqCst);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// 実行中のクエリ。キャンセルするときに、クエリの id から接続の id を引く
+#[derive(Default)]
+pub struct RunningQueries {
+    // クエリの id => 実行中のクエリ
+    connections: Mutex<HashMap<String, Arc<Running>>>,
+    // 実行を始める前(接続を待っている間など)に届いたキャンセル。start で取り出す
+    // connections のロックを取ってから触るので、start と cancel が重なっても取りこぼさない
+    pending: Mutex<HashSet<String>>,
+    // キャンセルしたクエリの id。take_cancelled で消す
+    cancelled: Mutex<HashSet<String>>,
+}
+
+struct Running {
+    // データベース側の接続の id
+    connection_id: u64,
+    // 終了したか。キャンセルの送信と終了が重ならないよう、どちらもこのロックを取ってから行う
+    // 終了した後に送ると、接続は 1 本なので次のクエリを止めてしまう
+    finished: tokio::sync::Mutex<bool>,
+}
+
+// 他のスレッドがロック中に panic していても、1 件の追加や削除で壊れることは無いのでそのまま使う
+impl RunningQueries {
+    // finish を呼ぶ(または戻り値が破棄される)まで、クエリを実行中とする
+    // 始める前にキャンセルされていれば、キャンセルしたことを記録してエラーを返す
+    pub fn start(
+        &self,
+        query_id: &str,
+        connection_id: u64,
+    ) -> Result<RunningQuery<'_>, sqlx::Error> {
+        let mut connections = self
+            .connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(query_id);
+        if pending {
+            self.cancelled
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(query_id.to_string());
+            return Err(sqlx::Error::Io(std::io::ErrorKind::Interrupted.into()));
+        }
+
+        let running = Arc::new(Running {
+            connection_id,
+            finished: tokio::sync::Mutex::new(false),
+        });
+        connections.insert(query_id.to_string(), running.clone());
+
+        Ok(RunningQuery {
+            queries: self,
+            query_id: query_id.to_string(),
+            running,
+        })
+    }
+
+    // 実行中であれば、接続の id を kill に渡してクエリを止め、キャンセルしたことを記録する
+    // kill を送り終わるまで finish を待たせるので、接続が次のクエリに使われることは無い
+    // まだ始まっていなければ、start で止めるよう記録しておく
+    // (終了した後に届いた場合も記録は残るが、クエリの id は毎回違うので使われない)
+    pub async fn cancel<F, Fut>(&self, query_id: &str, kill: F) -> Result<(), CommandError>
+    where
+        F: FnOnce(u64) -> Fut,
+        Fut: Future<Output = Result<(), sqlx::Error>>,
+    {
+        let running = {
+            let connections = self
+                .connections
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let running = connections.get(query_id).cloned();
+            if running.is_none() {
+                self.pending
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(query_id.to_string());
+            }
+            running
+        };
+        let Some(running) = running else {
+            return Ok(());
+        };
+
+        // 送る直前に、まだ終了していないことを確かめる
+        let finished = running.finished.lock().await;
+        if *finished {
+            return Ok(());
+        }
+        kill(running.connection_id).await?;
+        self.cancelled
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(query_id.to_string());
+
+        Ok(())
+    }
+
+    pub fn take_cancelled(&self, query_id: &str) -> bool {
+        self.cancelled
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(query_id)
+    }
+}
+
+pub struct RunningQuery<'a> {
+    queries: &'a RunningQueries,
+    query_id: String,
+    running: Arc<Running>,
+}
+
+impl RunningQuery<'_> {
+    // 接続を返す前に呼ぶ。送っている途中のキャンセルがあれば、送り終わるのを待つ
+    pub async fn finish(self) {
+        *self.running.finished.lock().await = true;
+    }
+}
+
+impl Drop for RunningQuery<'_> {
+    fn drop(&mut self) {
+        // finish を呼ばずに破棄された場合も、これから送られるキャンセルは送らせない
+        if let Ok(mut finished) = self.running.finished.try_lock() {
+            *finished = true;
+        }
+        self.queries
+            .connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.query_id);
+    }
+}
+
+// データベースを追加する場合は、ここに db_type を追加する
+pub async fn connect(
+    db_type: String,
+    url: String,
+    db: String,
+    user: String,
+    password: String,
+    read_only: bool,
+) -> Result<Box<dyn DatabaseBackend>, CommandError> {
+    match db_type.as_str() {
+        "postgres" => Ok(Box::new(
+            postgres::PostgresBackend::connect(url, db, user, password, read_only).await?,
+        )),
+        "mysql" => Ok(Box::new(
+            mysql::MySqlBackend::connect(url, db, user, password, read_only).await?,
+        )),
+        "sqlite" => Ok(Box::new(
+            sqlite::SqliteBackend::connect(url, db, user, password, read_only).await?,
+        )),
+        _ => Err(CommandError::other(format!("Unknown db_type: {}", db_type))),
+    }
+}
+
+// 結果が 0 行でもヘッダーを出せるよう、カラム情報は describe から作る
+// describe できない文の場合は 1 行目のカラム情報で代用する
+pub fn to_columns<DB: Database>(
+    describe: Result<Describe<DB>, sqlx::Error>,
+    first_row: Option<&DB::Row>,
+    tables: Vec<Option<String>>,
+) -> Vec<Column> {
+    let (columns, describe) = match &describe {
+        Ok(describe) => (describe.columns(), Some(describe)),
+        Err(_) => match first_row {
+            Some(row) => (row.columns(), None),
+            None => return vec![],
+        },
+    };
+
+    columns
+        .iter()
+        .map(|column| Column {
+            ordinal: column.ordinal(),
+            name: column.name().to_string(),
+            type_name: column.type_info().name().to_string(),
+            nullable: describe.and_then(|d| d.nullable(column.ordinal())),
+            table: tables.get(column.ordinal()).cloned().flatten(),
+        })
+        .collect()
+}
+
+// 行を ROW_BATCH_SIZE 行ずつ on_rows に渡す。戻り値は (カラム情報, 全体の行数, 打ち切ったか)
+// max_rows 行を渡した後にまだ行があれば、残りは読まずに打ち切る
+pub async fn stream_rows<DB, S>(
+    mut stream: S,
+    describe: Result<Describe<DB>, sqlx::Error>,
+    tables: Vec<Option<String>>,
+    to_value: fn(&DB::Row, &DB::Column) -> Value,
+    max_rows: Option<usize>,
+    on_rows: OnRows<'_>,
+) -> Result<(Vec<Column>, usize, bool), sqlx::Error>
+where
+    DB: Database,
+    S: Stream<Item = Result<DB::Row, sqlx::Error>> + Unpin,
+{
+    // describe できない文のカラム情報には 1 行目を使うので、先に読んでおく
+    let mut next = stream.try_next().await?;
+    let columns = to_columns(describe, next.as_ref(), tables);
+
+    let mut total_rows = 0;
+    let mut truncated = false;
+    let mut batch = vec![];
+    while let Some(row) = next {
+        if max_rows == Some(total_rows + batch.len()) {
+            truncated = true;
+            break;
+        }
+        batch.push(
+            row.columns()
+                .iter()
+                .map(|column| to_value(&row, column))
+                .collect(),
+        );
+        if batch.len() == ROW_BATCH_SIZE {
+            total_rows += batch.len();
+            on_rows(&columns, std::mem::take(&mut batch));
+        }
+        next = stream.try_next().await?;
+    }
+    if !batch.is_empty() {
+        total_rows += batch.len();
+        on_rows(&columns, batch);
+    }
+
+    Ok((columns, total_rows, truncated))
+}
+
+// 実行した文の結果と、開始してからの時間を StatementResult にする
+pub fn to_statement_result(
+    sql: String,
+    result: Result<QueryResult, sqlx::Error>,
+    start: Instant,
+) -> StatementResult {
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+    match result {
+        Ok(result) => StatementResult {
+            sql,
+            span: None,
+            result: Some(result),
+            elapsed_ms,
+            error: None,
+        },
+        Err(e) => StatementResult {
+            error: Some(CommandError::from_database(e, &sql)),
+            sql,
+            span: None,
+            result: None,
+            elapsed_ms,
+        },
+    }
+}
+
+// デコードの結果を Value にする。失敗した場合は、エラーと生のバイト列を持たせる
+pub fn decoded(value: Result<Value, String>, bytes: impl FnOnce() -> Vec<u8>) -> Value {
+    match value {
+        Ok(value) => value,
+        Err(message) => Value::DecodeError {
+            message,
+            bytes: bytes(),
+        },
+    }
+}
+
+// Decimal の値を、バインドする数値の型にする
+// 数値として読めない場合は NULL にせず、バインドのエラーにする
+pub fn parse_decimal<T>(text: &str) -> Result<T, sqlx::Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    text.trim()
+        .parse::<T>()
+        .map_err(|e| sqlx::Error::Encode(format!("{}: {}", text, e).into()))
+}
+
+// f32 をそのまま f64 にすると 0.1 が 0.10000000149011612 になるため、文字列表現を経由する
+pub fn f32_to_f64(v: f32) -> f64 {
+    v.to_string().parse().unwrap_or(v as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use futures_util::stream;
+    use sqlx::sqlite::{SqliteColumn, SqliteRow};
+    use sqlx::{Connection, Executor, Sqlite, SqliteConnection};
+
+    use super::*;
+
+    // 1 から n までの行と、その SELECT の describe
+    async fn numbers(n: usize) -> (Vec<SqliteRow>, Describe<Sqlite>) {
+        let sql = "WITH RECURSIVE c(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM c) \
+            SELECT n FROM c LIMIT ?";
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        let rows = sqlx::query(sql)
+            .bind(n as i64)
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+        let describe = (&mut conn).describe(sql).await.unwrap();
+        (rows, describe)
+    }
+
+    fn to_value(row: &SqliteRow, column: &SqliteColumn) -> Value {
+        Value::Int(row.get(column.ordinal()))
+    }
+
+    // (各 on_rows の行数, 渡した値, カラム名, 全体の行数, 打ち切ったか)
+    async fn stream_numbers(
+        n: usize,
+        max_rows: Option<usize>,
+    ) -> (Vec<usize>, Vec<Value>, Vec<String>, usize, bool) {
+        let (rows, describe) = numbers(n).await;
+        let mut batches = vec![];
+        let mut values = vec![];
+        let (columns, total_rows, truncated) = stream_rows(
+            stream::iter(rows.into_iter().map(Ok)),
+            Ok(describe),
+            vec![],
+            to_value,
+            max_rows,
+            &mut |_, batch| {
+                batches.push(batch.len());
+                values.extend(batch.into_iter().flatten());
+            },
+        )
+        .await
+        .unwrap();
+        let names = columns.into_iter().map(|c| c.name).collect();
+        (batches, values, names, total_rows, truncated)
+    }
+
+    #[tokio::test]
+    async fn stream_rows_sends_full_batches() {
+        let (batches, values, _, total_rows, truncated) =
+            stream_numbers(ROW_BATCH_SIZE, None).await;
+        assert_eq!(batches, vec![ROW_BATCH_SIZE]);
+        assert_eq!((total_rows, truncated), (ROW_BATCH_SIZE, false));
+        assert_eq!(values.last(), Some(&Value::Int(ROW_BATCH_SIZE as i64)));
+
+        let (batches, values, _, total_rows, truncated) =
+            stream_numbers(ROW_BATCH_SIZE + 1, None).await;
+        assert_eq!(batches, vec![ROW_BATCH_SIZE, 1]);
+        assert_eq!((total_rows, truncated), (ROW_BATCH_SIZE + 1, false));
+        assert_eq!(
+            values[ROW_BATCH_SIZE - 1..],
+            [
+                Value::Int(ROW_BATCH_SIZE as i64),
+                Value::Int(ROW_BATCH_SIZE as i64 + 1)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_rows_truncates_only_beyond_max_rows() {
+        // ちょうど max_rows 行なら打ち切っていない
+        let (batches, _, _, total_rows, truncated) = stream_numbers(5, Some(5)).await;
+        assert_eq!(batches, vec![5]);
+        assert_eq!((total_rows, truncated), (5, false));
+
+        let (batches, values, _, total_rows, truncated) = stream_numbers(6, Some(5)).await;
+        assert_eq!(batches, vec![5]);
+        assert_eq!(values.last(), Some(&Value::Int(5)));
+        assert_eq!((total_rows, truncated), (5, true));
+
+        let (batches, _, _, total_rows, truncated) =
+            stream_numbers(ROW_BATCH_SIZE + 1, Some(ROW_BATCH_SIZE)).await;
+        assert_eq!(batches, vec![ROW_BATCH_SIZE]);
+        assert_eq!((total_rows, truncated), (ROW_BATCH_SIZE, true));
+    }
+
+    #[tokio::test]
+    async fn stream_rows_takes_columns_from_describe_when_empty() {
+        let (batches, _, names, total_rows, truncated) = stream_numbers(0, None).await;
+        assert!(batches.is_empty());
+        assert_eq!(names, vec!["n"]);
+        assert_eq!((total_rows, truncated), (0, false));
+
+        // describe できない場合は 1 行目のカラム情報を使い、行も無ければカラム情報は無い
+        for (n, expected) in [(1, vec!["n"]), (0, vec![])] {
+            let (rows, _) = numbers(n).await;
+            let (columns, total_rows, _) = stream_rows(
+                stream::iter(rows.into_iter().map(Ok)),
+                Err::<Describe<Sqlite>, _>(sqlx::Error::RowNotFound),
+                vec![],
+                to_value,
+                None,
+                &mut |_, _| {},
+            )
+            .await
+            .unwrap();
+            let names: Vec<String> = columns.into_iter().map(|c| c.name).collect();
+            assert_eq!(names, expected);
+            assert_eq!(total_rows, n);
+        }
+    }
+
+    #[tokio::test]
+    async fn finish_waits_for_kill() {
+        let queries = RunningQueries::default();
+        let running = queries.start("q", 42).unwrap();
+        let killed = &AtomicBool::new(false);
+
+        // kill を送っている途中に終了しても、送り終わるまで finish は戻らない
+        let cancel = queries.cancel("q", |id| async move {
+            assert_eq!(id, 42);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            killed.store(true, Ordering::SeqCst);
+            Ok(())
+        });
+        let finish = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            running.finish().await;
+            assert!(killed.load(Ordering::SeqCst));
+        };
+        let (cancelled, _) = tokio::join!(cancel, finish);
+
+        assert!(cancelled.is_ok());
+        assert!(queries.take_cancelled("q"));
+        assert!(!queries.take_cancelled("q"));
+    }
+
+    #[tokio::test]
+    async fn cancel_after_finish_does_not_kill() {
+        let queries = RunningQueries::default();
+        queries.start("q", 42).unwrap().finish().await;
+
+        let result = queries
+            .cancel("q", |_| async { panic!("killed a finished query") })
+            .await;
+
+        assert!(result.is_ok());
+        assert!(!queries.take_cancelled("q"));
+    }
+
+    #[tokio::test]
+    async fn cancel_before_start_stops_the_query() {
+        let queries = RunningQueries::default();
+
+        let result = queries
+            .cancel("q", |_| async {
+                panic!("killed a query that has not started")
+            })
+            .await;
+        assert!(result.is_ok());
+
+        assert!(queries.start("q", 42).is_err());
+        assert!(queries.take_cancelled("q"));
+        // 記録は 1 度で消えるので、同じ id でも次は実行できる
+        assert!(queries.start("q", 42).is_ok());
+    }
+}
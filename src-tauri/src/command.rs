@@ -1,11 +1,20 @@
-use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sqlparser::dialect::Dialect;
 
 use tauri::webview::WebviewWindowBuilder;
 use tauri::{AppHandle, Emitter, Listener, State};
 
 use crate::{
-    model::{Column, Parameter},
-    mysql, postgres, sql_parser, AppState,
+    backend::{self, DatabaseBackend},
+    error::CommandError,
+    model::{
+        Column, Parameter, ParameterInfo, QueryResult, QuerySource, QuerySummary, RowBatch,
+        SelectStatements, Span, StatementResult, SubqueryNode, Value,
+    },
+    parameter::{self, BoundQuery},
+    sql_parser, AppState,
 };
 
 #[tauri::command]
@@ -16,131 +25,376 @@ pub async fn connect_command(
     db: String,
     user: String,
     password: String,
-) -> Result<(), String> {
+    read_only: bool,
+) -> Result<(), CommandError> {
     println!("connect_command!");
 
-    if db_type == String::from("postgres") {
-        let pool = postgres::create_postgres_connection_pool(url, db, user, password).await?;
-
-        *state.db_type.lock().await = Some(String::from("postgres"));
-        *state.pg_pool.lock().await = Some(pool);
-    } else if db_type == String::from("mysql") {
-        let pool = mysql::create_mysql_connection_pool(url, db, user, password).await?;
+    let backend = backend::connect(db_type, url, db, user, password, read_only).await?;
 
-        *state.db_type.lock().await = Some(String::from("mysql"));
-        *state.mysql_pool.lock().await = Some(pool);
-    } else {
-        return Err(String::from("Unknown db_type."));
+    let mut current = state.backend.lock().await;
+    if let Some(old) = current.take() {
+        old.close().await?;
     }
+    *current = Some(Arc::from(backend));
 
     Ok(())
 }
 
 #[tauri::command]
-pub async fn close_command(state: State<'_, AppState>) -> Result<(), String> {
+pub async fn close_command(state: State<'_, AppState>) -> Result<(), CommandError> {
     println!("close_command!");
-    let state = state.clone();
-    let db_type = state.db_type.lock().await.clone();
-
-    if let Some(db_type) = &db_type {
-        if db_type == &String::from("postgres") {
-            let pool = state.pg_pool.clone();
-
-            postgres::close_postgres_connection_pool(pool).await?;
 
-            *state.pg_pool.lock().await = None;
-        } else if db_type == &String::from("mysql") {
-            let pool = state.mysql_pool.clone();
+    let backend = state.backend.lock().await.take();
 
-            mysql::close_mysql_connection_pool(pool).await?;
-
-            *state.mysql_pool.lock().await = None;
-        } else {
-            return Err(String::from("Unknown db_type."));
-        }
+    if let Some(backend) = backend {
+        backend.close().await?;
     }
 
     Ok(())
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn query_command(
+    app: AppHandle,
     state: State<'_, AppState>,
+    query_id: String,
     query: String,
-) -> Result<(Vec<Column>, Vec<HashMap<String, String>>), String> {
+    parameter_pattern: String,
+    parameters: Vec<Parameter>,
+    row_limit: Option<u64>,
+    source: Option<QuerySource>,
+) -> Result<QuerySummary, CommandError> {
     println!("query_command!");
-    let state = state.clone();
-    let db_type = state.db_type.lock().await.clone();
-
-    if let Some(db_type) = &db_type {
-        if db_type == &String::from("postgres") {
-            let state = state.clone();
-            let pool = state.pg_pool.clone();
-
-            let result = postgres::query_to_postgres(&pool, query).await;
-
-            let result = match result {
-                Ok(r) => r,
-                Err(e) => return Err(e.to_string()),
-            };
-            return Ok(result);
-        } else if db_type == &String::from("mysql") {
-            let state = state.clone();
-            let pool = state.mysql_pool.clone();
-
-            let result = mysql::query_to_mysql(&pool, query).await;
-
-            let result = match result {
-                Ok(r) => r,
-                Err(e) => return Err(e.to_string()),
-            };
-            return Ok(result);
-        } else {
-            return Err(String::from("Unknown database type"));
+
+    let backend = match state.backend.lock().await.as_ref() {
+        Some(b) => b.clone(),
+        None => return Err(CommandError::NotConnected),
+    };
+
+    check_read_only(backend.as_ref(), &query, &parameter_pattern)
+        .map_err(|e| locate_in_source(e, &query, source.as_ref()))?;
+
+    // パラメーターは SQL に埋め込まず、プレースホルダーにしてバインドする
+    let (bound, sent) = bind_parameters(
+        backend.as_ref(),
+        &query,
+        &parameter_pattern,
+        &parameters,
+        row_limit,
+    )
+    .await?;
+
+    // 結果の行は、全部そろうのを待たずに query_rows イベントで送る
+    // フロントエンドは totalRows 行が届くまで待つので、送れなかった場合はコマンドを失敗させる
+    let mut offset = 0;
+    let mut emit_error = None;
+    let mut on_rows = |columns: &[Column], rows: Vec<Vec<Value>>| {
+        if emit_error.is_some() {
+            return;
+        }
+        let batch = RowBatch {
+            query_id: query_id.clone(),
+            offset,
+            columns: columns.to_vec(),
+            rows,
+        };
+        offset += batch.rows.len();
+        if let Err(e) = app.emit("query_rows", batch) {
+            emit_error = Some(e);
         }
+    };
+    let max_rows = row_limit.map(|n| n as usize);
+    let result = backend
+        .query(
+            &query_id,
+            sent.clone(),
+            bound.values.clone(),
+            max_rows,
+            &mut on_rows,
+        )
+        .await;
+
+    // キャンセルした場合は、データベースのエラーではなくキャンセルされたことを返す
+    let cancelled = backend.take_cancelled(&query_id);
+    let result = match result {
+        Ok(r) => r,
+        Err(_) if cancelled => return Err(CommandError::Cancelled),
+        Err(e) => {
+            // エラーの位置は、実行した SQL から入力した SQL の位置に戻す
+            let error = CommandError::from_database(e, &sent)
+                .relocate(&query, |offset| to_query_offset(&bound, &sent, offset));
+            return Err(locate_in_source(error, &query, source.as_ref()));
+        }
+    };
+    if let Some(e) = emit_error {
+        return Err(e.into());
     }
 
-    Err(String::from("Unknown database type"))
+    Ok(result)
 }
 
+// スクリプトを文に分けて、1 つの接続で順に実行する
 #[tauri::command]
-pub async fn find_select_statement_command(
+pub async fn execute_script_command(
     state: State<'_, AppState>,
+    query_id: String,
     query: String,
-) -> Result<(Vec<String>, Vec<String>), String> {
-    println!("find_select_statement_command!");
+    parameter_pattern: String,
+    parameters: Vec<Parameter>,
+    row_limit: Option<u64>,
+) -> Result<Vec<StatementResult>, CommandError> {
+    println!("execute_script_command!");
 
-    let state = state.clone();
-    let db_type = state.db_type.lock().await.clone();
+    let backend = match state.backend.lock().await.as_ref() {
+        Some(b) => b.clone(),
+        None => return Err(CommandError::NotConnected),
+    };
+
+    let statements =
+        parameter::split_statements(backend.dialect().as_ref(), &query, &parameter_pattern)?;
 
-    let select_statements = if db_type == Some(String::from("postgres")) {
-        sql_parser::find_postgres_select_statement(&query).await
-    } else if db_type == Some(String::from("mysql")) {
-        sql_parser::find_mysql_select_statement(&query).await
-    } else {
-        return Err(String::from("Unknown db_type."));
+    // 文の中の位置を、スクリプト全体の中の位置にする
+    let to_script = |error: CommandError, span: &Option<Span>| {
+        error.relocate(&query, |offset| {
+            span.as_ref().map(|span| span.start + offset)
+        })
     };
 
-    let result = match select_statements {
+    // (バインドした文, 実行する SQL)
+    let mut bound = vec![];
+    for (statement, span) in &statements {
+        check_read_only(backend.as_ref(), statement, &parameter_pattern)
+            .map_err(|e| to_script(e, span))?;
+        bound.push(
+            bind_parameters(
+                backend.as_ref(),
+                statement,
+                &parameter_pattern,
+                &parameters,
+                row_limit,
+            )
+            .await?,
+        );
+    }
+
+    let max_rows = row_limit.map(|n| n as usize);
+    let script = bound
+        .iter()
+        .map(|(bound_query, sent)| (sent.clone(), bound_query.values.clone()))
+        .collect();
+    let result = backend.execute_script(&query_id, script, max_rows).await;
+
+    // 途中でキャンセルした場合、止まった文のエラーをキャンセルされたことにする
+    let cancelled = backend.take_cancelled(&query_id);
+    let mut results = match result {
         Ok(r) => r,
-        Err(e) => return Err(e.to_string()),
+        Err(_) if cancelled => return Err(CommandError::Cancelled),
+        Err(e) => return Err(e.into()),
     };
+    if cancelled {
+        if let Some(last) = results.last_mut().filter(|r| r.error.is_some()) {
+            last.error = Some(CommandError::Cancelled);
+        }
+    }
 
-    Ok(result)
+    // 結果にはプレースホルダーにする前の文と、その位置を返す
+    // エラーの位置も、実行した SQL からスクリプト全体の中の位置に戻す
+    for ((result, (statement, span)), (bound_query, sent)) in
+        results.iter_mut().zip(statements).zip(&bound)
+    {
+        result.error = result.error.take().map(|error| {
+            let error = error.relocate(&statement, |offset| {
+                to_query_offset(bound_query, sent, offset)
+            });
+            to_script(error, &span)
+        });
+        result.sql = statement;
+        result.span = span;
+    }
+
+    Ok(results)
+}
+
+// パラメーターをプレースホルダーにし、行数の上限を付けた実行する SQL と合わせて返す
+// そのままバインドできない型のパラメーターには、バックエンドが返すキャストをプレースホルダーの後に付ける
+// (位置の対応は BoundQuery が持つ)。キャストは実行する SQL で調べ、バックエンドはその結果を実行時に使える
+async fn bind_parameters(
+    backend: &dyn DatabaseBackend,
+    query: &str,
+    parameter_pattern: &str,
+    parameters: &[Parameter],
+    row_limit: Option<u64>,
+) -> Result<(BoundQuery, String), CommandError> {
+    let bound = parameter::bind_parameters(query, parameter_pattern, parameters, |i| {
+        backend.placeholder(i)
+    })?;
+    let sent = limit_rows(backend, bound.sql.clone(), row_limit);
+    if bound.values.is_empty() {
+        return Ok((bound, sent));
+    }
+
+    let casts = backend.parameter_casts(&sent).await?;
+    if casts.iter().all(Option::is_none) {
+        return Ok((bound, sent));
+    }
+    let bound = parameter::bind_parameters(query, parameter_pattern, parameters, |i| match casts
+        .get(i - 1)
+    {
+        Some(Some(cast)) => format!("{}{}", backend.placeholder(i), cast),
+        _ => backend.placeholder(i),
+    })?;
+    let sent = limit_rows(backend, bound.sql.clone(), row_limit);
+    Ok((bound, sent))
+}
+
+// 読み取り専用の接続では、問い合わせ以外の文を実行しない
+// 種類を判断できない(パースできない)文も実行しない
+// バインドする前の SQL で調べるので、パースエラーの位置は入力した SQL のものになる
+fn check_read_only(
+    backend: &dyn DatabaseBackend,
+    query: &str,
+    parameter_pattern: &str,
+) -> Result<(), CommandError> {
+    if !backend.read_only() {
+        return Ok(());
+    }
+
+    match parameter::find_non_query(backend.dialect().as_ref(), query, parameter_pattern) {
+        Ok(None) => Ok(()),
+        Ok(Some(statement)) => Err(CommandError::ReadOnly { statement }),
+        Err(CommandError::Parse {
+            message,
+            position,
+            expected,
+            found,
+        }) => Err(CommandError::Parse {
+            message: format!(
+                "Statements that cannot be parsed are not allowed on a read-only connection: {}",
+                message
+            ),
+            position,
+            expected,
+            found,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+// 実行した SQL(sent)中の位置を、バインドする前の SQL 中の位置にする
+// LIMIT を付けるときに作り直して変わった部分は、元の SQL の位置が分からないので None
+fn to_query_offset(bound: &BoundQuery, sent: &str, offset: usize) -> Option<usize> {
+    (bound.sql.get(..offset)? == sent.get(..offset)?).then(|| bound.original_offset(offset))
+}
+
+// source から取り出して実行した SQL(query)のエラーの位置を、source の SQL 中の位置にする
+// query は取り出した部分の前に CTE を付けたもの(runnable_sql)でもよい
+// 取り出した部分の外を指す場合や、部分を書き換えている場合は位置を外す
+fn locate_in_source(
+    error: CommandError,
+    query: &str,
+    source: Option<&QuerySource>,
+) -> CommandError {
+    let Some(source) = source else {
+        return error;
+    };
+
+    error.relocate(&source.sql, |offset| {
+        let fragment = source.sql.get(source.span.start..source.span.end)?;
+        let prefix = query.strip_suffix(fragment)?.len();
+        Some(source.span.start + offset.checked_sub(prefix)?)
+    })
+}
+
+// 行数の上限があれば、サーバー側でも打ち切るよう LIMIT を付ける
+// 付けられない文でも、上限を超えた行は backend が読まずに打ち切る
+fn limit_rows(backend: &dyn DatabaseBackend, query: String, row_limit: Option<u64>) -> String {
+    let limited =
+        row_limit.and_then(|n| sql_parser::limit_rows(backend.dialect().as_ref(), &query, n));
+
+    limited.unwrap_or(query)
 }
 
+// query_command や execute_script_command に渡した query_id のクエリを止める
+#[tauri::command]
+pub async fn cancel_query_command(
+    state: State<'_, AppState>,
+    query_id: String,
+) -> Result<(), CommandError> {
+    println!("cancel_query_command!");
+
+    let backend = match state.backend.lock().await.as_ref() {
+        Some(b) => b.clone(),
+        None => return Err(CommandError::NotConnected),
+    };
+
+    backend.cancel(&query_id).await
+}
+
+// recover の場合、解析できない文があっても、; で区切った他の文からは抽出する
+// dialect を指定すれば、接続していなくても解析できる
+#[tauri::command]
+pub async fn find_select_statement_command(
+    state: State<'_, AppState>,
+    query: String,
+    parameter_pattern: String,
+    recover: bool,
+    dialect: Option<String>,
+) -> Result<SelectStatements, CommandError> {
+    println!("find_select_statement_command!");
+
+    let dialect = resolve_dialect(&state, dialect).await?;
+
+    parameter::find_select_statement(dialect.as_ref(), &query, &parameter_pattern, recover)
+}
+
+#[tauri::command]
+pub async fn find_parameters_command(
+    state: State<'_, AppState>,
+    query: String,
+    parameter_pattern: String,
+    dialect: Option<String>,
+) -> Result<Vec<ParameterInfo>, CommandError> {
+    println!("find_parameters_command!");
+
+    let dialect = resolve_dialect(&state, dialect).await?;
+
+    parameter::discover_parameters(dialect.as_ref(), &query, &parameter_pattern)
+}
+
+// 解析に使う dialect。指定が無ければ接続先のものを使う
+async fn resolve_dialect(
+    state: &AppState,
+    dialect: Option<String>,
+) -> Result<Box<dyn Dialect>, CommandError> {
+    match dialect {
+        Some(name) => sql_parser::dialect_from_name(&name)
+            .ok_or_else(|| CommandError::other(format!("Unknown dialect: {}", name))),
+        None => match state.backend.lock().await.as_ref() {
+            Some(b) => Ok(b.dialect()),
+            None => Err(CommandError::NotConnected),
+        },
+    }
+}
+
+// open_new_statement_window_command で、新しいウィンドウの準備ができるのを待つ時間
+const WINDOW_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[tauri::command]
 pub async fn open_new_statement_window_command(
     app: AppHandle,
     parameter_pattern: String,
     parameters: Vec<Parameter>,
-    select_statements: Vec<String>,
-    columns: Vec<Column>,
-    query_result: Vec<HashMap<String, String>>,
-) -> Result<(), tauri::Error> {
+    select_statements: Vec<SubqueryNode>,
+    query_result: QueryResult,
+    row_limit: Option<u64>,
+) -> Result<(), CommandError> {
     println!("open_new_statement_window_command!");
 
-    let md5 = md5::compute(select_statements[0].clone());
+    let Some(first) = select_statements.first() else {
+        return Err(CommandError::other("No select statement to open"));
+    };
+    let md5 = md5::compute(first.sql.clone());
     let window_label = format!("select_{:x}", md5);
 
     let builder = WebviewWindowBuilder::new(
@@ -149,24 +403,155 @@ pub async fn open_new_statement_window_command(
         tauri::WebviewUrl::App("statement.html".into()),
     );
 
-    let new_webview = builder.title(select_statements[0].clone()).build()?;
+    let new_webview = builder.title(first.sql.clone()).build()?;
 
+    // 新しいウィンドウがデータを受け取れるようになったら(done イベント)、データを送る
+    let (done, ready) = tokio::sync::oneshot::channel();
     new_webview.once("done", move |_| {
-        app.emit_to(
-            window_label,
-            "data",
-            (
-                parameter_pattern,
-                parameters,
-                select_statements,
-                columns,
-                query_result,
-            ),
-        )
-        .expect("emit_to error.");
+        // 送れないのは、コマンドが待つのをやめてエラーを返した後だけ
+        let _ = done.send(());
     });
 
     new_webview.show()?;
 
+    // 準備ができる前にウィンドウが閉じられた場合なども、送れなかったことをエラーで返す
+    match tokio::time::timeout(WINDOW_READY_TIMEOUT, ready).await {
+        Ok(Ok(())) => {}
+        _ => {
+            return Err(CommandError::other(
+                "The statement window did not become ready",
+            ))
+        }
+    }
+    app.emit_to(
+        window_label,
+        "data",
+        (
+            parameter_pattern,
+            parameters,
+            select_statements,
+            query_result,
+            row_limit,
+        ),
+    )?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+
+    use sqlparser::dialect::{MySqlDialect, PostgreSqlDialect, SQLiteDialect};
+
+    use super::*;
+    use crate::error::DatabaseError;
+
+    fn database_error(sql: &str, token: &str) -> CommandError {
+        let start = sql.find(token).unwrap();
+        CommandError::Database(Box::new(DatabaseError {
+            position: Some(sql_parser::to_span(sql, start, start + token.len())),
+            ..Default::default()
+        }))
+    }
+
+    fn position(error: CommandError) -> Option<Span> {
+        match error {
+            CommandError::Database(database_error) => database_error.position,
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_dialect_prefers_the_named_dialect() {
+        let state = AppState {
+            backend: Arc::new(tokio::sync::Mutex::new(None)),
+        };
+        let dialect_id = |dialect: Result<Box<dyn Dialect>, CommandError>| {
+            dialect.map(|d| d.dialect()).map_err(|e| e.to_string())
+        };
+
+        // 接続していなければ、名前の指定が要る
+        assert_eq!(
+            dialect_id(resolve_dialect(&state, None).await),
+            Err(CommandError::NotConnected.to_string())
+        );
+        assert_eq!(
+            dialect_id(resolve_dialect(&state, Some(String::from("mysql"))).await),
+            Ok(TypeId::of::<MySqlDialect>())
+        );
+        assert_eq!(
+            dialect_id(resolve_dialect(&state, Some(String::from("oracle"))).await),
+            Err(String::from("Unknown dialect: oracle"))
+        );
+
+        // 名前の指定が無ければ、接続先の dialect を使う
+        let backend = backend::connect(
+            String::from("sqlite"),
+            String::from(":memory:"),
+            String::new(),
+            String::new(),
+            String::new(),
+            false,
+        )
+        .await
+        .unwrap();
+        *state.backend.lock().await = Some(Arc::from(backend));
+        assert_eq!(
+            dialect_id(resolve_dialect(&state, None).await),
+            Ok(TypeId::of::<SQLiteDialect>())
+        );
+        assert_eq!(
+            dialect_id(resolve_dialect(&state, Some(String::from("postgres"))).await),
+            Ok(TypeId::of::<PostgreSqlDialect>())
+        );
+    }
+
+    #[test]
+    fn locate_in_source_maps_runnable_sql_positions() {
+        let sql = "SELECT 'あいう' AS 列\nFROM (SELECT 名前 FROM テーブル) x";
+        let fragment = "SELECT 名前 FROM テーブル";
+        let start = sql.find(fragment).unwrap();
+        let source = QuerySource {
+            sql: sql.to_string(),
+            span: sql_parser::to_span(sql, start, start + fragment.len()),
+        };
+        let expected = sql.find("テーブル").unwrap();
+
+        // 取り出した部分をそのまま実行した場合と、CTE を前に付けて実行した場合
+        for query in [
+            fragment.to_string(),
+            format!("WITH 前 AS (SELECT 1)\n{}", fragment),
+        ] {
+            let span = position(locate_in_source(
+                database_error(&query, "テーブル"),
+                &query,
+                Some(&source),
+            ))
+            .unwrap();
+            assert_eq!(
+                (span.start, span.end, span.line, span.column),
+                (expected, expected + "テーブル".len(), 2, 22),
+                "{}",
+                query
+            );
+        }
+
+        // 前に付けた CTE の中の位置は外す
+        let query = format!("WITH テーブル AS (SELECT 1)\n{}", fragment);
+        let error = locate_in_source(database_error(&query, "テーブル"), &query, Some(&source));
+        assert_eq!(position(error), None);
+
+        // 取り出した部分を書き換えて実行した場合も外す
+        let query = "SELECT 名前 FROM テーブル LIMIT 1";
+        let error = locate_in_source(database_error(query, "テーブル"), query, Some(&source));
+        assert_eq!(position(error), None);
+    }
+
+    #[test]
+    fn locate_in_source_without_source_keeps_position() {
+        let query = "SELECT 'é' FROM t";
+        let error = locate_in_source(database_error(query, "t"), query, None);
+        assert_eq!(position(error).map(|s| (s.start, s.column)), Some((17, 17)));
+    }
+}
@@ -0,0 +1,288 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use sqlx::mysql::MySqlDatabaseError;
+use sqlx::postgres::{PgDatabaseError, PgErrorPosition};
+
+use crate::model::Span;
+use crate::sql_parser;
+
+// コマンドのエラー。フロントエンドには { "kind": "Database", "message": "...", ... } の形で渡る
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all_fields = "camelCase")]
+pub enum CommandError {
+    // データベースに接続していない
+    NotConnected,
+    // SQL をパースできない。position は元の SQL 中の位置(分かる場合)
+    // expected と found は、期待したトークンと見つかったトークン(分かる場合)
+    Parse {
+        message: String,
+        position: Option<Span>,
+        expected: Option<String>,
+        found: Option<String>,
+    },
+    // データベースが返したエラー
+    Database(Box<DatabaseError>),
+    // 結果の値を取得(デコード)できない
+    Decode {
+        message: String,
+    },
+    // cancel_query_command で止めた
+    Cancelled,
+    // 接続を待つ間に時間切れになった
+    Timeout,
+    // 読み取り専用の接続で、問い合わせ以外の文を実行しようとした
+    ReadOnly {
+        statement: String,
+    },
+    // 上のどれにも当たらないもの(不明な db_type、ウィンドウの操作の失敗など)
+    Other {
+        message: String,
+    },
+}
+
+// データベースが返したエラーの詳細。code は SQLSTATE など
+// detail, hint, position は PostgreSQL のみ、number は MySQL のみ
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseError {
+    pub message: String,
+    pub code: Option<String>,
+    pub detail: Option<String>,
+    pub hint: Option<String>,
+    // エラーになったトークンの、入力した SQL 中の位置
+    pub position: Option<Span>,
+    // MySQL のエラー番号(1146 など)
+    pub number: Option<u16>,
+}
+
+impl CommandError {
+    pub fn other(message: impl Display) -> Self {
+        CommandError::Other {
+            message: message.to_string(),
+        }
+    }
+
+    // データベースのエラーを CommandError にする。位置は sql(実行した SQL)中の位置にする
+    pub fn from_database(e: sqlx::Error, sql: &str) -> Self {
+        let span = database_position(&e).and_then(|position| position_to_span(sql, position));
+
+        let mut error = CommandError::from(e);
+        if let CommandError::Database(database_error) = &mut error {
+            database_error.position = span;
+        }
+        error
+    }
+
+    // 位置を持つエラー(Parse, Database)の位置を、別の SQL(text)中の位置に付け替える
+    // to_text は位置を text のバイト位置にする。付け替えられない場合は位置を外す
+    pub fn relocate(mut self, text: &str, to_text: impl Fn(usize) -> Option<usize>) -> Self {
+        let position = match &mut self {
+            CommandError::Parse { position, .. } => position,
+            CommandError::Database(database_error) => &mut database_error.position,
+            _ => return self,
+        };
+        *position = position.take().and_then(|span| {
+            let start = to_text(span.start)?;
+            let end = to_text(span.end)?;
+            (start <= end && text.get(start..end).is_some())
+                .then(|| sql_parser::to_span(text, start, end))
+        });
+        self
+    }
+}
+
+// PostgreSQL のエラーの位置(実行した SQL の何文字目か、1 始まり)
+fn database_position(e: &sqlx::Error) -> Option<usize> {
+    let sqlx::Error::Database(database_error) = e else {
+        return None;
+    };
+    match database_error
+        .try_downcast_ref::<PgDatabaseError>()?
+        .position()?
+    {
+        PgErrorPosition::Original(position) => Some(position),
+        // 関数の中で実行した SQL など、入力した SQL の外の位置
+        PgErrorPosition::Internal { .. } => None,
+    }
+}
+
+// エラーの位置(sql の何文字目か、1 始まり)を、sql 中のそのトークンの範囲にする
+fn position_to_span(sql: &str, position: usize) -> Option<Span> {
+    let (offset, _) = sql.char_indices().nth(position.saturating_sub(1))?;
+    Some(sql_parser::to_span(
+        sql,
+        offset,
+        sql_parser::token_end(sql, offset),
+    ))
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::NotConnected => write!(f, "Not connected to a database"),
+            CommandError::Parse { message, .. } => write!(f, "{}", message),
+            CommandError::Database(database_error) => match &database_error.code {
+                Some(code) => write!(f, "{} ({})", database_error.message, code),
+                None => write!(f, "{}", database_error.message),
+            },
+            CommandError::Decode { message } => write!(f, "{}", message),
+            CommandError::Cancelled => write!(f, "Query was cancelled"),
+            CommandError::Timeout => write!(f, "Timed out waiting for a connection"),
+            CommandError::ReadOnly { statement } => write!(
+                f,
+                "{} statements are not allowed on a read-only connection",
+                statement
+            ),
+            CommandError::Other { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<sqlx::Error> for CommandError {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::Database(database_error) => {
+                let postgres = database_error.try_downcast_ref::<PgDatabaseError>();
+                let mysql = database_error.try_downcast_ref::<MySqlDatabaseError>();
+                CommandError::Database(Box::new(DatabaseError {
+                    message: database_error.message().to_string(),
+                    code: database_error.code().map(|code| code.to_string()),
+                    detail: postgres.and_then(|e| e.detail()).map(String::from),
+                    hint: postgres.and_then(|e| e.hint()).map(String::from),
+                    // 位置は実行した SQL が分かる from_database で付ける
+                    position: None,
+                    number: mysql.map(|e| e.number()),
+                }))
+            }
+            sqlx::Error::ColumnDecode { .. } | sqlx::Error::Decode(_) => CommandError::Decode {
+                message: e.to_string(),
+            },
+            // パラメーターの値をバインドできない(型に合わない値など)
+            sqlx::Error::Encode(_) => CommandError::other(&e),
+            sqlx::Error::PoolTimedOut => CommandError::Timeout,
+            // 実行中に切断した場合
+            sqlx::Error::PoolClosed => CommandError::NotConnected,
+            _ => CommandError::Database(Box::new(DatabaseError {
+                message: e.to_string(),
+                ..Default::default()
+            })),
+        }
+    }
+}
+
+impl From<tauri::Error> for CommandError {
+    fn from(e: tauri::Error) -> Self {
+        CommandError::other(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(position: Span) -> CommandError {
+        CommandError::Parse {
+            message: String::from("error"),
+            position: Some(position),
+            expected: None,
+            found: None,
+        }
+    }
+
+    fn position(error: &CommandError) -> Option<Span> {
+        match error {
+            CommandError::Parse { position, .. } => position.clone(),
+            CommandError::Database(database_error) => database_error.position.clone(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn position_to_span_counts_characters() {
+        let sql = "SELECT 'あいう', 名前\nFROM テーブル";
+        let cases = [
+            // 名前 は 15 文字目
+            (15, Some((20, 26, 1, 15))),
+            // テーブル は 2 行目の 6 文字目
+            (23, Some((32, 44, 2, 6))),
+            (8, Some((7, 18, 1, 8))),
+            (1, Some((0, 6, 1, 1))),
+            (27, None),
+        ];
+        for (position, expected) in cases {
+            let span = position_to_span(sql, position);
+            assert_eq!(
+                span.map(|s| (s.start, s.end, s.line, s.column)),
+                expected,
+                "{}",
+                position
+            );
+        }
+    }
+
+    #[test]
+    fn relocate_recomputes_line_and_column() {
+        let text = "-- 日本語のコメント\nSELECT 'é' FROM t;\nSELECT 名前 FROM u";
+        let statement = "SELECT 名前 FROM u";
+        let offset = text.find(statement).unwrap();
+        // 文の中の u の位置
+        let start = statement.find('u').unwrap();
+        let error = parse_error(sql_parser::to_span(statement, start, start + 1));
+
+        let relocated = error.clone().relocate(text, |i| Some(offset + i));
+        assert_eq!(
+            position(&relocated),
+            Some(Span {
+                start: offset + start,
+                end: offset + start + 1,
+                line: 3,
+                column: 16,
+            })
+        );
+        assert_eq!(&text[offset + start..offset + start + 1], "u");
+
+        // 付け替えられない位置は外す
+        let relocated = error.clone().relocate(text, |_| None);
+        assert_eq!(position(&relocated), None);
+        // 文字の途中を指す位置も外す
+        let relocated = error.relocate(text, |i| Some(i + 4));
+        assert_eq!(position(&relocated), None);
+    }
+
+    #[test]
+    fn relocate_keeps_errors_without_position() {
+        let relocated = CommandError::Cancelled.relocate("SELECT 1", |i| Some(i + 1));
+        assert!(matches!(relocated, CommandError::Cancelled));
+    }
+
+    // `docker compose up -d postgres` の後に `cargo test -- --ignored` で実行する
+    #[tokio::test]
+    #[ignore = "requires the postgres service in docker-compose.yml"]
+    async fn from_database_locates_postgres_errors() {
+        dotenv::from_filename("../.env").ok();
+        let env = |key: &str, default: &str| std::env::var(key).unwrap_or(default.to_string());
+        let pool = crate::postgres::create_postgres_connection_pool(
+            format!(
+                "{}:{}",
+                env("POSTGRES_HOSTNAME", "localhost"),
+                env("POSTGRES_PORT", "5432")
+            ),
+            env("POSTGRES_DB", "postgres"),
+            env("POSTGRES_USER", "postgres"),
+            env("POSTGRES_PASSWORD", "postgres"),
+            crate::backend::ConnectionId::default(),
+        )
+        .await
+        .unwrap();
+
+        let sql = "SELECT 'あいう',\n  名前 FROM 存在しないテーブル";
+        let e = sqlx::query(sql).execute(&pool).await.unwrap_err();
+        pool.close().await;
+
+        let error = CommandError::from_database(e, sql);
+        let span = position(&error).unwrap();
+        assert_eq!(&sql[span.start..span.end], "存在しないテーブル");
+        assert_eq!((span.line, span.column), (2, 11));
+    }
+}
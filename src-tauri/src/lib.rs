@@ -1,22 +1,23 @@
-use std::{env, sync::Arc};
+use std::sync::Arc;
 
 use dotenv::dotenv;
 
-use sqlx::{MySql, Pool, Postgres};
-
 use tauri::Manager;
 use tokio::sync::Mutex;
 
+mod backend;
 mod command;
+mod error;
 mod model;
 mod mysql;
+mod parameter;
 mod postgres;
 mod sql_parser;
+mod sqlite;
 
+// クエリの実行中もキャンセルできるよう、backend は Arc で取り出してからロックを外す
 pub struct AppState {
-    pub db_type: Arc<Mutex<Option<String>>>,
-    pub pg_pool: Arc<Mutex<Option<Pool<Postgres>>>>,
-    pub mysql_pool: Arc<Mutex<Option<Pool<MySql>>>>,
+    pub backend: Arc<Mutex<Option<Arc<dyn backend::DatabaseBackend>>>>,
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -26,9 +27,7 @@ pub async fn run() {
     tauri::Builder::default()
         .setup(move |app| {
             app.manage(AppState {
-                db_type: Arc::new(Mutex::new(None)),
-                pg_pool: Arc::new(Mutex::new(None)),
-                mysql_pool: Arc::new(Mutex::new(None)),
+                backend: Arc::new(Mutex::new(None)),
             });
             Ok(())
         })
@@ -39,7 +38,10 @@ pub async fn run() {
             command::connect_command,
             command::close_command,
             command::query_command,
+            command::execute_script_command,
+            command::cancel_query_command,
             command::find_select_statement_command,
+            command::find_parameters_command,
             command::open_new_statement_window_command,
         ])
         .run(tauri::generate_context!())
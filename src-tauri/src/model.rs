@@ -1,9 +1,18 @@
 use serde::{Deserialize, Serialize};
 
+use crate::error::CommandError;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct Column {
     pub ordinal: usize,
     pub name: String,
+    pub type_name: String,
+    // 不明な場合は None
+    pub nullable: Option<bool>,
+    // データベースから受け取ったものではなく、SQL の FROM 句の別名やテーブル名から推測したもの
+    // (sql_parser::find_projection_tables)。式のカラムや、推測できない場合は None
+    pub table: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -11,3 +20,238 @@ pub struct Parameter {
     pub name: String,
     pub value: String,
 }
+
+// 元の SQL 中の範囲。start/end はバイト位置、line/column は開始位置で 1 始まり(column は文字数)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+// サブクエリがどの句に書かれていたか。最上位の文は Statement
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ClauseKind {
+    Statement,
+    Cte,
+    SelectList,
+    From,
+    Join,
+    Where,
+    GroupBy,
+    Having,
+    OrderBy,
+    Limit,
+    SetOperation,
+    // UPDATE の SET 句
+    Set,
+    // INSERT や CREATE TABLE AS の SELECT、MERGE の USING
+    Source,
+    Other,
+}
+
+// 抽出したサブクエリ。parent_id をたどると木構造になる
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SubqueryNode {
+    pub id: usize,
+    // 最上位の文は None
+    pub parent_id: Option<usize>,
+    pub clause: ClauseKind,
+    // FROM 句のサブクエリや CTE の別名
+    pub alias: Option<String>,
+    pub sql: String,
+    // 元の SQL 中の位置。UPDATE などのプレビューは文全体の位置
+    // sqlparser が位置を持たないもの(VALUES だけの行など)は None
+    pub span: Option<Span>,
+    // 参照している CTE を前に付けた、単独で実行できる SQL
+    pub runnable_sql: String,
+    // runnable_sql の前に付けた CTE の名前
+    pub ctes: Vec<String>,
+    // INSERT や UPDATE などの対象行のプレビューなら、元の文の種類("UPDATE" など)
+    // UPDATE, DELETE, MERGE の sql は、対象行を選ぶ SELECT に書き換えたもの
+    pub preview_of: Option<String>,
+    // 集合演算(UNION など)のノードなら、その演算子
+    pub set_operation: Option<SetOperation>,
+    // 外側のクエリのカラムを参照している(相関サブクエリ)か
+    pub correlated: bool,
+    pub outer_references: Vec<OuterReference>,
+}
+
+// サブクエリの抽出結果。errors は回復モードで解析できなかった文のエラー
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectStatements {
+    pub nodes: Vec<SubqueryNode>,
+    pub errors: Vec<CommandError>,
+}
+
+// 集合演算の演算子("UNION" など)と、ALL や DISTINCT の指定(無ければ None)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SetOperation {
+    pub operator: String,
+    pub quantifier: Option<String>,
+}
+
+// 相関サブクエリが参照している外側のカラム
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OuterReference {
+    // SQL 中の参照(o.id など)
+    pub sql: String,
+    // テーブルの別名(スキーマ付きの場合は schema.table)
+    pub qualifier: String,
+    pub column: String,
+    // runnable_sql の中で、この参照を置き換えたパラメーターの名前
+    pub parameter_name: String,
+}
+
+// SQL から見つけたパラメーター。guessed_type は "number", "text", "boolean", "date",
+// "timestamp", "time" または CAST 先の型名で、推測できなければ None
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ParameterInfo {
+    pub name: String,
+    pub spans: Vec<Span>,
+    pub guessed_type: Option<String>,
+}
+
+// フロントエンドには { "type": "Int", "value": 1 } の形で渡る
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "value")]
+pub enum Value {
+    Null,
+    Bool(bool),
+    // JavaScript の number で正確に表せない値は、文字列で渡す
+    Int(#[serde(with = "safe_integer")] i64),
+    Float(f64),
+    // 精度を落とさないよう文字列で持つ
+    Decimal(String),
+    Text(String),
+    Date(String),
+    Time(String),
+    Timestamp(String),
+    Json(serde_json::Value),
+    Bytes(Vec<u8>),
+    // 取得(デコード)できなかった値。SQL の NULL とは区別し、エラーと生のバイト列を持つ
+    DecodeError { message: String, bytes: Vec<u8> },
+}
+
+// rows の各行は columns と同じ並び(ordinal 順)で値を持つ
+// rows_affected は INSERT や UPDATE などで変更された行数
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryResult {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Value>>,
+    pub rows_affected: u64,
+    // 行数の上限で打ち切ったか
+    pub truncated: bool,
+}
+
+// query_command が少しずつ送る結果の行。offset は rows の最初の行が何行目か(0 始まり)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RowBatch {
+    pub query_id: String,
+    pub offset: usize,
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+// query_command の戻り値。行は RowBatch で送り終えている
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QuerySummary {
+    pub columns: Vec<Column>,
+    pub total_rows: usize,
+    pub rows_affected: u64,
+    pub truncated: bool,
+}
+
+// 実行する SQL を取り出した元の SQL と、その中の位置(サブクエリを実行する場合など)
+// エラーの位置を元の SQL の位置にするのに使う
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QuerySource {
+    pub sql: String,
+    pub span: Span,
+}
+
+// スクリプトの各文の実行結果。失敗した文は result が None で error を持つ
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StatementResult {
+    pub sql: String,
+    // スクリプト中の文の位置
+    pub span: Option<Span>,
+    pub result: Option<QueryResult>,
+    pub elapsed_ms: u64,
+    pub error: Option<CommandError>,
+}
+
+// JavaScript の number で正確に表せる整数の最大値(Number.MAX_SAFE_INTEGER)
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+// 範囲外の整数は丸められないよう文字列にする。受け取る場合はどちらの形でもよい
+mod safe_integer {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+        if value.unsigned_abs() <= super::MAX_SAFE_INTEGER {
+            serializer.serialize_i64(*value)
+        } else {
+            serializer.collect_str(value)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Integer {
+            Number(i64),
+            Text(String),
+        }
+
+        match Integer::deserialize(deserializer)? {
+            Integer::Number(value) => Ok(value),
+            Integer::Text(value) => value.parse().map_err(D::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn large_integers_are_serialized_as_strings() {
+        let cases = [
+            (Value::Int(1), json!({ "type": "Int", "value": 1 })),
+            (
+                Value::Int(9007199254740991),
+                json!({ "type": "Int", "value": 9007199254740991_i64 }),
+            ),
+            (
+                Value::Int(9007199254740992),
+                json!({ "type": "Int", "value": "9007199254740992" }),
+            ),
+            (
+                Value::Int(-9007199254740993),
+                json!({ "type": "Int", "value": "-9007199254740993" }),
+            ),
+            (
+                Value::Int(i64::MIN),
+                json!({ "type": "Int", "value": "-9223372036854775808" }),
+            ),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(serde_json::to_value(&value).unwrap(), expected);
+            assert_eq!(serde_json::from_value::<Value>(expected).unwrap(), value);
+        }
+    }
+}
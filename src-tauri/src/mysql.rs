@@ -1,192 +1,756 @@
-use std::{collections::HashMap, sync::Arc};
+use std::time::Instant;
 
-use sqlx::mysql::MySqlPoolOptions;
-use sqlx::types::chrono::NaiveDate;
+use async_trait::async_trait;
+use sqlparser::dialect::{Dialect, MySqlDialect};
+
+use sqlx::mysql::types::MySqlTime;
+use sqlx::mysql::{MySqlArguments, MySqlColumn, MySqlConnection, MySqlPoolOptions, MySqlRow};
+use sqlx::query::Query;
+use sqlx::types::chrono::{NaiveDate, NaiveDateTime};
 use sqlx::types::BigDecimal;
 use sqlx::Column;
+use sqlx::ConnectOptions;
+use sqlx::Connection;
+use sqlx::Decode;
 use sqlx::Error;
+use sqlx::Executor;
 use sqlx::MySql;
 use sqlx::Pool;
 use sqlx::Row;
+use sqlx::Type;
 use sqlx::TypeInfo;
+use sqlx::ValueRef;
+
+use crate::backend::{self, f32_to_f64, ConnectionId, DatabaseBackend, OnRows, RunningQueries};
+use crate::error::CommandError;
+use crate::model::{QueryResult, QuerySummary, StatementResult, Value};
+use crate::parameter;
+use crate::sql_parser;
+
+pub struct MySqlBackend {
+    pool: Pool<MySql>,
+    running: RunningQueries,
+    // キャンセルするときに指定する、プールの接続のサーバー側の id
+    connection_id: ConnectionId,
+    read_only: bool,
+}
+
+#[async_trait]
+impl DatabaseBackend for MySqlBackend {
+    async fn connect(
+        url: String,
+        db: String,
+        user: String,
+        password: String,
+        read_only: bool,
+    ) -> Result<Self, CommandError> {
+        let connection_id = ConnectionId::default();
+        let pool =
+            create_mysql_connection_pool(url, db, user, password, connection_id.clone()).await?;
+
+        Ok(MySqlBackend {
+            pool,
+            running: RunningQueries::default(),
+            connection_id,
+            read_only,
+        })
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    async fn close(&self) -> Result<(), CommandError> {
+        self.pool.close().await;
+
+        Ok(())
+    }
+
+    async fn query(
+        &self,
+        query_id: &str,
+        query: String,
+        parameters: Vec<Value>,
+        max_rows: Option<usize>,
+        on_rows: OnRows<'_>,
+    ) -> Result<QuerySummary, Error> {
+        let mut conn = self.pool.acquire().await?;
+        let running = self.running.start(query_id, self.connection_id.get())?;
+
+        let result = async {
+            self.begin(&mut conn).await?;
+            let result = stream_mysql(&mut conn, query, parameters, max_rows, on_rows).await;
+            let end = self.end(&mut conn).await;
+
+            let summary = result?;
+            end?;
+            Ok(summary)
+        }
+        .await;
+        // 接続を返す前に、送っている途中のキャンセルを待つ
+        running.finish().await;
+
+        result
+    }
+
+    async fn execute_script(
+        &self,
+        query_id: &str,
+        statements: Vec<(String, Vec<Value>)>,
+        max_rows: Option<usize>,
+    ) -> Result<Vec<StatementResult>, Error> {
+        let mut conn = self.pool.acquire().await?;
+        let running = self.running.start(query_id, self.connection_id.get())?;
+
+        let result = async {
+            self.begin(&mut conn).await?;
+            let mut results = vec![];
+            for (query, parameters) in statements {
+                let start = Instant::now();
+                let result = query_to_mysql(&mut conn, query.clone(), parameters, max_rows).await;
+                let failed = result.is_err();
+                results.push(backend::to_statement_result(query, result, start));
+                if failed {
+                    break;
+                }
+            }
+            self.end(&mut conn).await?;
+
+            Ok(results)
+        }
+        .await;
+        // 接続を返す前に、送っている途中のキャンセルを待つ
+        running.finish().await;
+
+        result
+    }
+
+    async fn cancel(&self, query_id: &str) -> Result<(), CommandError> {
+        self.running
+            .cancel(query_id, |id| kill_query(&self.pool, id))
+            .await
+    }
+
+    fn take_cancelled(&self, query_id: &str) -> bool {
+        self.running.take_cancelled(query_id)
+    }
+
+    fn placeholder(&self, _index: usize) -> String {
+        String::from("?")
+    }
+
+    fn dialect(&self) -> Box<dyn Dialect> {
+        Box::new(MySqlDialect {})
+    }
+}
+
+impl MySqlBackend {
+    // 読み取り専用の接続では、READ ONLY トランザクションの中で実行する
+    async fn begin(&self, conn: &mut MySqlConnection) -> Result<(), Error> {
+        if self.read_only {
+            conn.execute("START TRANSACTION READ ONLY").await?;
+        }
+        Ok(())
+    }
 
-use tokio::sync::Mutex;
+    // 読み取り専用の接続では、何も残さないようロールバックする
+    async fn end(&self, conn: &mut MySqlConnection) -> Result<(), Error> {
+        if self.read_only {
+            conn.execute("ROLLBACK").await?;
+        }
+        Ok(())
+    }
+}
 
 pub async fn create_mysql_connection_pool(
     url: String,
     db: String,
     user: String,
     password: String,
-) -> Result<Pool<MySql>, String> {
+    connection_id: ConnectionId,
+) -> Result<Pool<MySql>, CommandError> {
     let database_url = format!("mysql://{}:{}@{}/{}", user, password, url, db);
 
+    // 接続のサーバー側の id は、接続を作ったときに 1 度だけ調べる
     let result = MySqlPoolOptions::new()
         .max_connections(1)
+        .after_connect(move |conn, _| {
+            let connection_id = connection_id.clone();
+            Box::pin(async move {
+                connection_id.set(server_connection_id(conn).await?);
+                Ok(())
+            })
+        })
         .connect(&database_url)
         .await;
 
     let result = match result {
         Ok(r) => r,
-        Err(e) => return Err(e.to_string()),
+        Err(e) => return Err(e.into()),
     };
 
     Ok(result)
 }
 
-pub async fn close_mysql_connection_pool(
-    pool: Arc<Mutex<Option<Pool<MySql>>>>,
-) -> Result<(), String> {
+// キャンセルするときに指定する、サーバー側の接続 ID
+async fn server_connection_id(conn: &mut MySqlConnection) -> Result<u64, Error> {
+    sqlx::query_scalar("SELECT CONNECTION_ID()")
+        .fetch_one(&mut *conn)
+        .await
+}
+
+// 実行中の接続はふさがっているので、プールの外の接続からキャンセルする
+async fn kill_query(pool: &Pool<MySql>, id: u64) -> Result<(), Error> {
+    let mut conn = pool.connect_options().connect().await?;
+    // KILL はプレースホルダーを受け付けないので、数値を埋め込む
+    conn.execute(format!("KILL QUERY {}", id).as_str()).await?;
+    conn.close().await
+}
+
+// 結果の行は on_rows に少しずつ渡し、手元には残さない
+pub async fn stream_mysql(
+    conn: &mut MySqlConnection,
+    query: String,
+    parameters: Vec<Value>,
+    max_rows: Option<usize>,
+    on_rows: OnRows<'_>,
+) -> Result<QuerySummary, Error> {
+    let describe = (&mut *conn).describe(&query).await;
+
+    let mut sql_query = sqlx::query(&query);
+    for value in parameters {
+        sql_query = bind_value(sql_query, value)?;
+    }
+
+    // 結果のカラムが無い文(INSERT や SET など)は、変更された行数を受け取る
+    if matches!(&describe, Ok(d) if d.columns().is_empty()) {
+        let done = sql_query.execute(&mut *conn).await?;
+        return Ok(QuerySummary {
+            columns: vec![],
+            total_rows: 0,
+            rows_affected: done.rows_affected(),
+            truncated: false,
+        });
+    }
 
-    let pool = pool.lock().await;
-    pool.clone().unwrap().close().await;
-    drop(pool);
+    let tables = sql_parser::find_projection_tables(&MySqlDialect {}, &query);
+    let (columns, total_rows, truncated) = backend::stream_rows(
+        sql_query.fetch(&mut *conn),
+        describe,
+        tables,
+        to_value,
+        max_rows,
+        on_rows,
+    )
+    .await?;
 
-    Ok(())
+    Ok(QuerySummary {
+        columns,
+        total_rows,
+        rows_affected: 0,
+        truncated,
+    })
 }
 
+// スクリプトの実行など、結果をまとめて受け取る場合に使う
 pub async fn query_to_mysql(
-    pool: &Arc<Mutex<Option<Pool<MySql>>>>,
+    conn: &mut MySqlConnection,
     query: String,
-) -> Result<(Vec<crate::model::Column>, Vec<HashMap<String, String>>), Error> {
-    let pool = pool.lock().await;
-    let pool = pool.clone().unwrap();
-    let query_result = sqlx::query(&query).fetch_all(&pool).await?;
-    drop(pool);
-
-    let mut result: Vec<HashMap<String, String>> = vec![];
-    for row in &query_result {
-        let mut map: HashMap<String, String> = HashMap::new();
-
-        for column in row.columns() {
-            let type_info = column.type_info();
-            let type_name = type_info.name();
-            match type_name {
-                "FLOAT4" => {
-                    let value: Result<f32, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "FLOAT8" => {
-                    let value: Result<f64, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "DECIMAL" => {
-                    let value: Result<BigDecimal, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "BOOL" => {
-                    let value: Result<bool, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "INT1" => {
-                    let value: Result<i8, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "SMALLINT" => {
-                    let value: Result<i16, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "INT" => {
-                    let value: Result<i32, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "BIGINT" => {
-                    let value: Result<i64, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "CHAR" => {
-                    let value: Result<String, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "VARCHAR" => {
-                    let value: Result<String, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "TEXT" => {
-                    let value: Result<String, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "DATE" => {
-                    let value: Result<NaiveDate, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                _ => {
-                    println!("{}", type_name);
-                }
+    parameters: Vec<Value>,
+    max_rows: Option<usize>,
+) -> Result<QueryResult, Error> {
+    let mut rows = vec![];
+    let summary = stream_mysql(conn, query, parameters, max_rows, &mut |_, batch| {
+        rows.extend(batch)
+    })
+    .await?;
+
+    Ok(QueryResult {
+        columns: summary.columns,
+        rows,
+        rows_affected: summary.rows_affected,
+        truncated: summary.truncated,
+    })
+}
+
+fn bind_value<'q>(
+    query: Query<'q, MySql, MySqlArguments>,
+    value: Value,
+) -> Result<Query<'q, MySql, MySqlArguments>, Error> {
+    // MySQL は文字列を比較先の型に変換してくれるので、数値以外は文字列で渡す
+    let query = match value {
+        Value::Null => query.bind(None::<String>),
+        Value::Bool(v) => query.bind(v),
+        Value::Int(v) => query.bind(v),
+        Value::Float(v) => query.bind(v),
+        // 桁を落とさないよう DECIMAL として渡す
+        Value::Decimal(v) => query.bind(backend::parse_decimal::<BigDecimal>(&v)?),
+        Value::Bytes(v) => query.bind(v),
+        v => query.bind(parameter::to_text(&v)),
+    };
+
+    Ok(query)
+}
+
+// NULL は to_value で先に除いているので、ここでの失敗はデコードの失敗
+fn get<'r, T>(row: &'r MySqlRow, ordinal: usize) -> Result<T, String>
+where
+    T: Decode<'r, MySql> + Type<MySql>,
+{
+    row.try_get::<T, _>(ordinal).map_err(|e| e.to_string())
+}
+
+fn get_bytes(row: &MySqlRow, ordinal: usize) -> Result<&[u8], String> {
+    // sqlx が対応していない型(YEAR, BIT, GEOMETRY など)は、値のバイト列を直接読む
+    row.try_get_unchecked::<&[u8], _>(ordinal)
+        .map_err(|e| e.to_string())
+}
+
+fn to_value(row: &MySqlRow, column: &MySqlColumn) -> Value {
+    let ordinal = column.ordinal();
+    let type_info = column.type_info();
+    let type_name = type_info.name();
+
+    if row.try_get_raw(ordinal).is_ok_and(|raw| raw.is_null()) {
+        return Value::Null;
+    }
+    let invalid = || format!("Invalid {} value", type_name);
+
+    let value = match type_name {
+        // TINYINT(1)
+        "BOOLEAN" => get::<bool>(row, ordinal).map(Value::Bool),
+        "TINYINT" | "SMALLINT" | "MEDIUMINT" | "INT" | "BIGINT" => {
+            get::<i64>(row, ordinal).map(Value::Int)
+        }
+        "TINYINT UNSIGNED" | "SMALLINT UNSIGNED" | "MEDIUMINT UNSIGNED" | "INT UNSIGNED"
+        | "BIGINT UNSIGNED" => get::<u64>(row, ordinal).map(unsigned_to_value),
+        "FLOAT" => get::<f32>(row, ordinal).map(|v| Value::Float(f32_to_f64(v))),
+        "DOUBLE" => get::<f64>(row, ordinal).map(Value::Float),
+        "DECIMAL" => get::<BigDecimal>(row, ordinal).map(|v| Value::Decimal(v.to_string())),
+        "CHAR" | "VARCHAR" | "TINYTEXT" | "TEXT" | "MEDIUMTEXT" | "LONGTEXT" | "ENUM" | "SET" => {
+            get::<String>(row, ordinal).map(Value::Text)
+        }
+        "BINARY" | "VARBINARY" | "TINYBLOB" | "BLOB" | "MEDIUMBLOB" | "LONGBLOB" => {
+            get::<Vec<u8>>(row, ordinal).map(Value::Bytes)
+        }
+        "DATE" => get::<NaiveDate>(row, ordinal).map(|v| Value::Date(v.to_string())),
+        "TIME" => get::<MySqlTime>(row, ordinal).map(|v| Value::Time(v.to_string())),
+        // TIMESTAMP はセッションのタイムゾーンで返ってくるので、DATETIME と同じく見たままを返す
+        "DATETIME" | "TIMESTAMP" => {
+            get::<NaiveDateTime>(row, ordinal).map(|v| Value::Timestamp(v.to_string()))
+        }
+        "YEAR" => get_bytes(row, ordinal).and_then(|v| {
+            v.get(0..2)
+                .and_then(|b| b.try_into().ok())
+                .map(|b| Value::Int(u16::from_le_bytes(b) as i64))
+                .ok_or_else(invalid)
+        }),
+        // BIT(M) は上位バイトから詰めたバイト列で返ってくる
+        "BIT" => get_bytes(row, ordinal).and_then(|v| {
+            if v.len() > 8 {
+                return Err(invalid());
             }
+            Ok(unsigned_to_value(
+                v.iter().fold(0u64, |acc, b| acc << 8 | *b as u64),
+            ))
+        }),
+        "JSON" => get::<serde_json::Value>(row, ordinal).map(Value::Json),
+        "GEOMETRY" => get_bytes(row, ordinal)
+            .and_then(|v| format_geometry(v).map(Value::Text).ok_or_else(invalid)),
+        // 未対応の型は、文字列として読めればそのまま使い、読めなければ型名を付けてデコードの失敗にする
+        _ => get_bytes(row, ordinal).and_then(|v| {
+            std::str::from_utf8(v)
+                .map(|text| Value::Text(text.to_string()))
+                .map_err(|e| format!("Unsupported type: {} ({})", type_name, e))
+        }),
+    };
+
+    backend::decoded(value, || {
+        get_bytes(row, ordinal)
+            .map(|v| v.to_vec())
+            .unwrap_or_default()
+    })
+}
+
+fn unsigned_to_value(v: u64) -> Value {
+    // i64 に収まらない BIGINT UNSIGNED は、桁を落とさないよう Decimal で返す
+    // どちらもフロントエンドには、number で表せなければ文字列で渡る
+    match i64::try_from(v) {
+        Ok(v) => Value::Int(v),
+        Err(_) => Value::Decimal(v.to_string()),
+    }
+}
+
+struct WkbReader<'a> {
+    bytes: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> WkbReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Some(head)
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        let bytes: [u8; 4] = self.take(4)?.try_into().ok()?;
+        if self.little_endian {
+            Some(u32::from_le_bytes(bytes))
+        } else {
+            Some(u32::from_be_bytes(bytes))
         }
-        result.push(map);
     }
 
-    let mut columns: Vec<crate::model::Column> = vec![];
-    if query_result.len() > 0 {
-        for column in query_result[0].columns() {
-            columns.push(crate::model::Column {
-                ordinal: column.ordinal(),
-                name: column.name().to_string(),
-            });
+    fn read_f64(&mut self) -> Option<f64> {
+        let bytes: [u8; 8] = self.take(8)?.try_into().ok()?;
+        if self.little_endian {
+            Some(f64::from_le_bytes(bytes))
+        } else {
+            Some(f64::from_be_bytes(bytes))
         }
     }
 
-    Ok((columns, result))
+    fn read_point(&mut self) -> Option<String> {
+        let x = self.read_f64()?;
+        let y = self.read_f64()?;
+        Some(format!("{} {}", x, y))
+    }
+
+    fn read_points(&mut self) -> Option<String> {
+        let count = self.read_u32()?;
+        let points = (0..count)
+            .map(|_| self.read_point())
+            .collect::<Option<Vec<String>>>()?;
+        Some(format!("({})", points.join(",")))
+    }
+
+    fn read_rings(&mut self) -> Option<String> {
+        let count = self.read_u32()?;
+        let rings = (0..count)
+            .map(|_| self.read_points())
+            .collect::<Option<Vec<String>>>()?;
+        Some(format!("({})", rings.join(",")))
+    }
+
+    // 戻り値は (型名, 中身) で、MULTI* の要素では型名を付けずに中身だけを使う
+    fn read_geometry(&mut self) -> Option<(&'static str, String)> {
+        self.little_endian = *self.take(1)?.first()? == 1;
+
+        match self.read_u32()? {
+            1 => Some(("POINT", format!("({})", self.read_point()?))),
+            2 => Some(("LINESTRING", self.read_points()?)),
+            3 => Some(("POLYGON", self.read_rings()?)),
+            4 => Some(("MULTIPOINT", self.read_collection(false)?)),
+            5 => Some(("MULTILINESTRING", self.read_collection(false)?)),
+            6 => Some(("MULTIPOLYGON", self.read_collection(false)?)),
+            7 => Some(("GEOMETRYCOLLECTION", self.read_collection(true)?)),
+            _ => None,
+        }
+    }
+
+    fn read_collection(&mut self, with_type: bool) -> Option<String> {
+        let count = self.read_u32()?;
+        let mut items = vec![];
+        for _ in 0..count {
+            let (type_name, body) = self.read_geometry()?;
+            if with_type {
+                items.push(format!("{}{}", type_name, body));
+            } else {
+                items.push(body);
+            }
+        }
+        Some(format!("({})", items.join(",")))
+    }
 }
 
+// MySQL の GEOMETRY は SRID(4) + WKB で返ってくるので、ST_AsText と同じ WKT にする
+fn format_geometry(bytes: &[u8]) -> Option<String> {
+    let mut reader = WkbReader {
+        bytes: bytes.get(4..)?,
+        little_endian: true,
+    };
+
+    let (type_name, body) = reader.read_geometry()?;
+    Some(format!("{}{}", type_name, body))
+}
+
+// docker-compose.yml の mysql サービスに接続して、型ごとに値が往復するかを確認する
+// `docker compose up -d mysql` の後に `cargo test -- --ignored` で実行する
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn create_pool() -> Pool<MySql> {
+        dotenv::from_filename("../.env").ok();
+
+        let env = |key: &str, default: &str| std::env::var(key).unwrap_or(default.to_string());
+        let url = format!(
+            "{}:{}",
+            env("MYSQL_HOSTNAME", "localhost"),
+            env("MYSQL_PORT", "3306")
+        );
+
+        create_mysql_connection_pool(
+            url,
+            env("MYSQL_DATABASE", "mysql"),
+            env("MYSQL_USER", "mysql"),
+            env("MYSQL_PASSWORD", "mysql"),
+            ConnectionId::default(),
+        )
+        .await
+        .unwrap()
+    }
+
+    // 接続は 1 本だけなので、一時テーブルは同じセッションから読める
+    async fn round_trip(column_type: &str, literal: &str) -> (String, Value) {
+        let pool = create_pool().await;
+
+        sqlx::query(&format!("CREATE TEMPORARY TABLE t (v {})", column_type))
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(&format!("INSERT INTO t (v) VALUES ({})", literal))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        let mut result = query_to_mysql(&mut conn, String::from("SELECT v FROM t"), vec![], None)
+            .await
+            .unwrap();
+        drop(conn);
+        pool.close().await;
+
+        let type_name = result.columns[0].type_name.clone();
+        (type_name, result.rows.remove(0).remove(0))
+    }
+
+    async fn assert_round_trip(cases: Vec<(&str, &str, &str, Value)>) {
+        for (column_type, literal, expected_type_name, expected) in cases {
+            let (type_name, value) = round_trip(column_type, literal).await;
+            assert_eq!(type_name, expected_type_name, "{}", column_type);
+            assert_eq!(value, expected, "{} {}", column_type, literal);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires the mysql service in docker-compose.yml"]
+    async fn integer_types() {
+        assert_round_trip(vec![
+            ("BOOLEAN", "TRUE", "BOOLEAN", Value::Bool(true)),
+            ("TINYINT", "-128", "TINYINT", Value::Int(-128)),
+            ("SMALLINT", "-32768", "SMALLINT", Value::Int(-32768)),
+            ("MEDIUMINT", "-8388608", "MEDIUMINT", Value::Int(-8388608)),
+            ("INT", "-2147483648", "INT", Value::Int(-2147483648)),
+            (
+                "BIGINT",
+                "-9223372036854775808",
+                "BIGINT",
+                Value::Int(i64::MIN),
+            ),
+            (
+                "TINYINT UNSIGNED",
+                "255",
+                "TINYINT UNSIGNED",
+                Value::Int(255),
+            ),
+            (
+                "SMALLINT UNSIGNED",
+                "65535",
+                "SMALLINT UNSIGNED",
+                Value::Int(65535),
+            ),
+            (
+                "MEDIUMINT UNSIGNED",
+                "16777215",
+                "MEDIUMINT UNSIGNED",
+                Value::Int(16777215),
+            ),
+            (
+                "INT UNSIGNED",
+                "4294967295",
+                "INT UNSIGNED",
+                Value::Int(4294967295),
+            ),
+            (
+                "BIGINT UNSIGNED",
+                "18446744073709551615",
+                "BIGINT UNSIGNED",
+                Value::Decimal(String::from("18446744073709551615")),
+            ),
+            ("YEAR", "2024", "YEAR", Value::Int(2024)),
+            ("BIT(8)", "b'00000101'", "BIT", Value::Int(5)),
+            (
+                "BIT(64)",
+                "x'FFFFFFFFFFFFFFFF'",
+                "BIT",
+                Value::Decimal(String::from("18446744073709551615")),
+            ),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires the mysql service in docker-compose.yml"]
+    async fn numeric_types() {
+        assert_round_trip(vec![
+            ("FLOAT", "0.1", "FLOAT", Value::Float(0.1)),
+            ("DOUBLE", "0.1", "DOUBLE", Value::Float(0.1)),
+            (
+                "DECIMAL(10, 2)",
+                "-123.45",
+                "DECIMAL",
+                Value::Decimal(String::from("-123.45")),
+            ),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires the mysql service in docker-compose.yml"]
+    async fn string_types() {
+        let text = |v: &str| Value::Text(String::from(v));
+
+        assert_round_trip(vec![
+            ("CHAR(3)", "'abc'", "CHAR", text("abc")),
+            ("VARCHAR(10)", "'あいう'", "VARCHAR", text("あいう")),
+            ("TINYTEXT", "'abc'", "TINYTEXT", text("abc")),
+            ("TEXT", "'abc'", "TEXT", text("abc")),
+            ("MEDIUMTEXT", "'abc'", "MEDIUMTEXT", text("abc")),
+            ("LONGTEXT", "'abc'", "LONGTEXT", text("abc")),
+            ("ENUM('a', 'b')", "'b'", "ENUM", text("b")),
+            ("SET('a', 'b')", "'a,b'", "CHAR", text("a,b")),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires the mysql service in docker-compose.yml"]
+    async fn binary_types() {
+        let bytes = || Value::Bytes(vec![0x00, 0xff, 0x10]);
+
+        assert_round_trip(vec![
+            ("BINARY(3)", "x'00FF10'", "BINARY", bytes()),
+            ("VARBINARY(3)", "x'00FF10'", "VARBINARY", bytes()),
+            ("TINYBLOB", "x'00FF10'", "TINYBLOB", bytes()),
+            ("BLOB", "x'00FF10'", "BLOB", bytes()),
+            ("MEDIUMBLOB", "x'00FF10'", "MEDIUMBLOB", bytes()),
+            ("LONGBLOB", "x'00FF10'", "LONGBLOB", bytes()),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires the mysql service in docker-compose.yml"]
+    async fn date_time_types() {
+        assert_round_trip(vec![
+            (
+                "DATE",
+                "'2024-01-02'",
+                "DATE",
+                Value::Date(String::from("2024-01-02")),
+            ),
+            (
+                "TIME",
+                "'-838:59:59'",
+                "TIME",
+                Value::Time(String::from("-838:59:59")),
+            ),
+            (
+                "TIME(3)",
+                "'01:02:03.456'",
+                "TIME",
+                Value::Time(String::from("01:02:03.456")),
+            ),
+            (
+                "DATETIME",
+                "'2024-01-02 03:04:05'",
+                "DATETIME",
+                Value::Timestamp(String::from("2024-01-02 03:04:05")),
+            ),
+            (
+                "TIMESTAMP",
+                "'2024-01-02 03:04:05'",
+                "TIMESTAMP",
+                Value::Timestamp(String::from("2024-01-02 03:04:05")),
+            ),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires the mysql service in docker-compose.yml"]
+    async fn json_type() {
+        assert_round_trip(vec![(
+            "JSON",
+            r#"'{"a": [1, "b", null]}'"#,
+            "JSON",
+            Value::Json(serde_json::json!({"a": [1, "b", null]})),
+        )])
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires the mysql service in docker-compose.yml"]
+    async fn geometry_types() {
+        let cases = [
+            "POINT(1 2)",
+            "LINESTRING(0 0,1 1.5)",
+            "POLYGON((0 0,1 0,1 1,0 0))",
+            "MULTIPOINT((1 2),(3 4))",
+            "MULTILINESTRING((0 0,1 1),(2 2,3 3))",
+            "MULTIPOLYGON(((0 0,1 0,1 1,0 0)))",
+            "GEOMETRYCOLLECTION(POINT(1 2),LINESTRING(0 0,1 1))",
+        ];
+
+        for wkt in cases {
+            let (type_name, value) =
+                round_trip("GEOMETRY", &format!("ST_GeomFromText('{}')", wkt)).await;
+            assert_eq!(type_name, "GEOMETRY");
+            assert_eq!(value, Value::Text(String::from(wkt)));
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires the mysql service in docker-compose.yml"]
+    async fn null_value() {
+        let (_, value) = round_trip("INT", "NULL").await;
+        assert_eq!(value, Value::Null);
+    }
+
+    #[test]
+    fn invalid_decimal_parameters_are_bind_errors() {
+        let result = bind_value(
+            sqlx::query("SELECT ?"),
+            Value::Decimal(String::from("1.2.3")),
+        );
+        assert!(matches!(result, Err(Error::Encode(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires the mysql service in docker-compose.yml"]
+    async fn decimal_parameters_keep_their_digits() {
+        let pool = create_pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        let decimal = "12345678901.12345678901234567890";
+        let result = query_to_mysql(
+            &mut conn,
+            String::from("SELECT CAST(? AS DECIMAL(31, 20))"),
+            vec![Value::Decimal(String::from(decimal))],
+            None,
+        )
+        .await
+        .unwrap();
+        drop(conn);
+        pool.close().await;
+
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Decimal(String::from(decimal))]]
+        );
+    }
+}
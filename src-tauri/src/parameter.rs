@@ -0,0 +1,782 @@
+use sqlparser::dialect::Dialect;
+use sqlparser::parser::ParserError;
+
+use crate::error::CommandError;
+use crate::model::{Parameter, ParameterInfo, SelectStatements, Span, Value};
+use crate::sql_parser;
+
+// SQL 中に現れたパラメーター。start/end は元の SQL のバイト位置
+pub struct Placeholder {
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+// SQL 中のパラメーターを、現れた順にすべて返す
+pub fn find_placeholders(
+    sql: &str,
+    parameter_pattern: &str,
+) -> Result<Vec<Placeholder>, CommandError> {
+    let prefix = match parameter_pattern {
+        "mybatis" => '#',
+        "jpa" => ':',
+        "dapper" => '@',
+        "log" => '$',
+        _ => {
+            return Err(CommandError::other(format!(
+                "Unknown parameter pattern: {}",
+                parameter_pattern
+            )))
+        }
+    };
+
+    let chars: Vec<char> = sql.chars().collect();
+    // 文字の位置からバイト位置を引くための表(末尾に SQL の長さを足しておく)
+    let offsets: Vec<usize> = sql
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(sql.len()))
+        .collect();
+
+    let mut placeholders = vec![];
+
+    let mut i = 0;
+    while i < chars.len() {
+        // 文字列リテラル、識別子、コメントの中はパラメーターとして扱わない
+        let skip = sql_parser::skip_literal(&chars, i);
+        if skip > i {
+            i = skip;
+            continue;
+        }
+
+        if chars[i] == prefix {
+            if let Some((name, end)) = read_parameter(&chars, i, prefix) {
+                placeholders.push(Placeholder {
+                    name,
+                    start: offsets[i],
+                    end: offsets[end],
+                });
+                i = end;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    Ok(placeholders)
+}
+
+// パラメーターを含む SQL からサブクエリを抽出する
+// パラメーターを識別子に置き換えて解析し、各ノードの SQL と位置は元の SQL のものに戻す
+// recover の場合、解析できない文があれば ; で区切った文ごとに解析し、解析できた文から抽出する
+pub fn find_select_statement(
+    dialect: &dyn Dialect,
+    sql: &str,
+    parameter_pattern: &str,
+    recover: bool,
+) -> Result<SelectStatements, CommandError> {
+    let placeholders = find_placeholders(sql, parameter_pattern)?;
+    let replaced = replace_with_identifiers(sql, &placeholders);
+
+    // 相関サブクエリの外側への参照は、指定された形式のパラメーターにする
+    let placeholder = placeholder_format(parameter_pattern)?;
+    let (mut nodes, errors) =
+        match sql_parser::find_select_statement(dialect, &replaced, &placeholder) {
+            Ok(nodes) => (nodes, vec![]),
+            Err(_) if recover => {
+                let (nodes, errors) =
+                    sql_parser::find_select_statement_each(dialect, &replaced, &placeholder);
+                let errors = errors
+                    .into_iter()
+                    .map(|((start, end), e)| {
+                        parse_error(sql, &placeholders, &replaced[start..end], start, e)
+                    })
+                    .collect();
+                (nodes, errors)
+            }
+            Err(e) => return Err(parse_error(sql, &placeholders, &replaced, 0, e)),
+        };
+
+    let to_original = original_offset(&placeholders);
+    for node in nodes.iter_mut() {
+        // UPDATE などのプレビューは、位置があっても SQL は書き換えたもの
+        let in_source = node
+            .span
+            .as_ref()
+            .is_some_and(|span| replaced.get(span.start..span.end) == Some(node.sql.as_str()));
+        node.span = node
+            .span
+            .as_ref()
+            .map(|span| sql_parser::to_span(sql, to_original(span.start), to_original(span.end)));
+        node.sql = match &node.span {
+            Some(span) if in_source => sql[span.start..span.end].to_string(),
+            _ => restore_placeholders(&node.sql, sql, &placeholders),
+        };
+        node.runnable_sql = restore_placeholders(&node.runnable_sql, sql, &placeholders);
+    }
+
+    Ok(SelectStatements { nodes, errors })
+}
+
+// スクリプトを文に分ける。戻り値は (元の SQL の文, SQL 中の位置)
+pub fn split_statements(
+    dialect: &dyn Dialect,
+    sql: &str,
+    parameter_pattern: &str,
+) -> Result<Vec<(String, Option<Span>)>, CommandError> {
+    let placeholders = find_placeholders(sql, parameter_pattern)?;
+    let replaced = replace_with_identifiers(sql, &placeholders);
+
+    let statements = match sql_parser::split_statements(dialect, &replaced) {
+        Ok(statements) => statements,
+        Err(e) => return Err(parse_error(sql, &placeholders, &replaced, 0, e)),
+    };
+
+    let to_original = original_offset(&placeholders);
+    let statements = statements
+        .into_iter()
+        .map(|(statement, range)| match range {
+            Some((start, end)) => {
+                let span = sql_parser::to_span(sql, to_original(start), to_original(end));
+                (sql[span.start..span.end].to_string(), Some(span))
+            }
+            None => (restore_placeholders(&statement, sql, &placeholders), None),
+        })
+        .collect();
+
+    Ok(statements)
+}
+
+// 問い合わせ以外の文があれば、その種類(INSERT, CREATE TABLE など)を返す
+// パラメーターは識別子に置き換えて解析するので、値が無くても判断できる
+pub fn find_non_query(
+    dialect: &dyn Dialect,
+    sql: &str,
+    parameter_pattern: &str,
+) -> Result<Option<String>, CommandError> {
+    let placeholders = find_placeholders(sql, parameter_pattern)?;
+    let replaced = replace_with_identifiers(sql, &placeholders);
+
+    sql_parser::find_non_query(dialect, &replaced)
+        .map_err(|e| parse_error(sql, &placeholders, &replaced, 0, e))
+}
+
+// 置き換えた SQL のうち start から始まる部分(parsed)のパースエラーを、元の SQL の位置を持つエラーにする
+// 位置が書かれていない SQL の終わりでのエラーは、SQL の終わりの位置にする
+fn parse_error(
+    sql: &str,
+    placeholders: &[Placeholder],
+    parsed: &str,
+    start: usize,
+    error: ParserError,
+) -> CommandError {
+    let expected_found = sql_parser::expected_found(&error);
+    let at_end = matches!(&expected_found, Some((_, found)) if found == "EOF");
+    let offset = sql_parser::error_offset(parsed, &error)
+        .or_else(|| at_end.then(|| parsed.trim_end().len()));
+
+    let to_original = original_offset(placeholders);
+    let position = offset.map(|offset| {
+        let offset = to_original(start + offset);
+        sql_parser::to_span(sql, offset, offset)
+    });
+
+    // メッセージの位置は置き換えた SQL のものなので外し、識別子は元のパラメーターに戻す
+    let message = error.to_string();
+    let message = match message.rsplit_once(" at Line: ") {
+        Some((message, _)) if position.is_some() => message,
+        _ => &message,
+    };
+    let (expected, found) = match expected_found {
+        Some((expected, found)) => (
+            Some(expected),
+            Some(restore_placeholders(&found, sql, placeholders)),
+        ),
+        None => (None, None),
+    };
+
+    CommandError::Parse {
+        message: restore_placeholders(message, sql, placeholders),
+        position,
+        expected,
+        found,
+    }
+}
+
+// 置き換えた範囲。(置き換えた SQL での始まり, 終わり, 元の SQL での始まり, 終わり)
+type Replacement = (usize, usize, usize, usize);
+
+// 置き換えた SQL の位置から、元の SQL の位置を求める。replacements は現れた順に並ぶ
+// 置き換えた範囲の中を指す場合は、元の範囲の先頭の位置になる
+fn map_offset(replacements: &[Replacement], offset: usize) -> usize {
+    match replacements
+        .iter()
+        .rev()
+        .find(|(start, ..)| *start <= offset)
+    {
+        Some((_, end, original_start, _)) if offset < *end => *original_start,
+        Some((_, end, _, original_end)) => original_end + (offset - end),
+        None => offset,
+    }
+}
+
+// replace_with_identifiers で置き換えた SQL の位置から、元の SQL の位置を求める
+fn original_offset(placeholders: &[Placeholder]) -> impl Fn(usize) -> usize {
+    let mut replacements = vec![];
+    let mut replaced_end = 0;
+    let mut last = 0;
+    for (i, placeholder) in placeholders.iter().enumerate() {
+        let identifier = format!("{}{}", sql_parser::PARAMETER_PREFIX, i);
+        let replaced_start = replaced_end + placeholder.start - last;
+        replaced_end = replaced_start + identifier.len();
+        last = placeholder.end;
+        replacements.push((
+            replaced_start,
+            replaced_end,
+            placeholder.start,
+            placeholder.end,
+        ));
+    }
+
+    move |offset: usize| map_offset(&replacements, offset)
+}
+
+// パラメーターを __sql_divider_parameter_0 のような識別子に置き換える
+fn replace_with_identifiers(sql: &str, placeholders: &[Placeholder]) -> String {
+    let mut replaced = String::new();
+    let mut last = 0;
+    for (i, placeholder) in placeholders.iter().enumerate() {
+        replaced.push_str(&sql[last..placeholder.start]);
+        replaced.push_str(&format!("{}{}", sql_parser::PARAMETER_PREFIX, i));
+        last = placeholder.end;
+    }
+    replaced.push_str(&sql[last..]);
+    replaced
+}
+
+// replace_with_identifiers で置き換えた識別子を、元の SQL のパラメーターに戻す
+fn restore_placeholders(text: &str, sql: &str, placeholders: &[Placeholder]) -> String {
+    let mut restored = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find(sql_parser::PARAMETER_PREFIX) {
+        let after = &rest[i + sql_parser::PARAMETER_PREFIX.len()..];
+        let digits = after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let placeholder = after[..digits]
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| placeholders.get(index));
+
+        restored.push_str(&rest[..i]);
+        match placeholder {
+            Some(placeholder) => restored.push_str(&sql[placeholder.start..placeholder.end]),
+            None => restored.push_str(&rest[i..i + sql_parser::PARAMETER_PREFIX.len() + digits]),
+        }
+        rest = &after[digits..];
+    }
+    restored.push_str(rest);
+    restored
+}
+
+// パラメーター名から、SQL 中に書くパラメーター(#{name}, :name など)を作る関数を返す
+pub fn placeholder_format(
+    parameter_pattern: &str,
+) -> Result<impl Fn(&str) -> String, CommandError> {
+    let (prefix, suffix) = match parameter_pattern {
+        "mybatis" => ("#{", "}"),
+        "jpa" => (":", ""),
+        "dapper" => ("@", ""),
+        "log" => ("$", ""),
+        _ => {
+            return Err(CommandError::other(format!(
+                "Unknown parameter pattern: {}",
+                parameter_pattern
+            )))
+        }
+    };
+
+    Ok(move |name: &str| format!("{}{}{}", prefix, name, suffix))
+}
+
+// SQL 中のパラメーターを名前ごとにまとめ、現れた位置と推測した型を返す
+// SQL が解析できない場合も、型を None にしてパラメーターの一覧は返す
+pub fn discover_parameters(
+    dialect: &dyn Dialect,
+    sql: &str,
+    parameter_pattern: &str,
+) -> Result<Vec<ParameterInfo>, CommandError> {
+    let placeholders = find_placeholders(sql, parameter_pattern)?;
+
+    // 型の推測は AST で行うため、パラメーターを識別子に置き換えてから解析する
+    let replaced = replace_with_identifiers(sql, &placeholders);
+    let types = sql_parser::find_parameter_types(dialect, &replaced).unwrap_or_default();
+
+    let mut parameters: Vec<ParameterInfo> = vec![];
+    for (i, placeholder) in placeholders.iter().enumerate() {
+        let span = sql_parser::to_span(sql, placeholder.start, placeholder.end);
+        let guessed_type = types.get(&i).cloned();
+
+        match parameters.iter_mut().find(|p| p.name == placeholder.name) {
+            Some(parameter) => {
+                parameter.spans.push(span);
+                if parameter.guessed_type.is_none() {
+                    parameter.guessed_type = guessed_type;
+                }
+            }
+            None => parameters.push(ParameterInfo {
+                name: placeholder.name.clone(),
+                spans: vec![span],
+                guessed_type,
+            }),
+        }
+    }
+
+    Ok(parameters)
+}
+
+// bind_parameters の結果
+pub struct BoundQuery {
+    // パラメーターをプレースホルダーに置き換えた SQL
+    pub sql: String,
+    pub values: Vec<Value>,
+    replacements: Vec<Replacement>,
+}
+
+impl BoundQuery {
+    // sql のバイト位置から、置き換える前の SQL のバイト位置を求める
+    pub fn original_offset(&self, offset: usize) -> usize {
+        map_offset(&self.replacements, offset)
+    }
+}
+
+// SQL 中のパラメーターをデータベースのプレースホルダーに置き換え、バインドする値を返す
+// placeholder には 1 始まりの番号を渡す(PostgreSQL は $1, MySQL/SQLite は ?)
+pub fn bind_parameters(
+    sql: &str,
+    parameter_pattern: &str,
+    parameters: &[Parameter],
+    placeholder: impl Fn(usize) -> String,
+) -> Result<BoundQuery, CommandError> {
+    let mut replaced = String::new();
+    let mut values: Vec<Value> = vec![];
+    let mut replacements = vec![];
+
+    let mut last = 0;
+    for found in find_placeholders(sql, parameter_pattern)? {
+        // 値が設定されていないパラメーターはそのまま残す(MySQL のユーザー変数など)
+        if let Some(parameter) = parameters.iter().find(|p| p.name == found.name) {
+            values.push(parse_literal(&parameter.value));
+            replaced.push_str(&sql[last..found.start]);
+            let start = replaced.len();
+            replaced.push_str(&placeholder(values.len()));
+            replacements.push((start, replaced.len(), found.start, found.end));
+            last = found.end;
+        }
+    }
+    replaced.push_str(&sql[last..]);
+
+    Ok(BoundQuery {
+        sql: replaced,
+        values,
+        replacements,
+    })
+}
+
+// 戻り値は (パラメーター名, パラメーターの直後の位置)
+fn read_parameter(chars: &[char], start: usize, prefix: char) -> Option<(String, usize)> {
+    let is_name_char = |c: &char| c.is_alphanumeric() || *c == '_';
+
+    if prefix == '#' {
+        // #{name} と #{name,jdbcType=VARCHAR} の両方を受け付ける
+        if chars.get(start + 1) != Some(&'{') {
+            return None;
+        }
+        let close = (start + 2..chars.len()).find(|i| chars[*i] == '}')?;
+        let body: String = chars[start + 2..close].iter().collect();
+        let name = body.split(',').next()?.trim().to_string();
+        if name.is_empty() {
+            return None;
+        }
+        return Some((name, close + 1));
+    }
+
+    // PostgreSQL のキャスト(::)や MySQL のシステム変数(@@)はパラメーターではない
+    if start > 0 && chars[start - 1] == prefix {
+        return None;
+    }
+    if chars.get(start + 1) == Some(&prefix) {
+        return None;
+    }
+
+    let end = (start + 1..chars.len())
+        .find(|i| !is_name_char(&chars[*i]))
+        .unwrap_or(chars.len());
+    if end == start + 1 {
+        return None;
+    }
+
+    Some((chars[start + 1..end].iter().collect(), end))
+}
+
+// パラメーターの値は SQL のリテラルとして解釈する
+// 'abc' は文字列、123 は整数、NULL は NULL、それ以外は入力をそのまま文字列として扱う
+pub fn parse_literal(value: &str) -> Value {
+    let value = value.trim();
+
+    if value.eq_ignore_ascii_case("null") {
+        return Value::Null;
+    }
+    if value.eq_ignore_ascii_case("true") {
+        return Value::Bool(true);
+    }
+    if value.eq_ignore_ascii_case("false") {
+        return Value::Bool(false);
+    }
+    if let Ok(v) = value.parse::<i64>() {
+        return Value::Int(v);
+    }
+    // f64 としては inf や NaN も読めてしまうので、数字と記号だけのものに限る
+    if value.chars().all(|c| "0123456789+-.eE".contains(c)) && value.parse::<f64>().is_ok() {
+        // 精度を落とさないよう文字列のまま持つ
+        return Value::Decimal(value.to_string());
+    }
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        return Value::Text(value[1..value.len() - 1].replace("''", "'"));
+    }
+
+    Value::Text(value.to_string())
+}
+
+// バインドする値を文字列にする。NULL は None
+pub fn to_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Bool(v) => Some(v.to_string()),
+        Value::Int(v) => Some(v.to_string()),
+        Value::Float(v) => Some(v.to_string()),
+        Value::Decimal(v)
+        | Value::Text(v)
+        | Value::Date(v)
+        | Value::Time(v)
+        | Value::Timestamp(v) => Some(v.clone()),
+        Value::Json(v) => Some(v.to_string()),
+        Value::Bytes(v) | Value::DecodeError { bytes: v, .. } => {
+            Some(String::from_utf8_lossy(v).to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlparser::dialect::PostgreSqlDialect;
+
+    use super::*;
+
+    // (start, end, line, column)
+    type Position = Option<(usize, usize, usize, usize)>;
+
+    fn span(span: &Option<Span>) -> Position {
+        span.as_ref().map(|s| (s.start, s.end, s.line, s.column))
+    }
+
+    fn parameter(name: &str, value: &str) -> Parameter {
+        Parameter {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn bind(sql: &str, parameter_pattern: &str, parameters: &[Parameter]) -> (String, Vec<Value>) {
+        let bound =
+            bind_parameters(sql, parameter_pattern, parameters, |n| format!("${}", n)).unwrap();
+        (bound.sql, bound.values)
+    }
+
+    #[test]
+    fn bind_parameters_skips_literals_and_comments() {
+        let sql = "select ':a', \":a\", `:a`, $$ :a $$, $t$ :a $t$ -- :a\n\
+            /* :a */ from t where x = 'it''s :a' and y = :a";
+        let (replaced, values) = bind(sql, "jpa", &[parameter("a", "1")]);
+
+        assert_eq!(replaced, sql.replace("y = :a", "y = $1"));
+        assert_eq!(values, vec![Value::Int(1)]);
+    }
+
+    #[test]
+    fn bind_parameters_skips_casts_and_system_variables() {
+        let (replaced, _) = bind(
+            "select :a::int, x::text from t",
+            "jpa",
+            &[
+                parameter("a", "1"),
+                parameter("int", "2"),
+                parameter("text", "3"),
+            ],
+        );
+        assert_eq!(replaced, "select $1::int, x::text from t");
+
+        let (replaced, _) = bind(
+            "select @@version, @a, @@session.sql_mode",
+            "dapper",
+            &[
+                parameter("a", "1"),
+                parameter("version", "2"),
+                parameter("session", "3"),
+            ],
+        );
+        assert_eq!(replaced, "select @@version, $1, @@session.sql_mode");
+    }
+
+    #[test]
+    fn bind_parameters_reads_mybatis_options() {
+        let (replaced, values) = bind(
+            "select * from t where id = #{id,jdbcType=INTEGER} and x = #{ name } and y = #{}",
+            "mybatis",
+            &[parameter("id", "10"), parameter("name", "'x'")],
+        );
+        assert_eq!(
+            replaced,
+            "select * from t where id = $1 and x = $2 and y = #{}"
+        );
+        assert_eq!(values, vec![Value::Int(10), Value::Text("x".to_string())]);
+    }
+
+    #[test]
+    fn bind_parameters_repeats_placeholders() {
+        // 同じパラメーターが何度現れても、現れるたびに値をバインドする
+        let (replaced, values) = bind(
+            "select * from t where a = :id or b = :id and c = :name",
+            "jpa",
+            &[parameter("id", "10"), parameter("name", "NULL")],
+        );
+        assert_eq!(
+            replaced,
+            "select * from t where a = $1 or b = $2 and c = $3"
+        );
+        assert_eq!(values, vec![Value::Int(10), Value::Int(10), Value::Null]);
+    }
+
+    #[test]
+    fn bind_parameters_keeps_unset_parameters() {
+        let (replaced, values) = bind(
+            "set @a = 1; select @a, @b",
+            "dapper",
+            &[parameter("b", "2")],
+        );
+        assert_eq!(replaced, "set @a = 1; select @a, $1");
+        assert_eq!(values, vec![Value::Int(2)]);
+
+        let (replaced, _) = bind(
+            "select $1, $name from t where x = $a$ $b $a$",
+            "log",
+            &[
+                parameter("1", "1"),
+                parameter("name", "2"),
+                parameter("b", "3"),
+            ],
+        );
+        assert_eq!(replaced, "select $1, $2 from t where x = $a$ $b $a$");
+
+        assert!(bind_parameters("select 1", "unknown", &[], |_| "?".to_string()).is_err());
+    }
+
+    #[test]
+    fn parse_literal_values() {
+        assert_eq!(parse_literal(" NULL "), Value::Null);
+        assert_eq!(parse_literal("true"), Value::Bool(true));
+        assert_eq!(parse_literal("FALSE"), Value::Bool(false));
+        assert_eq!(parse_literal("-12"), Value::Int(-12));
+        assert_eq!(parse_literal("1.50"), Value::Decimal("1.50".to_string()));
+        assert_eq!(parse_literal("1e3"), Value::Decimal("1e3".to_string()));
+        assert_eq!(parse_literal("inf"), Value::Text("inf".to_string()));
+        assert_eq!(parse_literal("'it''s'"), Value::Text("it's".to_string()));
+        assert_eq!(parse_literal("'1'"), Value::Text("1".to_string()));
+        assert_eq!(parse_literal("abc"), Value::Text("abc".to_string()));
+    }
+
+    #[test]
+    fn discover_parameters_guesses_types_in_dml() {
+        let parameters = discover_parameters(
+            &PostgreSqlDialect {},
+            "UPDATE t SET price = :p WHERE id = :id",
+            "jpa",
+        )
+        .unwrap();
+
+        let types: Vec<(&str, Option<&str>)> = parameters
+            .iter()
+            .map(|p| (p.name.as_str(), p.guessed_type.as_deref()))
+            .collect();
+        assert_eq!(types, vec![("p", Some("number")), ("id", Some("number"))]);
+    }
+
+    #[test]
+    fn find_select_statement_spans_in_original_sql() {
+        let sql = "select * /* #{no} */\nfrom t\nwhere id = #{id,jdbcType=INTEGER}\n  \
+            and x in (select y from u where z = #{id} and w = '#{no}')";
+        let statements =
+            find_select_statement(&PostgreSqlDialect {}, sql, "mybatis", false).unwrap();
+
+        // 位置と SQL は、パラメーターを置き換える前の SQL のもの
+        let nodes: Vec<(&str, &str, Position)> = statements
+            .nodes
+            .iter()
+            .map(|n| (n.sql.as_str(), n.runnable_sql.as_str(), span(&n.span)))
+            .collect();
+        let inner = "select y from u where z = #{id} and w = '#{no}'";
+        let start = sql.find(inner).unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                (sql, sql, Some((0, sql.len(), 1, 1))),
+                (inner, inner, Some((start, start + inner.len(), 4, 13))),
+            ]
+        );
+        assert!(statements.errors.is_empty());
+    }
+
+    #[test]
+    fn find_select_statement_recovers_with_error_positions() {
+        let sql = "select #{a} from t;\nselect * frm #{b};\nselect (select 1) x";
+        let dialect = PostgreSqlDialect {};
+
+        let Err(CommandError::Parse {
+            position,
+            expected,
+            found,
+            ..
+        }) = find_select_statement(&dialect, sql, "mybatis", false)
+        else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(span(&position), Some((29, 29, 2, 10)));
+        assert_eq!(expected.as_deref(), Some("end of statement"));
+        assert_eq!(found.as_deref(), Some("frm"));
+
+        let statements = find_select_statement(&dialect, sql, "mybatis", true).unwrap();
+        let nodes: Vec<(&str, Position)> = statements
+            .nodes
+            .iter()
+            .map(|n| (n.sql.as_str(), span(&n.span)))
+            .collect();
+        assert_eq!(
+            nodes,
+            vec![
+                ("select #{a} from t", Some((0, 18, 1, 1))),
+                ("select (select 1) x", Some((39, 58, 3, 1))),
+                ("select 1", Some((47, 55, 3, 9))),
+            ]
+        );
+        let errors: Vec<Position> = statements
+            .errors
+            .iter()
+            .map(|e| match e {
+                CommandError::Parse { position, .. } => span(position),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(errors, vec![Some((29, 29, 2, 10))]);
+    }
+
+    #[test]
+    fn restore_placeholders_round_trips_identifiers() {
+        let sql = "select :a, 'é :x' from t where b = :long_name and c = :a";
+        let placeholders = find_placeholders(sql, "jpa").unwrap();
+        let replaced = replace_with_identifiers(sql, &placeholders);
+
+        assert_eq!(
+            replaced,
+            format!(
+                "select {0}0, 'é :x' from t where b = {0}1 and c = {0}2",
+                sql_parser::PARAMETER_PREFIX
+            )
+        );
+        assert_eq!(restore_placeholders(&replaced, sql, &placeholders), sql);
+    }
+
+    #[test]
+    fn bound_query_original_offset() {
+        let sql = "select #{a,jdbcType=INTEGER}, x, #{bb} from t where y = #{a}";
+        let bound = bind_parameters(
+            sql,
+            "mybatis",
+            &[parameter("a", "1"), parameter("bb", "2")],
+            |n| format!("${}", n),
+        )
+        .unwrap();
+        assert_eq!(bound.sql, "select $1, x, $2 from t where y = $3");
+
+        // プレースホルダーの外は同じ文字を指し、中は元のパラメーターの先頭を指す
+        for text in ["select", ", x, ", " from t where y = "] {
+            let offset = bound.sql.find(text).unwrap();
+            let original = bound.original_offset(offset);
+            assert_eq!(&sql[original..original + text.len()], text);
+        }
+        assert_eq!(bound.original_offset(8), sql.find("#{a,").unwrap());
+        assert_eq!(bound.original_offset(15), sql.find("#{bb}").unwrap());
+        assert_eq!(bound.original_offset(bound.sql.len()), sql.len());
+    }
+
+    #[test]
+    fn original_offset_round_trips_replaced_sql() {
+        let sql = "select :a, 'é' from t where b = :long_name and c = :a";
+        let placeholders = find_placeholders(sql, "jpa").unwrap();
+        let replaced = replace_with_identifiers(sql, &placeholders);
+        let original = original_offset(&placeholders);
+
+        for text in ["select ", ", 'é' from t where b = ", " and c = "] {
+            let offset = replaced.find(text).unwrap();
+            assert_eq!(&sql[original(offset)..original(offset) + text.len()], text);
+        }
+        let starts: Vec<usize> = replaced
+            .match_indices(sql_parser::PARAMETER_PREFIX)
+            .map(|(offset, _)| original(offset + 1))
+            .collect();
+        assert_eq!(
+            starts,
+            placeholders.iter().map(|p| p.start).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            restore_placeholders(&replaced, sql, &placeholders),
+            sql.to_string()
+        );
+    }
+
+    #[test]
+    fn split_statements_ignores_semicolons_in_literals_and_comments() {
+        let sql = "insert into t values ('a;b', :x); -- c;d\n\
+            /* e; */ update t set \"f;g\" = 1;\n\
+            select $$;$$, :x from t";
+        let statements = split_statements(&PostgreSqlDialect {}, sql, "jpa").unwrap();
+
+        let statements: Vec<(&str, Position)> = statements
+            .iter()
+            .map(|(statement, position)| (statement.as_str(), span(position)))
+            .collect();
+        let update = sql.find("update").unwrap();
+        let select = sql.find("select").unwrap();
+        // 最後の文は ; が無くてもよい。前のコメントは文に含めない
+        assert_eq!(
+            statements,
+            vec![
+                ("insert into t values ('a;b', :x)", Some((0, 32, 1, 1))),
+                (
+                    "update t set \"f;g\" = 1",
+                    Some((update, update + 22, 2, 10))
+                ),
+                ("select $$;$$, :x from t", Some((select, sql.len(), 3, 1))),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_statements_reports_errors_in_later_statements() {
+        let sql = "select 1;\nselect * frm t;";
+        let Err(CommandError::Parse { position, .. }) =
+            split_statements(&PostgreSqlDialect {}, sql, "jpa")
+        else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(span(&position).map(|(_, _, line, _)| line), Some(2));
+    }
+}
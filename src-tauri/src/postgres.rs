@@ -1,190 +1,958 @@
-use std::{collections::HashMap, sync::Arc};
+use async_trait::async_trait;
+use sqlparser::dialect::{Dialect, PostgreSqlDialect};
 
-use sqlx::types::chrono::NaiveDate;
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+use sqlx::postgres::types::{Oid, PgInterval, PgMoney, PgRange, PgTimeTz};
+use sqlx::postgres::{PgArguments, PgTypeInfo, PgTypeKind, PgValueFormat};
+use sqlx::query::Query;
+use sqlx::types::chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use sqlx::types::BigDecimal;
 use sqlx::Column;
+use sqlx::ConnectOptions;
+use sqlx::Connection;
+use sqlx::Decode;
+use sqlx::Describe;
+use sqlx::Either;
 use sqlx::Error;
+use sqlx::Executor;
 use sqlx::Row;
+use sqlx::Type;
 use sqlx::TypeInfo;
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use sqlx::ValueRef;
+use sqlx::{
+    postgres::{PgColumn, PgConnection, PgPoolOptions, PgRow},
+    Pool, Postgres,
+};
+
+use crate::backend::{self, f32_to_f64, ConnectionId, DatabaseBackend, OnRows, RunningQueries};
+use crate::error::CommandError;
+use crate::model::{QueryResult, QuerySummary, StatementResult, Value};
+use crate::parameter;
+use crate::sql_parser;
+
+pub struct PostgresBackend {
+    pool: Pool<Postgres>,
+    running: RunningQueries,
+    // キャンセルするときに指定する、プールの接続のサーバー側の id
+    connection_id: ConnectionId,
+    read_only: bool,
+    // parameter_casts で調べた SQL と、その describe の結果。続けて query で実行するときに使う
+    described: Mutex<Option<(String, Describe<Postgres>)>>,
+}
+
+#[async_trait]
+impl DatabaseBackend for PostgresBackend {
+    async fn connect(
+        url: String,
+        db: String,
+        user: String,
+        password: String,
+        read_only: bool,
+    ) -> Result<Self, CommandError> {
+        let connection_id = ConnectionId::default();
+        let pool =
+            create_postgres_connection_pool(url, db, user, password, connection_id.clone()).await?;
+
+        Ok(PostgresBackend {
+            pool,
+            running: RunningQueries::default(),
+            connection_id,
+            read_only,
+            described: Mutex::default(),
+        })
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    async fn close(&self) -> Result<(), CommandError> {
+        self.pool.close().await;
+
+        Ok(())
+    }
 
-use tokio::sync::Mutex;
+    async fn query(
+        &self,
+        query_id: &str,
+        query: String,
+        parameters: Vec<Value>,
+        max_rows: Option<usize>,
+        on_rows: OnRows<'_>,
+    ) -> Result<QuerySummary, Error> {
+        let described = self
+            .described
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .and_then(|(sql, describe)| (sql == query).then_some(describe));
+
+        let mut conn = self.pool.acquire().await?;
+        let running = self.running.start(query_id, self.connection_id.get())?;
+
+        let result = async {
+            self.begin(&mut conn).await?;
+            let result =
+                stream_postgres(&mut conn, query, parameters, described, max_rows, on_rows).await;
+            let end = self.end(&mut conn).await;
+
+            let summary = result?;
+            end?;
+            Ok(summary)
+        }
+        .await;
+        // 接続を返す前に、送っている途中のキャンセルを待つ
+        running.finish().await;
+
+        result
+    }
+
+    async fn execute_script(
+        &self,
+        query_id: &str,
+        statements: Vec<(String, Vec<Value>)>,
+        max_rows: Option<usize>,
+    ) -> Result<Vec<StatementResult>, Error> {
+        // 前の文で型やテーブルが変わることがあるので、describe は文ごとに実行する直前に行う
+        self.described
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+
+        let mut conn = self.pool.acquire().await?;
+        let running = self.running.start(query_id, self.connection_id.get())?;
+
+        let result = async {
+            self.begin(&mut conn).await?;
+            let mut results = vec![];
+            for (query, parameters) in statements {
+                let start = Instant::now();
+                let result =
+                    query_to_postgres(&mut conn, query.clone(), parameters, max_rows).await;
+                let failed = result.is_err();
+                results.push(backend::to_statement_result(query, result, start));
+                if failed {
+                    break;
+                }
+            }
+            self.end(&mut conn).await?;
+
+            Ok(results)
+        }
+        .await;
+        // 接続を返す前に、送っている途中のキャンセルを待つ
+        running.finish().await;
+
+        result
+    }
+
+    async fn cancel(&self, query_id: &str) -> Result<(), CommandError> {
+        self.running
+            .cancel(query_id, |pid| cancel_backend(&self.pool, pid))
+            .await
+    }
+
+    fn take_cancelled(&self, query_id: &str) -> bool {
+        self.running.take_cancelled(query_id)
+    }
+
+    fn placeholder(&self, index: usize) -> String {
+        format!("${}", index)
+    }
+
+    // uuid や enum などは $1::text::uuid のようにして、文字列からサーバーで変換する
+    async fn parameter_casts(&self, query: &str) -> Result<Vec<Option<String>>, Error> {
+        let mut conn = self.pool.acquire().await?;
+        // 型が分からない場合(構文エラーなど)はキャストを付けず、エラーは実行したときに返す
+        let Ok(describe) = (&mut *conn).describe(query).await else {
+            return Ok(vec![]);
+        };
+        let types = match describe.parameters() {
+            Some(Either::Left(types)) => types.to_vec(),
+            _ => vec![],
+        };
+
+        let mut casts = vec![];
+        for type_info in &types {
+            let cast = if BINDABLE_TYPES.contains(&type_info.name()) {
+                None
+            } else {
+                cast_type_name(&mut conn, type_info)
+                    .await?
+                    .map(|name| format!("::text::{}", name))
+            };
+            casts.push(cast);
+        }
+
+        // キャストを付けない場合は同じ SQL を実行するので、describe の結果をそのまま使う
+        if casts.iter().all(Option::is_none) {
+            *self
+                .described
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some((query.to_string(), describe));
+        }
+        Ok(casts)
+    }
+
+    fn dialect(&self) -> Box<dyn Dialect> {
+        Box::new(PostgreSqlDialect {})
+    }
+}
+
+impl PostgresBackend {
+    // 読み取り専用の接続では、READ ONLY トランザクションの中で実行する
+    async fn begin(&self, conn: &mut PgConnection) -> Result<(), Error> {
+        if self.read_only {
+            conn.execute("START TRANSACTION READ ONLY").await?;
+        }
+        Ok(())
+    }
+
+    // 読み取り専用の接続では、何も残さないようロールバックする
+    async fn end(&self, conn: &mut PgConnection) -> Result<(), Error> {
+        if self.read_only {
+            conn.execute("ROLLBACK").await?;
+        }
+        Ok(())
+    }
+}
 
 pub async fn create_postgres_connection_pool(
     url: String,
     db: String,
     user: String,
     password: String,
-) -> Result<Pool<Postgres>, String> {
+    connection_id: ConnectionId,
+) -> Result<Pool<Postgres>, CommandError> {
     let database_url = format!("postgres://{}:{}@{}/{}", user, password, url, db);
 
+    // 接続のサーバー側の id は、接続を作ったときに 1 度だけ調べる
     let result = PgPoolOptions::new()
         .max_connections(1)
+        .after_connect(move |conn, _| {
+            let connection_id = connection_id.clone();
+            Box::pin(async move {
+                connection_id.set(server_connection_id(conn).await?);
+                Ok(())
+            })
+        })
         .connect(&database_url)
         .await;
 
     let result = match result {
         Ok(r) => r,
-        Err(e) => return Err(e.to_string()),
+        Err(e) => return Err(e.into()),
     };
 
     Ok(result)
 }
 
-pub async fn close_postgres_connection_pool(
-    pool: Arc<Mutex<Option<Pool<Postgres>>>>,
-) -> Result<(), String> {
+// キャンセルするときに指定する、サーバー側のプロセス ID
+async fn server_connection_id(conn: &mut PgConnection) -> Result<u64, Error> {
+    let pid: i32 = sqlx::query_scalar("SELECT pg_backend_pid()")
+        .fetch_one(&mut *conn)
+        .await?;
+
+    Ok(pid as u64)
+}
+
+// 実行中の接続はふさがっているので、プールの外の接続からキャンセルする
+async fn cancel_backend(pool: &Pool<Postgres>, pid: u64) -> Result<(), Error> {
+    let mut conn = pool.connect_options().connect().await?;
+    sqlx::query("SELECT pg_cancel_backend($1)")
+        .bind(pid as i32)
+        .execute(&mut conn)
+        .await?;
+    conn.close().await
+}
+
+// 結果の行は on_rows に少しずつ渡し、手元には残さない
+// described は query を describe した結果(既に調べてある場合)
+pub async fn stream_postgres(
+    conn: &mut PgConnection,
+    query: String,
+    parameters: Vec<Value>,
+    described: Option<Describe<Postgres>>,
+    max_rows: Option<usize>,
+    on_rows: OnRows<'_>,
+) -> Result<QuerySummary, Error> {
+    // パラメーターは、サーバーが推論した型に合わせてバインドする
+    let describe = match described {
+        Some(describe) => Ok(describe),
+        None => (&mut *conn).describe(&query).await,
+    };
+    let parameter_types = match describe.as_ref().map(|d| d.parameters()) {
+        Ok(Some(Either::Left(types))) => types.to_vec(),
+        _ => vec![],
+    };
 
-    let pool = pool.lock().await;
-    pool.clone().unwrap().close().await;
-    drop(pool);
+    // 結果のカラムの型は describe のものを返すので、キャストしても元の型名のまま
+    let sent = match &describe {
+        Ok(d) => cast_to_text(&query, d.columns()),
+        Err(_) => None,
+    };
+    let mut sql_query = sqlx::query(sent.as_deref().unwrap_or(&query));
+    for (i, value) in parameters.iter().enumerate() {
+        sql_query = bind_value(sql_query, parameter_types.get(i), value)?;
+    }
 
-    Ok(())
+    // 結果のカラムが無い文(INSERT や SET など)は、変更された行数を受け取る
+    if matches!(&describe, Ok(d) if d.columns().is_empty()) {
+        let done = sql_query.execute(&mut *conn).await?;
+        return Ok(QuerySummary {
+            columns: vec![],
+            total_rows: 0,
+            rows_affected: done.rows_affected(),
+            truncated: false,
+        });
+    }
+
+    let tables = sql_parser::find_projection_tables(&PostgreSqlDialect {}, &query);
+    let (columns, total_rows, truncated) = backend::stream_rows(
+        sql_query.fetch(&mut *conn),
+        describe,
+        tables,
+        to_value,
+        max_rows,
+        on_rows,
+    )
+    .await?;
+
+    Ok(QuerySummary {
+        columns,
+        total_rows,
+        rows_affected: 0,
+        truncated,
+    })
 }
 
+// スクリプトの実行など、結果をまとめて受け取る場合に使う
 pub async fn query_to_postgres(
-    pool: &Arc<Mutex<Option<Pool<Postgres>>>>,
+    conn: &mut PgConnection,
     query: String,
-) -> Result<(Vec<crate::model::Column>, Vec<HashMap<String, String>>), Error> {
-    let pool = pool.lock().await;
-    let pool = pool.clone().unwrap();
-    let query_result = sqlx::query(&query).fetch_all(&pool).await?;
-    drop(pool);
-
-    let mut result: Vec<HashMap<String, String>> = vec![];
-    for row in &query_result {
-        let mut map: HashMap<String, String> = HashMap::new();
-
-        for column in row.columns() {
-            let type_info = column.type_info();
-            let type_name = type_info.name();
-            match type_name {
-                "FLOAT4" => {
-                    let value: Result<f32, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "FLOAT8" => {
-                    let value: Result<f64, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "NUMERIC" => {
-                    let value: Result<BigDecimal, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "BOOL" => {
-                    let value: Result<bool, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "INT1" => {
-                    let value: Result<i8, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "INT2" => {
-                    let value: Result<i16, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "INT4" => {
-                    let value: Result<i32, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "INT8" => {
-                    let value: Result<i64, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "CHAR" => {
-                    let value: Result<String, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "VARCHAR" => {
-                    let value: Result<String, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "TEXT" => {
-                    let value: Result<String, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                "DATE" => {
-                    let value: Result<NaiveDate, _> = row.try_get(column.ordinal());
-
-                    if value.is_ok() {
-                        map.insert(column.name().to_string(), value.unwrap().to_string());
-                    } else {
-                        map.insert(column.name().to_string(), "NULL".to_string());
-                    }
-                }
-                _ => {
-                    println!("{}", type_name);
-                }
-            }
+    parameters: Vec<Value>,
+    max_rows: Option<usize>,
+) -> Result<QueryResult, Error> {
+    let mut rows = vec![];
+    let summary = stream_postgres(conn, query, parameters, None, max_rows, &mut |_, batch| {
+        rows.extend(batch)
+    })
+    .await?;
+
+    Ok(QueryResult {
+        columns: summary.columns,
+        rows,
+        rows_affected: summary.rows_affected,
+        truncated: summary.truncated,
+    })
+}
+
+// bind_value で値をその型に変換してバインドする型。それ以外は parameter_casts でキャストを付ける
+const BINDABLE_TYPES: [&str; 18] = [
+    "BOOL",
+    "INT2",
+    "INT4",
+    "INT8",
+    "FLOAT4",
+    "FLOAT8",
+    "NUMERIC",
+    "CHAR",
+    "VARCHAR",
+    "TEXT",
+    "NAME",
+    "UNKNOWN",
+    "DATE",
+    "TIME",
+    "TIMESTAMP",
+    "TIMESTAMPTZ",
+    "JSON",
+    "JSONB",
+];
+
+// キャストに書く型の名前。format_type は配列や、search_path に無いスキーマの型も書ける形にする
+async fn cast_type_name(
+    conn: &mut PgConnection,
+    type_info: &PgTypeInfo,
+) -> Result<Option<String>, Error> {
+    let Some(oid) = type_info.oid() else {
+        return Ok(None);
+    };
+    sqlx::query_scalar("SELECT format_type($1, NULL)")
+        .bind(oid)
+        .fetch_one(&mut *conn)
+        .await
+}
+
+fn bind_value<'q>(
+    query: Query<'q, Postgres, PgArguments>,
+    type_info: Option<&PgTypeInfo>,
+    value: &Value,
+) -> Result<Query<'q, Postgres, PgArguments>, Error> {
+    let text = parameter::to_text(value);
+
+    // 型が分からない場合は文字列として渡し、エラーはサーバーに任せる
+    let type_name = type_info.map(|t| t.name()).unwrap_or("TEXT");
+
+    let query = match type_name {
+        "BOOL" => query.bind(parse::<bool>(text)?),
+        "INT2" => query.bind(parse::<i16>(text)?),
+        "INT4" => query.bind(parse::<i32>(text)?),
+        "INT8" => query.bind(parse::<i64>(text)?),
+        "FLOAT4" => query.bind(parse::<f32>(text)?),
+        "FLOAT8" => query.bind(parse::<f64>(text)?),
+        "NUMERIC" => query.bind(parse::<BigDecimal>(text)?),
+        "CHAR" | "VARCHAR" | "TEXT" | "NAME" | "UNKNOWN" => query.bind(text),
+        "DATE" => query.bind(parse::<NaiveDate>(text)?),
+        "TIME" => query.bind(parse::<NaiveTime>(text)?),
+        // '2024-01-02 03:04:05' の形式でも受け付ける
+        "TIMESTAMP" => query.bind(parse::<NaiveDateTime>(
+            text.map(|t| t.trim().replacen(' ', "T", 1)),
+        )?),
+        "TIMESTAMPTZ" => query.bind(parse::<DateTime<FixedOffset>>(text)?),
+        "JSON" | "JSONB" => query.bind(parse::<serde_json::Value>(text)?),
+        // parameter_casts でキャストを付けられなかった場合も、文字列として渡して変換はサーバーに任せる
+        _ => query.bind(text),
+    };
+
+    Ok(query)
+}
+
+fn parse<T>(text: Option<String>) -> Result<Option<T>, Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    text.map(|t| {
+        t.trim()
+            .parse::<T>()
+            .map_err(|e| Error::Encode(format!("{}: {}", t, e).into()))
+    })
+    .transpose()
+}
+
+// NULL は to_value で先に除いているので、ここでの失敗はデコードの失敗
+fn get<'r, T>(row: &'r PgRow, ordinal: usize) -> Result<T, String>
+where
+    T: Decode<'r, Postgres> + Type<Postgres>,
+{
+    row.try_get::<T, _>(ordinal).map_err(|e| e.to_string())
+}
+
+fn get_array<'r, T>(
+    row: &'r PgRow,
+    ordinal: usize,
+    to_json: impl Fn(T) -> serde_json::Value,
+) -> Result<Value, String>
+where
+    Vec<Option<T>>: Decode<'r, Postgres> + Type<Postgres>,
+{
+    let values = get::<Vec<Option<T>>>(row, ordinal)?;
+
+    Ok(Value::Json(serde_json::Value::Array(
+        values
+            .into_iter()
+            .map(|v| v.map(&to_json).unwrap_or(serde_json::Value::Null))
+            .collect(),
+    )))
+}
+
+// to_value でバイナリ形式から読める型。型を追加する場合は、to_value と合わせる
+const DECODABLE_TYPES: &[&str] = &[
+    "BOOL",
+    "INT1",
+    "\"CHAR\"",
+    "INT2",
+    "INT4",
+    "INT8",
+    "OID",
+    "FLOAT4",
+    "FLOAT8",
+    "NUMERIC",
+    "MONEY",
+    "CHAR",
+    "VARCHAR",
+    "TEXT",
+    "NAME",
+    "UNKNOWN",
+    "BYTEA",
+    "DATE",
+    "TIME",
+    "TIMETZ",
+    "TIMESTAMP",
+    "TIMESTAMPTZ",
+    "INTERVAL",
+    "JSON",
+    "JSONB",
+    "INT4RANGE",
+    "INT8RANGE",
+    "NUMRANGE",
+    "DATERANGE",
+    "TSRANGE",
+    "TSTZRANGE",
+    "BOOL[]",
+    "INT2[]",
+    "INT4[]",
+    "INT8[]",
+    "FLOAT4[]",
+    "FLOAT8[]",
+    "NUMERIC[]",
+    "CHAR[]",
+    "VARCHAR[]",
+    "TEXT[]",
+    "NAME[]",
+    "DATE[]",
+    "TIME[]",
+    "TIMESTAMP[]",
+    "TIMESTAMPTZ[]",
+    "JSON[]",
+    "JSONB[]",
+    "UUID",
+    "INET",
+    "CIDR",
+    "MACADDR",
+    "MACADDR8",
+    "BIT",
+    "VARBIT",
+    "POINT",
+    "LSEG",
+    "BOX",
+    "LINE",
+    "PATH",
+    "POLYGON",
+    "CIRCLE",
+];
+
+fn decodable(type_info: &PgTypeInfo) -> bool {
+    DECODABLE_TYPES.contains(&type_info.name()) || matches!(type_info.kind(), PgTypeKind::Enum(_))
+}
+
+// to_value で読めない型(hstore, 複合型、ドメイン、enum の配列など)のカラムがあれば、
+// SELECT 句(RETURNING 句)のその式を (式)::text に書き換えた SQL を返す
+// 式の外は書き換えないので、エラーの位置がずれるのは書き換えた式より後ろだけになる
+// ワイルドカードなどで式の位置が分からない場合は書き換えず、そのカラムはデコードできない値になる
+fn cast_to_text(query: &str, columns: &[PgColumn]) -> Option<String> {
+    if columns.iter().all(|column| decodable(column.type_info())) {
+        return None;
+    }
+    let ranges = sql_parser::projection_ranges(&PostgreSqlDialect {}, query)?;
+    if ranges.len() != columns.len() {
+        return None;
+    }
+
+    let mut sent = query.to_string();
+    for (column, (start, end)) in columns.iter().zip(ranges).rev() {
+        if decodable(column.type_info()) {
+            continue;
+        }
+        sent.insert_str(end, ")::text");
+        sent.insert(start, '(');
+    }
+    Some(sent)
+}
+
+fn to_value(row: &PgRow, column: &PgColumn) -> Value {
+    let ordinal = column.ordinal();
+    let type_info = column.type_info();
+    let type_name = type_info.name();
+
+    let raw = match row.try_get_raw(ordinal) {
+        Ok(raw) if raw.is_null() => return Value::Null,
+        Ok(raw) => raw,
+        Err(e) => return backend::decoded(Err(e.to_string()), Vec::new),
+    };
+    let bytes = raw.as_bytes().unwrap_or_default();
+    let invalid = || format!("Invalid {} value", type_name);
+
+    // テキスト形式で返ってきた値は、サーバーの表現をそのまま使う
+    if raw.format() == PgValueFormat::Text {
+        let value = raw
+            .as_str()
+            .map(|v| Value::Text(v.to_string()))
+            .map_err(|e| e.to_string());
+        return backend::decoded(value, || bytes.to_vec());
+    }
+
+    let value = match type_name {
+        "BOOL" => get::<bool>(row, ordinal).map(Value::Bool),
+        "INT1" | "\"CHAR\"" => get::<i8>(row, ordinal).map(|v| Value::Int(v as i64)),
+        "INT2" => get::<i16>(row, ordinal).map(|v| Value::Int(v as i64)),
+        "INT4" => get::<i32>(row, ordinal).map(|v| Value::Int(v as i64)),
+        "INT8" => get::<i64>(row, ordinal).map(Value::Int),
+        "OID" => get::<Oid>(row, ordinal).map(|v| Value::Int(v.0 as i64)),
+        "FLOAT4" => get::<f32>(row, ordinal).map(|v| Value::Float(f32_to_f64(v))),
+        "FLOAT8" => get::<f64>(row, ordinal).map(Value::Float),
+        "NUMERIC" => get::<BigDecimal>(row, ordinal).map(|v| Value::Decimal(v.to_string())),
+        "MONEY" => get::<PgMoney>(row, ordinal)
+            .map(|v| Value::Decimal(v.to_bigdecimal(MONEY_FRAC_DIGITS).to_string())),
+        "CHAR" | "VARCHAR" | "TEXT" | "NAME" | "UNKNOWN" => {
+            get::<String>(row, ordinal).map(Value::Text)
+        }
+        "BYTEA" => get::<Vec<u8>>(row, ordinal).map(Value::Bytes),
+        "DATE" => get::<NaiveDate>(row, ordinal).map(|v| Value::Date(v.to_string())),
+        "TIME" => get::<NaiveTime>(row, ordinal).map(|v| Value::Time(v.to_string())),
+        "TIMETZ" => get::<PgTimeTz<NaiveTime, FixedOffset>>(row, ordinal)
+            .map(|v| Value::Time(format!("{}{}", v.time, v.offset))),
+        "TIMESTAMP" => get::<NaiveDateTime>(row, ordinal).map(|v| Value::Timestamp(v.to_string())),
+        "TIMESTAMPTZ" => {
+            get::<DateTime<Utc>>(row, ordinal).map(|v| Value::Timestamp(v.to_rfc3339()))
+        }
+        "INTERVAL" => get::<PgInterval>(row, ordinal).map(|v| Value::Text(format_interval(&v))),
+        "JSON" | "JSONB" => get::<serde_json::Value>(row, ordinal).map(Value::Json),
+        "INT4RANGE" => get::<PgRange<i32>>(row, ordinal).map(|v| Value::Text(v.to_string())),
+        "INT8RANGE" => get::<PgRange<i64>>(row, ordinal).map(|v| Value::Text(v.to_string())),
+        "NUMRANGE" => get::<PgRange<BigDecimal>>(row, ordinal).map(|v| Value::Text(v.to_string())),
+        "DATERANGE" => get::<PgRange<NaiveDate>>(row, ordinal).map(|v| Value::Text(v.to_string())),
+        "TSRANGE" => {
+            get::<PgRange<NaiveDateTime>>(row, ordinal).map(|v| Value::Text(v.to_string()))
+        }
+        "TSTZRANGE" => {
+            get::<PgRange<DateTime<Utc>>>(row, ordinal).map(|v| Value::Text(v.to_string()))
+        }
+        "BOOL[]" => get_array::<bool>(row, ordinal, serde_json::Value::from),
+        "INT2[]" => get_array::<i16>(row, ordinal, serde_json::Value::from),
+        "INT4[]" => get_array::<i32>(row, ordinal, serde_json::Value::from),
+        "INT8[]" => get_array::<i64>(row, ordinal, serde_json::Value::from),
+        "FLOAT4[]" => get_array::<f32>(row, ordinal, |v| f32_to_f64(v).into()),
+        "FLOAT8[]" => get_array::<f64>(row, ordinal, serde_json::Value::from),
+        "NUMERIC[]" => get_array::<BigDecimal>(row, ordinal, |v| v.to_string().into()),
+        "CHAR[]" | "VARCHAR[]" | "TEXT[]" | "NAME[]" => {
+            get_array::<String>(row, ordinal, serde_json::Value::from)
         }
-        result.push(map);
+        "DATE[]" => get_array::<NaiveDate>(row, ordinal, |v| v.to_string().into()),
+        "TIME[]" => get_array::<NaiveTime>(row, ordinal, |v| v.to_string().into()),
+        "TIMESTAMP[]" => get_array::<NaiveDateTime>(row, ordinal, |v| v.to_string().into()),
+        "TIMESTAMPTZ[]" => get_array::<DateTime<Utc>>(row, ordinal, |v| v.to_rfc3339().into()),
+        "JSON[]" | "JSONB[]" => get_array::<serde_json::Value>(row, ordinal, |v| v),
+        // 以下は sqlx が対応していないため、バイナリ形式を直接読む
+        "UUID" => format_uuid(bytes).map(Value::Text).ok_or_else(invalid),
+        "INET" | "CIDR" => format_inet(bytes, type_name == "CIDR")
+            .map(Value::Text)
+            .ok_or_else(invalid),
+        "MACADDR" | "MACADDR8" => Ok(Value::Text(format_macaddr(bytes))),
+        "BIT" | "VARBIT" => format_bits(bytes).map(Value::Text).ok_or_else(invalid),
+        "POINT" | "LSEG" | "BOX" | "LINE" | "PATH" | "POLYGON" | "CIRCLE" => {
+            format_geometry(type_name, bytes)
+                .map(Value::Text)
+                .ok_or_else(invalid)
+        }
+        _ => match type_info.kind() {
+            // enum のバイナリ表現はラベル文字列そのもの
+            PgTypeKind::Enum(_) => std::str::from_utf8(bytes)
+                .map(|v| Value::Text(v.to_string()))
+                .map_err(|e| e.to_string()),
+            // 未対応の型は cast_to_text で text にキャストする。キャストできなかった場合はここに来る
+            _ => Err(format!("Unsupported type: {}", type_name)),
+        },
+    };
+
+    backend::decoded(value, || bytes.to_vec())
+}
+
+// lc_monetary に依存するが、ほとんどのロケールで小数点以下は 2 桁
+const MONEY_FRAC_DIGITS: i64 = 2;
+
+// PostgreSQL の intervalstyle = postgres と同じ形式にする (例: 1 year 2 mons 3 days 04:05:06)
+// 単数形になるのは値がちょうど 1 の場合のみ (-1 は "-1 days")
+fn format_interval(interval: &PgInterval) -> String {
+    let mut parts: Vec<String> = vec![];
+
+    let years = interval.months / 12;
+    let months = interval.months % 12;
+    if years != 0 {
+        parts.push(format!(
+            "{} year{}",
+            years,
+            if years == 1 { "" } else { "s" }
+        ));
+    }
+    if months != 0 {
+        parts.push(format!(
+            "{} mon{}",
+            months,
+            if months == 1 { "" } else { "s" }
+        ));
     }
+    if interval.days != 0 {
+        let days = interval.days;
+        parts.push(format!("{} day{}", days, if days == 1 { "" } else { "s" }));
+    }
+
+    if interval.microseconds != 0 || parts.is_empty() {
+        let sign = if interval.microseconds < 0 { "-" } else { "" };
+        let micros = interval.microseconds.unsigned_abs();
+        let seconds = micros / 1_000_000;
+        let fraction = micros % 1_000_000;
 
-    let mut columns: Vec<crate::model::Column> = vec![];
-    if query_result.len() > 0 {
-        for column in query_result[0].columns() {
-            columns.push(crate::model::Column {
-                ordinal: column.ordinal(),
-                name: column.name().to_string(),
-            });
+        let mut time = format!(
+            "{}{:02}:{:02}:{:02}",
+            sign,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+        if fraction != 0 {
+            time.push_str(format!(".{:06}", fraction).trim_end_matches('0'));
         }
+        parts.push(time);
+    }
+
+    parts.join(" ")
+}
+
+fn format_uuid(bytes: &[u8]) -> Option<String> {
+    if bytes.len() != 16 {
+        return None;
+    }
+
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+// バイナリ形式: family(1) bits(1) is_cidr(1) nb(1) addr(nb)
+fn format_inet(bytes: &[u8], is_cidr: bool) -> Option<String> {
+    if bytes.len() < 4 {
+        return None;
+    }
+
+    let bits = bytes[1];
+    let address = &bytes[4..];
+    let (address, max_bits) = match address.len() {
+        4 => (
+            IpAddr::from(<[u8; 4]>::try_from(address).ok()?).to_string(),
+            32,
+        ),
+        16 => (
+            IpAddr::from(<[u8; 16]>::try_from(address).ok()?).to_string(),
+            128,
+        ),
+        _ => return None,
+    };
+
+    if is_cidr || bits != max_bits {
+        Some(format!("{}/{}", address, bits))
+    } else {
+        Some(address)
+    }
+}
+
+fn format_macaddr(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+// バイナリ形式: ビット長(4) + 上位ビットから詰めたバイト列
+fn format_bits(bytes: &[u8]) -> Option<String> {
+    let len = i32::from_be_bytes(bytes.get(0..4)?.try_into().ok()?) as usize;
+    let data = &bytes[4..];
+
+    let mut bits = String::with_capacity(len);
+    for i in 0..len {
+        let byte = data.get(i / 8)?;
+        bits.push(if byte & (0x80 >> (i % 8)) != 0 {
+            '1'
+        } else {
+            '0'
+        });
     }
 
-    Ok((columns, result))
+    Some(bits)
+}
+
+fn read_f64(bytes: &[u8]) -> Option<f64> {
+    Some(f64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn read_points(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks_exact(16)
+        .filter_map(|point| {
+            let x = read_f64(&point[0..8])?;
+            let y = read_f64(&point[8..16])?;
+            Some(format!("({},{})", x, y))
+        })
+        .collect()
+}
+
+// 幾何型は PostgreSQL のテキスト表現に合わせる
+fn format_geometry(type_name: &str, bytes: &[u8]) -> Option<String> {
+    match type_name {
+        "POINT" => read_points(bytes).into_iter().next(),
+        "LSEG" => Some(format!("[{}]", read_points(bytes).join(","))),
+        "BOX" => Some(read_points(bytes).join(",")),
+        "LINE" => {
+            let values: Vec<String> = bytes
+                .chunks_exact(8)
+                .filter_map(|v| read_f64(v).map(|v| v.to_string()))
+                .collect();
+            Some(format!("{{{}}}", values.join(",")))
+        }
+        "CIRCLE" => {
+            let center = read_points(bytes.get(0..16)?).join("");
+            let radius = read_f64(bytes.get(16..24)?)?;
+            Some(format!("<{},{}>", center, radius))
+        }
+        "PATH" => {
+            // closed(1) npts(4) points
+            let closed = *bytes.first()? != 0;
+            let points = read_points(bytes.get(5..)?).join(",");
+            if closed {
+                Some(format!("({})", points))
+            } else {
+                Some(format!("[{}]", points))
+            }
+        }
+        "POLYGON" => {
+            // npts(4) points
+            let points = read_points(bytes.get(4..)?).join(",");
+            Some(format!("({})", points))
+        }
+        _ => None,
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(values: &[f64]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn format_interval_matches_postgres_style() {
+        let interval = |months, days, microseconds| PgInterval {
+            months,
+            days,
+            microseconds,
+        };
+        let cases = [
+            (interval(0, 0, 0), "00:00:00"),
+            (
+                interval(14, 3, 14_706_000_000),
+                "1 year 2 mons 3 days 04:05:06",
+            ),
+            (interval(24, 1, 0), "2 years 1 day"),
+            (interval(1, 0, 1_500_000), "1 mon 00:00:01.5"),
+            (interval(-14, -1, 0), "-1 years -2 mons -1 days"),
+            (interval(0, 0, -3_723_000_001), "-01:02:03.000001"),
+            (interval(0, 1, -3_600_000_000), "1 day -01:00:00"),
+            (interval(0, 0, 360_000_000_000), "100:00:00"),
+        ];
+        for (interval, expected) in cases {
+            assert_eq!(format_interval(&interval), expected);
+        }
+    }
+
+    #[test]
+    fn format_uuid_from_wire_bytes() {
+        let bytes: Vec<u8> = (0..16).collect();
+        assert_eq!(
+            format_uuid(&bytes).as_deref(),
+            Some("00010203-0405-0607-0809-0a0b0c0d0e0f")
+        );
+        assert_eq!(format_uuid(&bytes[..15]), None);
+    }
+
+    #[test]
+    fn format_inet_from_wire_bytes() {
+        let cases: [(&[u8], bool, Option<&str>); 7] = [
+            (&[2, 32, 0, 4, 192, 168, 0, 1], false, Some("192.168.0.1")),
+            (
+                &[2, 24, 0, 4, 192, 168, 0, 1],
+                false,
+                Some("192.168.0.1/24"),
+            ),
+            // cidr は /32 でも prefix を付ける
+            (&[2, 32, 1, 4, 10, 0, 0, 1], true, Some("10.0.0.1/32")),
+            (
+                &[
+                    3, 128, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+                ],
+                false,
+                Some("::1"),
+            ),
+            (
+                &[
+                    3, 64, 1, 16, 0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                ],
+                true,
+                Some("2001:db8::/64"),
+            ),
+            (&[2, 32, 0, 3, 192, 168, 0], false, None),
+            (&[2, 32, 0], false, None),
+        ];
+        for (bytes, is_cidr, expected) in cases {
+            assert_eq!(format_inet(bytes, is_cidr).as_deref(), expected);
+        }
+    }
+
+    #[test]
+    fn format_macaddr_from_wire_bytes() {
+        assert_eq!(
+            format_macaddr(&[0x08, 0x00, 0x2b, 0x01, 0x02, 0x03]),
+            "08:00:2b:01:02:03"
+        );
+        // macaddr8
+        assert_eq!(
+            format_macaddr(&[0x08, 0x00, 0x2b, 0xff, 0xfe, 0x01, 0x02, 0x03]),
+            "08:00:2b:ff:fe:01:02:03"
+        );
+    }
+
+    #[test]
+    fn format_bits_from_wire_bytes() {
+        let cases: [(&[u8], Option<&str>); 5] = [
+            (&[0, 0, 0, 5, 0b1010_1000], Some("10101")),
+            (&[0, 0, 0, 10, 0xff, 0b0100_0000], Some("1111111101")),
+            (&[0, 0, 0, 0], Some("")),
+            // ビット長に対してバイトが足りない
+            (&[0, 0, 0, 9, 0xff], None),
+            (&[0, 0, 0], None),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(format_bits(bytes).as_deref(), expected);
+        }
+    }
+
+    #[test]
+    fn format_geometry_from_wire_bytes() {
+        let path = |closed: u8| {
+            let mut bytes = vec![closed, 0, 0, 0, 2];
+            bytes.extend(points(&[0.0, 0.0, 1.5, -2.0]));
+            bytes
+        };
+        let mut polygon = vec![0, 0, 0, 3];
+        polygon.extend(points(&[0.0, 0.0, 1.0, 0.0, 0.0, 1.0]));
+
+        let cases = [
+            ("POINT", points(&[1.5, -2.0]), Some("(1.5,-2)")),
+            ("LSEG", points(&[0.0, 0.0, 1.0, 1.0]), Some("[(0,0),(1,1)]")),
+            ("BOX", points(&[1.0, 1.0, 0.0, 0.0]), Some("(1,1),(0,0)")),
+            ("LINE", points(&[1.0, -1.0, 0.0]), Some("{1,-1,0}")),
+            ("CIRCLE", points(&[0.0, 0.0, 2.5]), Some("<(0,0),2.5>")),
+            ("PATH", path(1), Some("((0,0),(1.5,-2))")),
+            ("PATH", path(0), Some("[(0,0),(1.5,-2)]")),
+            ("POLYGON", polygon, Some("((0,0),(1,0),(0,1))")),
+            ("CIRCLE", points(&[0.0, 0.0]), None),
+            ("POINT", vec![], None),
+        ];
+        for (type_name, bytes, expected) in cases {
+            assert_eq!(format_geometry(type_name, &bytes).as_deref(), expected);
+        }
+    }
+}
@@ -1,901 +1,1995 @@
+use std::collections::{BTreeSet, HashMap};
+
 use sqlparser::{
     ast::{
-        Array, ConnectBy, Cte, DictionaryField, Distinct, Expr, ExprWithAlias, Fetch, Function,
-        FunctionArg, FunctionArgExpr, FunctionArgumentClause, FunctionArgumentList,
-        FunctionArguments, GroupByExpr, HavingBound, Interpolate, InterpolateExpr, Interval, Join,
+        Array, AssignmentTarget, BinaryOperator, ConnectBy, CreateTable, Cte, DataType,
+        DictionaryField, Distinct, Expr, ExprWithAlias, Fetch, FromTable, Function, FunctionArg,
+        FunctionArgExpr, FunctionArgumentClause, FunctionArgumentList, FunctionArguments,
+        GroupByExpr, HavingBound, Ident, Insert, Interpolate, InterpolateExpr, Interval, Join,
         JoinConstraint, JoinOperator, LambdaFunction, LateralView, ListAggOnOverflow, Map,
-        MapEntry, Measure, NamedWindowDefinition, NamedWindowExpr, Offset, OrderBy, OrderByExpr,
-        PivotValueSource, Query, Select, SelectItem, SetExpr, Statement, SymbolDefinition,
-        TableFactor, TableFunctionArgs, TableVersion, TableWithJoins, Top, TopQuantity, WindowSpec,
+        MapEntry, Measure, MergeAction, MergeInsertKind, NamedWindowDefinition, NamedWindowExpr,
+        ObjectName, Offset, OrderBy, OrderByExpr, PivotValueSource, Query, Select, SelectItem,
+        SetExpr, SetQuantifier, Spanned, Statement, SymbolDefinition, TableFactor,
+        TableFunctionArgs, TableVersion, TableWithJoins, Top, TopQuantity, Value, WindowSpec,
         WindowType, With, WithFill,
     },
-    dialect::{MySqlDialect, PostgreSqlDialect},
+    dialect::{
+        AnsiDialect, BigQueryDialect, Dialect, GenericDialect, HiveDialect, MsSqlDialect,
+        MySqlDialect, PostgreSqlDialect, RedshiftSqlDialect, SQLiteDialect, SnowflakeDialect,
+    },
+    keywords::Keyword,
     parser::{Parser, ParserError},
+    tokenizer::{Location, Span as TokenSpan, Token, TokenWithSpan, Tokenizer},
 };
 
-pub async fn find_postgres_select_statement(
-    sql: &String,
-) -> Result<(Vec<String>, Vec<String>), ParserError> {
-    let dialect = PostgreSqlDialect {};
+use crate::model::{ClauseKind, OuterReference, SetOperation, Span, SubqueryNode};
+
+// SQL 中の範囲。(開始位置, 終了位置) のバイト位置
+type Range = (usize, usize);
+
+// 元の SQL のトークン。(トークン, sqlparser の位置, SQL 中の範囲)
+type SourceToken = (Token, Location, Range);
+
+// 集合演算の各 SELECT は、次の演算子かクエリ全体の ORDER BY などの手前で終わる
+const SET_OPERATION_STOPS: &[Keyword] = &[
+    Keyword::UNION,
+    Keyword::EXCEPT,
+    Keyword::INTERSECT,
+    Keyword::ORDER,
+    Keyword::LIMIT,
+    Keyword::OFFSET,
+    Keyword::FETCH,
+];
+
+// INSERT の元になる SELECT は、ON CONFLICT などや RETURNING の手前で終わる
+const INSERT_SOURCE_STOPS: &[Keyword] = &[Keyword::ON, Keyword::RETURNING];
+
+// 名前から dialect を作る。接続せずに解析する場合や、他のデータベースの SQL を解析する場合に使う
+// dialect を追加する場合は、フロントエンドの Dialect 型にも追加する
+pub fn dialect_from_name(name: &str) -> Option<Box<dyn Dialect>> {
+    let dialect: Box<dyn Dialect> = match name {
+        "generic" => Box::new(GenericDialect {}),
+        "ansi" => Box::new(AnsiDialect {}),
+        "postgres" => Box::new(PostgreSqlDialect {}),
+        "mysql" => Box::new(MySqlDialect {}),
+        "sqlite" => Box::new(SQLiteDialect {}),
+        "mssql" => Box::new(MsSqlDialect {}),
+        "snowflake" => Box::new(SnowflakeDialect {}),
+        "bigquery" => Box::new(BigQueryDialect {}),
+        "hive" => Box::new(HiveDialect {}),
+        "redshift" => Box::new(RedshiftSqlDialect {}),
+        _ => return None,
+    };
+    Some(dialect)
+}
+
+// 相関サブクエリが参照する外側のカラムは、runnable_sql の中では
+// placeholder(パラメーター名) で作ったパラメーターに置き換える
+pub fn find_select_statement(
+    dialect: &dyn Dialect,
+    sql: &str,
+    placeholder: impl Fn(&str) -> String,
+) -> Result<Vec<SubqueryNode>, ParserError> {
+    // ノードの位置を AST の span から求めるので、同じトークンから解析する
+    let tokens = Tokenizer::new(dialect, sql).tokenize_with_location()?;
+    let ast = Parser::new(dialect)
+        .with_tokens_with_locations(tokens.clone())
+        .parse_statements()?;
+    let tokens = source_tokens(sql, tokens);
+
+    let mut walker = Walker {
+        sql,
+        tokens: &tokens,
+        statement: None,
+        stops: &[],
+        nodes: vec![],
+        parent_id: None,
+        clause: ClauseKind::Statement,
+        alias: None,
+        preview_of: None,
+        used_ctes: vec![],
+        reference_ranges: vec![],
+        ctes: HashMap::new(),
+        cte_scopes: vec![],
+        table_scopes: vec![],
+    };
+    for (statement, range) in ast.iter().zip(statement_token_ranges(&tokens)) {
+        walker.statement = Some(range);
+        walk_statement(&mut walker, statement);
+    }
+    walker.resolve(placeholder);
 
-    let ast = Parser::parse_sql(&dialect, &sql)?;
+    Ok(walker.nodes)
+}
 
-    let mut select_statements: (Vec<String>, Vec<String>) = (vec![], vec![]);
-    for statement in ast.iter() {
-        let (with, select) = walk_statement(statement);
-        select_statements.0.extend(with);
-        select_statements.1.extend(select);
+// 構文エラーがあっても抽出できるよう、; で区切った文ごとに解析する
+// 戻り値は (解析できた文のノード, 解析できなかった文の (SQL 中の範囲, エラー))
+// ノードの id と位置は、SQL 全体のものにする
+pub fn find_select_statement_each(
+    dialect: &dyn Dialect,
+    sql: &str,
+    placeholder: impl Fn(&str) -> String,
+) -> (Vec<SubqueryNode>, Vec<(Range, ParserError)>) {
+    let mut nodes = vec![];
+    let mut errors = vec![];
+    for (start, end) in statement_ranges(sql) {
+        match find_select_statement(dialect, &sql[start..end], &placeholder) {
+            Ok(part) => {
+                let base = nodes.len();
+                nodes.extend(part.into_iter().map(|mut node| {
+                    node.id += base;
+                    node.parent_id = node.parent_id.map(|id| id + base);
+                    node.span = node
+                        .span
+                        .map(|span| to_span(sql, start + span.start, start + span.end));
+                    node
+                }));
+            }
+            Err(e) => errors.push(((start, end), e)),
+        }
     }
-    return Ok(select_statements);
+    (nodes, errors)
 }
 
-pub async fn find_mysql_select_statement(
-    sql: &String,
-) -> Result<(Vec<String>, Vec<String>), ParserError> {
-    let dialect = MySqlDialect {};
+// スクリプトを文に分ける。戻り値は (正規化した文, SQL 中の範囲)
+// 区切りの ; が文の数と合わない場合、範囲は None にする
+pub fn split_statements(
+    dialect: &dyn Dialect,
+    sql: &str,
+) -> Result<Vec<(String, Option<Range>)>, ParserError> {
+    let ast = Parser::parse_sql(dialect, sql)?;
+
+    let ranges = statement_ranges(sql);
+    let statements = if ranges.len() == ast.len() {
+        ast.iter()
+            .zip(ranges)
+            .map(|(statement, range)| (statement.to_string(), Some(range)))
+            .collect()
+    } else {
+        ast.iter()
+            .map(|statement| (statement.to_string(), None))
+            .collect()
+    };
 
-    let ast = Parser::parse_sql(&dialect, &sql)?;
+    Ok(statements)
+}
 
-    let mut select_statements: (Vec<String>, Vec<String>) = (vec![], vec![]);
-    for statement in ast.iter() {
-        let (with, select) = walk_statement(statement);
-        select_statements.0.extend(with);
-        select_statements.1.extend(select);
+// 問い合わせ(SELECT など)以外の文があれば、最初のその文の種類("DROP TABLE" など)を返す
+// WITH ... INSERT や SELECT ... INTO のように、Query として解析されてもデータを変更するものも含める
+pub fn find_non_query(dialect: &dyn Dialect, sql: &str) -> Result<Option<String>, ParserError> {
+    let ast = Parser::parse_sql(dialect, sql)?;
+
+    let kind = ast.iter().find_map(|statement| match statement {
+        Statement::Query(query) => modifying_query_name(query),
+        _ => Some(statement_name(statement)),
+    });
+
+    Ok(kind)
+}
+
+// Query のうちデータを変更するものの種類。PostgreSQL では CTE の中でも変更できる
+fn modifying_query_name(query: &Query) -> Option<String> {
+    query
+        .with
+        .iter()
+        .flat_map(|with| &with.cte_tables)
+        .find_map(|cte| modifying_query_name(&cte.query))
+        .or_else(|| modifying_setexpr_name(&query.body))
+}
+
+fn modifying_setexpr_name(setexpr: &SetExpr) -> Option<String> {
+    match setexpr {
+        SetExpr::Insert(statement) | SetExpr::Update(statement) => Some(statement_name(statement)),
+        SetExpr::Select(select) if select.into.is_some() => Some(String::from("SELECT INTO")),
+        SetExpr::Query(query) => modifying_query_name(query),
+        SetExpr::SetOperation { left, right, .. } => {
+            modifying_setexpr_name(left).or_else(|| modifying_setexpr_name(right))
+        }
+        _ => None,
     }
-    return Ok(select_statements);
 }
 
+// 文の種類の名前。statement_kind に無いものは、文の先頭のキーワードで表す
+fn statement_name(statement: &Statement) -> String {
+    if let Some(kind) = statement_kind(statement) {
+        return kind.to_string();
+    }
 
-fn walk_statement(statement: &Statement) -> (Vec<String>, Vec<String>) {
-    //println!("{:?}", statement);
-    match statement {
-        Statement::Query(query) => {
-            return walk_query(query);
+    // CREATE などは対象の種類まで含める(CREATE OR REPLACE VIEW は CREATE VIEW)
+    let sql = statement.to_string();
+    let mut words = sql.split_whitespace();
+    let first = words.next().unwrap_or_default().to_uppercase();
+    if !["CREATE", "DROP", "ALTER"].contains(&first.as_str()) {
+        return first;
+    }
+    let modifiers = ["OR", "REPLACE", "TEMPORARY", "TEMP", "UNIQUE"];
+    match words.find(|word| !modifiers.contains(&word.to_uppercase().as_str())) {
+        Some(object) => format!("{} {}", first, object.to_uppercase()),
+        None => first,
+    }
+}
+
+// 結果を max_rows 行までにする LIMIT(FETCH がある場合は FETCH)を付けた SQL を返す
+// 打ち切られたかが分かるよう、1 行多く取る
+// 1 つの SELECT 文でない場合や、既に max_rows 以下の LIMIT/FETCH がある場合は None
+// LIMIT を末尾に足すだけで済む場合は、エラーの位置が元の SQL と変わらないよう文字列で足す
+pub fn limit_rows(dialect: &dyn Dialect, sql: &str, max_rows: u64) -> Option<String> {
+    let mut ast = Parser::parse_sql(dialect, sql).ok()?;
+    let [Statement::Query(query)] = ast.as_mut_slice() else {
+        return None;
+    };
+    // WITH ... INSERT/UPDATE も Query になるが、LIMIT を付けると変更する行まで減ってしまう
+    if !returns_rows(&query.body) {
+        return None;
+    }
+
+    let quantity = Expr::Value(Value::Number((max_rows + 1).to_string(), false));
+    match (&query.limit, &mut query.fetch) {
+        // OFFSET や FOR UPDATE との順序はデータベースによって違うので、その場合は作り直す
+        (None, None) if query.offset.is_none() && query.locks.is_empty() => {
+            // 末尾のコメントの中に入らないよう、改行してから足す
+            let end = content_end(sql);
+            return Some(format!(
+                "{}\nLIMIT {}{}",
+                &sql[..end],
+                max_rows + 1,
+                &sql[end..]
+            ));
+        }
+        (None, None) => query.limit = Some(quantity),
+        (Some(limit), _) => {
+            // パラメーターなど、値が分からない LIMIT はそのままにする
+            if number(limit)? <= max_rows {
+                return None;
+            }
+            query.limit = Some(quantity);
         }
-        _ => {
-            return (vec![], vec![]);
+        // FETCH FIRST ROW ONLY は 1 行
+        (None, Some(fetch)) => {
+            let current = match &fetch.quantity {
+                Some(expr) => number(expr)?,
+                None => 1,
+            };
+            if fetch.percent || current <= max_rows {
+                return None;
+            }
+            fetch.quantity = Some(quantity);
         }
     }
+
+    Some(query.to_string())
 }
 
-fn walk_distinct(distinct: &Distinct) -> (Vec<String>, Vec<String>) {
-    match distinct {
-        Distinct::On(vecexpr) => {
-            let mut select_statement = (vec![], vec![]);
-            for expr in vecexpr {
-                let (w, s) = walk_expr(&expr);
-                select_statement.0.extend(w);
-                select_statement.1.extend(s);
+// 行を返すだけの問い合わせの本体か。SELECT ... INTO は表を作るので含めない
+fn returns_rows(setexpr: &SetExpr) -> bool {
+    match setexpr {
+        SetExpr::Select(select) => select.into.is_none(),
+        SetExpr::SetOperation { .. }
+        | SetExpr::Values(_)
+        | SetExpr::Query(_)
+        | SetExpr::Table(_) => true,
+        SetExpr::Insert(_) | SetExpr::Update(_) => false,
+    }
+}
+
+fn number(expr: &Expr) -> Option<u64> {
+    match expr {
+        Expr::Value(Value::Number(n, _)) => n.parse().ok(),
+        _ => None,
+    }
+}
+
+// サブクエリを木構造で集める
+// parent_id と clause は、今歩いている場所(親のノードと句)を表す
+struct Walker<'a> {
+    // 元の SQL と、そのトークン
+    sql: &'a str,
+    tokens: &'a [SourceToken],
+    // 今歩いている文の範囲
+    statement: Option<Range>,
+    // 次に追加するノードを終える、括弧の外のキーワード
+    stops: &'static [Keyword],
+    nodes: Vec<SubqueryNode>,
+    parent_id: Option<usize>,
+    clause: ClauseKind,
+    // 次に追加するノードの別名
+    alias: Option<String>,
+    // 次に追加するノードがプレビューする文の種類
+    preview_of: Option<&'static str>,
+    // 以下はノードの id で引く
+    // ノードの中から直接参照している、ノードの外で定義された CTE
+    used_ctes: Vec<BTreeSet<usize>>,
+    // ノードの中にある外側への参照の、元の SQL 中の範囲とパラメーター名
+    reference_ranges: Vec<Vec<(Range, String)>>,
+    // CTE の定義と WITH RECURSIVE かどうか。CTE はその本体のノードの id で表す
+    ctes: HashMap<usize, (Cte, bool)>,
+    // 今見えている CTE。WITH 句ごとに (名前, CTE, WITH 句を持つクエリ) を積む
+    cte_scopes: Vec<Vec<(Ident, usize, usize)>>,
+    // 今見えているテーブルの別名。SELECT ごとに (SELECT を持つノード, 別名) を積む
+    table_scopes: Vec<(usize, Vec<Ident>)>,
+}
+
+impl Walker<'_> {
+    // 今いる場所にノードを追加して、その id を返す
+    // range は元の SQL 中の範囲。sql は書き換えたもの(UPDATE などのプレビュー)でもよい
+    fn push_node(&mut self, sql: String, range: Option<Range>) -> usize {
+        let id = self.nodes.len();
+        self.stops = &[];
+        self.nodes.push(SubqueryNode {
+            id,
+            parent_id: self.parent_id,
+            clause: self.clause,
+            alias: self.alias.take(),
+            runnable_sql: sql.clone(),
+            sql,
+            span: range.map(|(start, end)| to_span(self.sql, start, end)),
+            ctes: vec![],
+            preview_of: self.preview_of.take().map(String::from),
+            set_operation: None,
+            correlated: false,
+            outer_references: vec![],
+        });
+        self.used_ctes.push(BTreeSet::new());
+        self.reference_ranges.push(vec![]);
+        id
+    }
+
+    // AST のノードを、元の SQL の範囲の文字列(コメントや大文字小文字もそのまま)で追加する
+    // 範囲が分からない場合は、sqlparser が正規化した SQL にする
+    fn push_fragment(&mut self, node: &(impl Spanned + ToString)) -> usize {
+        let range = query_span(self.tokens, node.span())
+            .and_then(|span| source_range(self.tokens, span, self.stops));
+        let sql = match range {
+            Some((start, end)) => self.sql[start..end].to_string(),
+            None => node.to_string(),
+        };
+        self.push_node(sql, range)
+    }
+
+    // テーブル名が CTE を指していれば、今いるノードから CTE を定義したクエリの手前までの
+    // ノードに、その CTE を使うと記録する
+    fn use_table(&mut self, name: &ObjectName) {
+        let [table] = name.0.as_slice() else {
+            return;
+        };
+
+        let found = self
+            .cte_scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(cte_name, _, _)| same_ident(cte_name, table));
+        let Some((_, cte_id, owner_id)) = found else {
+            return;
+        };
+        let (cte_id, owner_id) = (*cte_id, *owner_id);
+
+        let mut current = self.parent_id;
+        while let Some(id) = current {
+            if id == owner_id {
+                break;
+            }
+            self.used_ctes[id].insert(cte_id);
+            current = self.nodes[id].parent_id;
+        }
+    }
+
+    // o.id のような参照の o が外側の SELECT の別名なら、今いるノードからその SELECT を
+    // 持つノードの手前までのノードを相関サブクエリとし、参照を記録する
+    // 別名の付いていないカラムは、どのテーブルのものか分からないので扱わない
+    fn use_column(&mut self, expr: &Expr, idents: &[Ident]) {
+        let [qualifier @ .., column] = idents else {
+            return;
+        };
+        let Some(table) = qualifier.last() else {
+            return;
+        };
+
+        let found = self
+            .table_scopes
+            .iter()
+            .rev()
+            .find(|(_, aliases)| aliases.iter().any(|alias| same_ident(alias, table)));
+        let Some((owner_id, _)) = found else {
+            return;
+        };
+        let owner_id = *owner_id;
+
+        let reference = OuterReference {
+            sql: expr.to_string(),
+            qualifier: qualifier
+                .iter()
+                .map(|ident| ident.value.clone())
+                .collect::<Vec<String>>()
+                .join("."),
+            column: column.value.clone(),
+            parameter_name: idents
+                .iter()
+                .map(|ident| ident.value.as_str())
+                .collect::<Vec<&str>>()
+                .join("_")
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '_' })
+                .collect(),
+        };
+
+        let range = token_range(self.tokens, expr.span());
+        let mut current = self.parent_id;
+        while let Some(id) = current {
+            if id == owner_id {
+                break;
+            }
+            if let Some(range) = range {
+                self.reference_ranges[id].push((range, reference.parameter_name.clone()));
+            }
+            let node = &mut self.nodes[id];
+            node.correlated = true;
+            if !node.outer_references.iter().any(|r| r.sql == reference.sql) {
+                node.outer_references.push(reference.clone());
+            }
+            current = node.parent_id;
+        }
+    }
+
+    // 相関サブクエリの外側への参照をパラメーターに置き換え、
+    // CTE の中で使っている CTE もたどって、各ノードに必要な CTE を前に付ける
+    fn resolve(&mut self, placeholder: impl Fn(&str) -> String) {
+        for id in 0..self.nodes.len() {
+            let mut needed = BTreeSet::new();
+            let mut stack: Vec<usize> = self.used_ctes[id].iter().cloned().collect();
+            while let Some(cte_id) = stack.pop() {
+                if needed.insert(cte_id) {
+                    stack.extend(self.used_ctes[cte_id].iter().cloned());
+                }
+            }
+
+            // 外側への参照を、AST で参照だった位置だけ後ろから置き換える
+            // 内側で同じ別名を定義した参照や、文字列リテラルの中は置き換えない
+            // 書き換えたプレビューは最上位の文なので、外側への参照を持たない
+            let mut sql = self.nodes[id].sql.clone();
+            if let Some(span) = &self.nodes[id].span {
+                if self.sql.get(span.start..span.end) == Some(sql.as_str()) {
+                    let mut ranges = self.reference_ranges[id].clone();
+                    ranges.sort();
+                    ranges.dedup();
+                    for ((start, end), name) in ranges.iter().rev() {
+                        sql.replace_range(start - span.start..end - span.start, &placeholder(name));
+                    }
+                }
+            }
+
+            // id は定義した順なので、参照される CTE が先に来る
+            let ctes: Vec<(usize, &(Cte, bool))> = needed
+                .iter()
+                .filter_map(|i| self.ctes.get(i).map(|cte| (*i, cte)))
+                .collect();
+            if ctes.is_empty() {
+                self.nodes[id].runnable_sql = sql;
+                continue;
+            }
+
+            let mut recursive = ctes.iter().any(|(_, (_, recursive))| *recursive);
+            // CTE の本体は、元の SQL の書き方のものを使う
+            let mut cte_tables: Vec<String> = ctes
+                .iter()
+                .map(|(cte_id, (cte, _))| {
+                    let body = &self.nodes[*cte_id];
+                    match body.span {
+                        Some(_) => cte
+                            .to_string()
+                            .replacen(&cte.query.to_string(), &body.sql, 1),
+                        None => cte.to_string(),
+                    }
+                })
+                .collect();
+
+            // WITH 句を 2 つ並べることはできないので、自身の WITH 句があれば CTE をその前に足す
+            let body = match strip_keyword(&sql, "with") {
+                Some(rest) => {
+                    let rest = match strip_keyword(rest, "recursive") {
+                        Some(rest) => {
+                            recursive = true;
+                            rest
+                        }
+                        None => rest,
+                    };
+                    cte_tables.push(rest.to_string());
+                    None
+                }
+                None => Some(sql),
+            };
+
+            let node_ctes = ctes
+                .iter()
+                .map(|(_, (cte, _))| cte.alias.name.value.clone())
+                .collect();
+            let node = &mut self.nodes[id];
+            node.ctes = node_ctes;
+            node.runnable_sql = format!(
+                "WITH {}{}",
+                if recursive { "RECURSIVE " } else { "" },
+                cte_tables.join(", ")
+            );
+            if let Some(body) = body {
+                node.runnable_sql.push(' ');
+                node.runnable_sql.push_str(&body);
             }
-            select_statement
         }
-        _ => (vec![], vec![]),
     }
 }
 
-fn walk_quantity(quantity: &TopQuantity) -> (Vec<String>, Vec<String>) {
-    match quantity {
-        TopQuantity::Expr(expr) => {
-            return walk_expr(&expr);
+// FROM 句のテーブルの別名(無ければテーブル名)
+fn table_aliases(from: &[TableWithJoins]) -> Vec<Ident> {
+    let mut aliases = vec![];
+    for table_with_joins in from {
+        aliases.extend(relation_aliases(&table_with_joins.relation));
+        for join in &table_with_joins.joins {
+            aliases.extend(relation_aliases(&join.relation));
         }
-        _ => {
-            return (vec![], vec![]);
+    }
+    aliases
+}
+
+fn relation_aliases(relation: &TableFactor) -> Vec<Ident> {
+    match relation {
+        TableFactor::Table { name, alias, .. } => match alias {
+            Some(alias) => vec![alias.name.clone()],
+            None => name.0.last().cloned().into_iter().collect(),
+        },
+        TableFactor::NestedJoin {
+            table_with_joins,
+            alias,
+        } => {
+            let mut aliases = table_aliases(std::slice::from_ref(table_with_joins.as_ref()));
+            aliases.extend(alias.as_ref().map(|alias| alias.name.clone()));
+            aliases
+        }
+        TableFactor::Derived { alias, .. }
+        | TableFactor::TableFunction { alias, .. }
+        | TableFactor::Function { alias, .. }
+        | TableFactor::UNNEST { alias, .. } => alias
+            .as_ref()
+            .map(|alias| alias.name.clone())
+            .into_iter()
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// パースエラーのメッセージ("Expected: ..., found: ...")から、期待したトークンと見つかったトークンを取り出す
+pub fn expected_found(error: &ParserError) -> Option<(String, String)> {
+    let message = error.to_string();
+    let (_, rest) = message.split_once("Expected: ")?;
+    let (expected, found) = rest.split_once(", found: ")?;
+    let found = match found.rsplit_once(" at Line: ") {
+        Some((found, _)) => found,
+        None => found,
+    };
+    Some((expected.to_string(), found.to_string()))
+}
+
+// 先頭の空白とコメントを飛ばして、キーワードで始まっていればその後ろを返す
+fn strip_keyword<'a>(sql: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = &sql[leading_comments_len(sql)..];
+    let head = rest.get(..keyword.len())?;
+    let next = rest[keyword.len()..].chars().next();
+    if !head.eq_ignore_ascii_case(keyword) || !next.is_some_and(|c| c.is_whitespace()) {
+        return None;
+    }
+    Some(rest[keyword.len()..].trim_start())
+}
+
+// 引用符で囲まれていない識別子は大文字小文字を区別しない
+fn same_ident(a: &Ident, b: &Ident) -> bool {
+    match (a.quote_style, b.quote_style) {
+        (None, None) => a.value.eq_ignore_ascii_case(&b.value),
+        _ => a.value == b.value,
+    }
+}
+
+// トークンの位置(行と、文字数で数えた列)を元の SQL のバイト位置にして、空白とコメントを除く
+// トークンは SQL 中に現れた順なので、SQL を前から 1 度だけ読む
+fn source_tokens(sql: &str, tokens: Vec<TokenWithSpan>) -> Vec<SourceToken> {
+    let mut chars = sql.char_indices().peekable();
+    let mut current = Location::new(1, 1);
+    let mut offset = |location: Location| {
+        while current < location {
+            match chars.next() {
+                Some((_, '\n')) => current = Location::new(current.line + 1, 1),
+                Some(_) => current.column += 1,
+                None => break,
+            }
         }
+        chars.peek().map(|(i, _)| *i).unwrap_or(sql.len())
+    };
+
+    tokens
+        .into_iter()
+        .filter(|token| !matches!(token.token, Token::Whitespace(_) | Token::EOF))
+        .map(|token| {
+            let range = (offset(token.span.start), offset(token.span.end));
+            (token.token, token.span.start, range)
+        })
+        .collect()
+}
+
+// ; で区切った各文の範囲。パーサーと同じく、空の文は飛ばす
+fn statement_token_ranges(tokens: &[SourceToken]) -> Vec<Range> {
+    tokens
+        .split(|(token, _, _)| *token == Token::SemiColon)
+        .filter_map(|statement| {
+            let (_, _, (start, _)) = statement.first()?;
+            let (_, _, (_, end)) = statement.last()?;
+            Some((*start, *end))
+        })
+        .collect()
+}
+
+// クエリのノードの span。VALUES の span は VALUES ではなく最初の値から始まるので、VALUES からにする
+fn query_span(tokens: &[SourceToken], span: TokenSpan) -> Option<TokenSpan> {
+    let start = tokens
+        .binary_search_by_key(&span.start, |(_, location, _)| *location)
+        .ok()?;
+    let head = [
+        Keyword::SELECT,
+        Keyword::WITH,
+        Keyword::VALUES,
+        Keyword::TABLE,
+    ];
+    if is_keyword(&tokens[start].0, &head) || tokens[start].0 == Token::LParen {
+        return Some(span);
     }
+    let (_, location, _) = tokens[..start]
+        .iter()
+        .rfind(|(token, _, _)| is_keyword(token, &[Keyword::VALUES]))?;
+    Some(TokenSpan::new(*location, span.end))
 }
 
-fn walk_top(top: &Top) -> (Vec<String>, Vec<String>) {
-    if let Some(quantity) = &top.quantity {
-        return walk_quantity(&quantity);
+// AST のノードの span から、元の SQL 中の範囲を求める
+// sqlparser 0.53 の span はリテラルや閉じ括弧の位置を持たず末尾が欠けることがあるので、
+// span の終わりからは、対応の無い ) か ; か stops のキーワードの手前までを含める
+fn source_range(tokens: &[SourceToken], span: TokenSpan, stops: &[Keyword]) -> Option<Range> {
+    if span == TokenSpan::empty() {
+        return None;
+    }
+    let mut start = tokens
+        .binary_search_by_key(&span.start, |(_, location, _)| *location)
+        .ok()?;
+
+    // span の中で閉じた括弧は、span の前で開いている((SELECT 1) UNION (SELECT 2) など)
+    // その深さがノードの外側になる
+    let mut depth = 0;
+    let mut outer = 0;
+    let mut end = start;
+    for (i, (token, location, _)) in tokens.iter().enumerate().skip(start) {
+        let inside = *location < span.end;
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen if !inside && depth == outer => break,
+            Token::RParen => depth -= 1,
+            Token::SemiColon if depth == outer => break,
+            token if !inside && depth == outer && is_keyword(token, stops) => break,
+            _ => {}
+        }
+        if inside {
+            outer = outer.min(depth);
+        }
+        end = i;
+    }
+    let unclosed = usize::try_from(-outer).ok()?;
+    start = start.checked_sub(unclosed)?;
+    if tokens[start..start + unclosed]
+        .iter()
+        .any(|(token, _, _)| *token != Token::LParen)
+    {
+        return None;
+    }
+
+    Some((tokens[start].2 .0, tokens[end].2 .1))
+}
+
+// 引用符で囲まれていない、keywords のどれかのキーワードか
+fn is_keyword(token: &Token, keywords: &[Keyword]) -> bool {
+    match token {
+        Token::Word(word) => word.quote_style.is_none() && keywords.contains(&word.keyword),
+        _ => false,
+    }
+}
+
+// AST のノードの span が指す、元の SQL 中の範囲
+// 末尾を補わないので、識別子のように span が全てのトークンを含むものに使う
+fn token_range(tokens: &[SourceToken], span: TokenSpan) -> Option<Range> {
+    if span == TokenSpan::empty() {
+        return None;
+    }
+    let start = tokens
+        .binary_search_by_key(&span.start, |(_, location, _)| *location)
+        .ok()?;
+    let end = tokens.partition_point(|(_, location, _)| *location < span.end) - 1;
+    Some((tokens[start].2 .0, tokens[end].2 .1))
+}
+
+// ; で区切った各文の範囲(前後の空白は除く)
+fn statement_ranges(sql: &str) -> Vec<(usize, usize)> {
+    let mut boundaries = vec![0];
+    scan(sql, |c, offset, depth| {
+        if c == ';' && depth == 0 {
+            boundaries.push(offset);
+        }
+    });
+    boundaries.push(sql.len());
+
+    boundaries
+        .windows(2)
+        .map(|w| {
+            let start = if w[0] == 0 { 0 } else { w[0] + 1 };
+            trim_range(sql, start, w[1])
+        })
+        .filter(|(start, end)| start < end)
+        .collect()
+}
+
+// 前の空白とコメント、後ろの空白を除く
+fn trim_range(sql: &str, start: usize, end: usize) -> (usize, usize) {
+    let text = &sql[start..end];
+    let start = start + leading_comments_len(text);
+    let end = end - (text.len() - text.trim_end().len());
+    (start, end.max(start))
+}
+
+// 先頭の空白とコメントのバイト数
+fn leading_comments_len(sql: &str) -> usize {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+        } else if matches!(chars[i], '-' | '/') && skip_literal(&chars, i) > i {
+            i = skip_literal(&chars, i);
+        } else {
+            break;
+        }
+    }
+    chars[..i].iter().map(|c| c.len_utf8()).sum()
+}
+
+// 末尾の空白、コメント、; を除いた SQL の終わりのバイト位置
+pub fn content_end(sql: &str) -> usize {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut end = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let skip = skip_literal(&chars, i);
+        if skip > i {
+            if !matches!(chars[i], '-' | '/') {
+                end = skip;
+            }
+            i = skip;
+        } else {
+            if !chars[i].is_whitespace() && chars[i] != ';' {
+                end = i + 1;
+            }
+            i += 1;
+        }
+    }
+    chars[..end].iter().map(|c| c.len_utf8()).sum()
+}
+
+// 文字列リテラルやコメントを飛ばしながら、1 文字ずつ (文字, バイト位置, 括弧の深さ) を渡す
+fn scan(sql: &str, mut f: impl FnMut(char, usize, usize)) {
+    let chars: Vec<char> = sql.chars().collect();
+    let offsets: Vec<usize> = sql.char_indices().map(|(i, _)| i).collect();
+
+    let mut depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let skip = skip_literal(&chars, i);
+        if skip > i {
+            i = skip;
+            continue;
+        }
+
+        if chars[i] == ')' {
+            depth = usize::saturating_sub(depth, 1);
+        }
+        f(chars[i], offsets[i], depth);
+        if chars[i] == '(' {
+            depth += 1;
+        }
+        i += 1;
+    }
+}
+
+// 元の SQL のバイト位置から Span を作る
+pub fn to_span(sql: &str, start: usize, end: usize) -> Span {
+    let before = &sql[..start];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = sql[line_start..start].chars().count() + 1;
+
+    Span {
+        start,
+        end,
+        line,
+        column,
+    }
+}
+
+// offset から始まるトークンの終わりのバイト位置
+// 識別子や数値、プレースホルダー($1 など)、引用符で囲んだもの。それ以外は 1 文字
+pub fn token_end(sql: &str, offset: usize) -> usize {
+    let chars: Vec<char> = sql[offset..].chars().collect();
+    let is_word_char = |c: &&char| c.is_alphanumeric() || **c == '_';
+    let len = match skip_literal(&chars, 0) {
+        0 if chars.is_empty() => 0,
+        0 => {
+            let prefix = usize::from(matches!(chars[0], '$' | '@' | ':'));
+            let word = chars[prefix..].iter().take_while(is_word_char).count();
+            (prefix + word).max(1)
+        }
+        skip => skip,
+    };
+    offset + chars[..len].iter().map(|c| c.len_utf8()).sum::<usize>()
+}
+
+// パースエラーのメッセージの末尾にある位置(" at Line: 1, Column: 8")を、SQL 中のバイト位置にする
+// Column は文字数で数える。位置が書かれていないエラーは None
+pub fn error_offset(sql: &str, error: &ParserError) -> Option<usize> {
+    let message = error.to_string();
+    let (_, location) = message.rsplit_once(" at Line: ")?;
+    let (line, column) = location.split_once(", Column: ")?;
+    let line: usize = line.parse().ok()?;
+    let digits = column.len()
+        - column
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    let column: usize = column[..digits].parse().ok()?;
+
+    let line_start = match line {
+        0 | 1 => 0,
+        _ => sql.match_indices('\n').nth(line - 2)?.0 + 1,
+    };
+    // 行末より後ろを指す場合(SQL の終わりなど)は行末にする
+    let line_end = sql[line_start..]
+        .find('\n')
+        .map(|i| line_start + i)
+        .unwrap_or(sql.len());
+    let offset = sql[line_start..line_end]
+        .char_indices()
+        .nth(column.saturating_sub(1))
+        .map(|(i, _)| line_start + i)
+        .unwrap_or(line_end);
+
+    Some(offset)
+}
+
+// chars[start] からリテラルやコメントが始まっていれば、その終わりの位置を返す
+// 始まっていなければ start を返す
+pub fn skip_literal(chars: &[char], start: usize) -> usize {
+    let find_from = |from: usize, end: &[char]| {
+        (from..chars.len())
+            .find(|i| chars[*i..].starts_with(end))
+            .map(|i| i + end.len())
+            .unwrap_or(chars.len())
     };
 
-    (vec![], vec![])
+    match chars[start] {
+        '\'' | '"' | '`' => {
+            // '' のように 2 つ重ねたものはエスケープなので、閉じずに読み進める
+            let quote = chars[start];
+            let mut i = start + 1;
+            while i < chars.len() {
+                if chars[i] == quote {
+                    if chars.get(i + 1) == Some(&quote) {
+                        i += 2;
+                        continue;
+                    }
+                    return i + 1;
+                }
+                i += 1;
+            }
+            chars.len()
+        }
+        '-' if chars.get(start + 1) == Some(&'-') => find_from(start, &['\n']),
+        '/' if chars.get(start + 1) == Some(&'*') => find_from(start + 2, &['*', '/']),
+        // PostgreSQL のドル引用符($$...$$, $tag$...$tag$)
+        '$' => {
+            let tag_end = (start + 1..chars.len())
+                .find(|i| !(chars[*i].is_alphabetic() || chars[*i] == '_'))
+                .unwrap_or(chars.len());
+            if chars.get(tag_end) != Some(&'$') {
+                return start;
+            }
+            let tag = &chars[start..=tag_end];
+            find_from(tag_end + 1, tag)
+        }
+        _ => start,
+    }
 }
 
-fn walk_select_item(select_item: &SelectItem) -> (Vec<String>, Vec<String>) {
-    match select_item {
-        SelectItem::UnnamedExpr(expr) => {
-            return walk_expr(expr);
+// SELECT 句の各カラムがどのテーブルのカラムかを、FROM 句のテーブル名・別名から推測する
+// 推測できないカラムは None、ワイルドカードなどで並びが分からない場合は空で返す
+pub fn find_projection_tables(dialect: &dyn Dialect, sql: &str) -> Vec<Option<String>> {
+    let ast = match Parser::parse_sql(dialect, sql) {
+        Ok(ast) => ast,
+        Err(_) => return vec![],
+    };
+
+    let select = match ast.last() {
+        Some(Statement::Query(query)) => match query.body.as_ref() {
+            SetExpr::Select(select) => select,
+            _ => return vec![],
+        },
+        _ => return vec![],
+    };
+
+    // (別名 or テーブル名, テーブル名)
+    let mut tables: Vec<(String, String)> = vec![];
+    for table_with_joins in &select.from {
+        let relations = std::iter::once(&table_with_joins.relation)
+            .chain(table_with_joins.joins.iter().map(|join| &join.relation));
+        for relation in relations {
+            if let TableFactor::Table { name, alias, .. } = relation {
+                let table_name = name.to_string();
+                let qualifier = match alias {
+                    Some(alias) => alias.name.value.clone(),
+                    None => match name.0.last() {
+                        Some(ident) => ident.value.clone(),
+                        None => table_name.clone(),
+                    },
+                };
+                tables.push((qualifier, table_name));
+            }
         }
-        SelectItem::ExprWithAlias { expr, .. } => {
-            return walk_expr(expr);
+    }
+
+    let mut projection_tables = vec![];
+    for select_item in &select.projection {
+        let expr = match select_item {
+            SelectItem::UnnamedExpr(expr) => expr,
+            SelectItem::ExprWithAlias { expr, .. } => expr,
+            _ => return vec![],
+        };
+
+        let table = match expr {
+            Expr::Identifier(_) if tables.len() == 1 => Some(tables[0].1.clone()),
+            Expr::CompoundIdentifier(idents) if idents.len() >= 2 => {
+                let qualifier = &idents[idents.len() - 2].value;
+                tables
+                    .iter()
+                    .find(|(q, _)| q.eq_ignore_ascii_case(qualifier))
+                    .map(|(_, t)| t.clone())
+            }
+            _ => None,
+        };
+        projection_tables.push(table);
+    }
+
+    projection_tables
+}
+
+// 結果の各カラムになる式の、SQL 中の範囲(別名は含めない)
+// 1 つの SELECT か、RETURNING の付いた INSERT, UPDATE, DELETE の場合だけ返す
+// ワイルドカードがあるとカラムとの対応が分からないので None にする
+// 式の span は CAST などの先頭のキーワードやリテラルを含まないので、括弧の外の , で区切って求める
+pub fn projection_ranges(dialect: &dyn Dialect, sql: &str) -> Option<Vec<Range>> {
+    let tokens = Tokenizer::new(dialect, sql).tokenize_with_location().ok()?;
+    let ast = Parser::new(dialect)
+        .with_tokens_with_locations(tokens.clone())
+        .parse_statements()
+        .ok()?;
+    let tokens = source_tokens(sql, tokens);
+    let index_of = |location: Location| {
+        tokens
+            .binary_search_by_key(&location, |(_, location, _)| *location)
+            .ok()
+    };
+
+    // SELECT 句(RETURNING 句)の最初のトークンの位置
+    let (items, start) = match ast.as_slice() {
+        [Statement::Query(query)] => match query.body.as_ref() {
+            SetExpr::Select(select) => {
+                let mut start = index_of(select.select_token.0.span.start)? + 1;
+                if is_keyword(&tokens.get(start)?.0, &[Keyword::ALL]) {
+                    start += 1;
+                } else if is_keyword(&tokens.get(start)?.0, &[Keyword::DISTINCT]) {
+                    start += 1;
+                    // DISTINCT ON (...) は括弧の後ろから
+                    if is_keyword(&tokens.get(start)?.0, &[Keyword::ON]) {
+                        start = closing_paren(&tokens, start + 1)? + 1;
+                    }
+                }
+                (&select.projection, start)
+            }
+            _ => return None,
+        },
+        [Statement::Insert(Insert { returning, .. })] | [Statement::Update { returning, .. }] => {
+            (returning.as_ref()?, returning_start(&tokens)?)
         }
-        _ => {
-            return (vec![], vec![]);
+        [Statement::Delete(delete)] => (delete.returning.as_ref()?, returning_start(&tokens)?),
+        _ => return None,
+    };
+
+    // 括弧の外の , で区切る。次の句か文の終わりまで
+    let clauses = [
+        Keyword::FROM,
+        Keyword::INTO,
+        Keyword::WHERE,
+        Keyword::GROUP,
+        Keyword::HAVING,
+        Keyword::WINDOW,
+        Keyword::QUALIFY,
+        Keyword::ORDER,
+        Keyword::LIMIT,
+        Keyword::OFFSET,
+        Keyword::FETCH,
+        Keyword::FOR,
+    ];
+    let mut pieces = vec![];
+    let mut piece_start = start;
+    let mut depth = 0;
+    let mut end = tokens.len();
+    for (i, (token, _, _)) in tokens.iter().enumerate().skip(start) {
+        match token {
+            Token::LParen | Token::LBracket => depth += 1,
+            Token::RParen | Token::RBracket if depth > 0 => depth -= 1,
+            Token::Comma if depth == 0 => {
+                pieces.push((piece_start, i));
+                piece_start = i + 1;
+            }
+            Token::RParen | Token::RBracket | Token::SemiColon => {
+                end = i;
+                break;
+            }
+            token if depth == 0 && is_keyword(token, &clauses) => {
+                end = i;
+                break;
+            }
+            _ => {}
         }
     }
+    pieces.push((piece_start, end));
+    if pieces.len() != items.len() {
+        return None;
+    }
+
+    items
+        .iter()
+        .zip(pieces)
+        .map(|(item, (start, end))| {
+            let end = match item {
+                SelectItem::UnnamedExpr(_) => end,
+                // 別名と、その前の AS は除く
+                SelectItem::ExprWithAlias { alias, .. } => {
+                    let alias = index_of(alias.span.start)?;
+                    match is_keyword(&tokens[alias - 1].0, &[Keyword::AS]) {
+                        true => alias - 1,
+                        false => alias,
+                    }
+                }
+                _ => return None,
+            };
+            if start >= end {
+                return None;
+            }
+            Some((tokens[start].2 .0, tokens[end - 1].2 .1))
+        })
+        .collect()
 }
 
-fn walk_lateral_view(lateral_view: &LateralView) -> (Vec<String>, Vec<String>) {
-    return walk_expr(&lateral_view.lateral_view);
+// RETURNING 句の最初のトークンの位置
+fn returning_start(tokens: &[SourceToken]) -> Option<usize> {
+    tokens
+        .iter()
+        .rposition(|(token, _, _)| is_keyword(token, &[Keyword::RETURNING]))
+        .map(|i| i + 1)
 }
 
-fn walk_group_by_expr(group_by_expr: &GroupByExpr) -> (Vec<String>, Vec<String>) {
-    match group_by_expr {
-        GroupByExpr::Expressions(vecexpr, _) => {
-            let mut select_statement = (vec![], vec![]);
-            for expr in vecexpr {
-                let (w, s) = walk_expr(&expr);
-                select_statement.0.extend(w);
-                select_statement.1.extend(s);
+// tokens[start] の ( に対応する ) の位置
+fn closing_paren(tokens: &[SourceToken], start: usize) -> Option<usize> {
+    if tokens.get(start)?.0 != Token::LParen {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, (token, _, _)) in tokens.iter().enumerate().skip(start) {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen if depth == 1 => return Some(i),
+            Token::RParen => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn walk_statement(walker: &mut Walker, statement: &Statement) {
+    match statement {
+        Statement::Query(query) => {
+            walk_query(walker, query);
+        }
+        // INSERT, CREATE TABLE AS, CREATE VIEW は、元になる SELECT をプレビューにする
+        Statement::Insert(Insert {
+            source: Some(query),
+            ..
+        })
+        | Statement::CreateTable(CreateTable {
+            query: Some(query), ..
+        })
+        | Statement::CreateView { query, .. } => {
+            walker.preview_of = statement_kind(statement);
+            if let Statement::Insert(_) = statement {
+                walker.stops = INSERT_SOURCE_STOPS;
+            }
+            walk_query(walker, query);
+        }
+        // UPDATE, DELETE, MERGE は、対象行を選ぶ SELECT をプレビューにする
+        // 位置は文全体にする
+        Statement::Update { .. } | Statement::Delete(_) | Statement::Merge { .. } => {
+            let Some(preview) = preview_select(statement) else {
+                return;
+            };
+            walker.preview_of = statement_kind(statement);
+            let id = walker.push_node(preview, walker.statement);
+            let parent_id = walker.parent_id.replace(id);
+            walk_dml(walker, statement);
+            walker.parent_id = parent_id;
+        }
+        _ => {}
+    }
+}
+
+fn statement_kind(statement: &Statement) -> Option<&'static str> {
+    match statement {
+        Statement::Insert(_) => Some("INSERT"),
+        Statement::CreateTable(_) => Some("CREATE TABLE"),
+        Statement::CreateView { .. } => Some("CREATE VIEW"),
+        Statement::Update { .. } => Some("UPDATE"),
+        Statement::Delete(_) => Some("DELETE"),
+        Statement::Merge { .. } => Some("MERGE"),
+        _ => None,
+    }
+}
+
+// 文が対象にする行を選ぶ SELECT
+// INSERT などは元になる SELECT、MERGE は USING の各行と一致する対象の行
+fn preview_select(statement: &Statement) -> Option<String> {
+    let join = |tables: &[&TableWithJoins]| {
+        tables
+            .iter()
+            .map(|table| table.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    let filter = |selection: &Option<Expr>| match selection {
+        Some(selection) => format!(" WHERE {}", selection),
+        None => String::new(),
+    };
+
+    match statement {
+        Statement::Insert(Insert {
+            source: Some(query),
+            ..
+        })
+        | Statement::CreateTable(CreateTable {
+            query: Some(query), ..
+        })
+        | Statement::CreateView { query, .. } => Some(query.to_string()),
+        Statement::Update {
+            table,
+            from,
+            selection,
+            ..
+        } => {
+            let tables: Vec<&TableWithJoins> = std::iter::once(table).chain(from).collect();
+            Some(format!(
+                "SELECT * FROM {}{}",
+                join(&tables),
+                filter(selection)
+            ))
+        }
+        Statement::Delete(delete) => {
+            let (FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from)) = &delete.from;
+            let tables: Vec<&TableWithJoins> =
+                from.iter().chain(delete.using.iter().flatten()).collect();
+            // DELETE t1 FROM t1 JOIN t2 のように削除するテーブルを指定した場合は、そのテーブルの行だけ
+            let projection = match delete.tables.is_empty() {
+                true => String::from("*"),
+                false => delete
+                    .tables
+                    .iter()
+                    .map(|table| format!("{}.*", table))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            };
+            let mut preview = format!(
+                "SELECT {} FROM {}{}",
+                projection,
+                join(&tables),
+                filter(&delete.selection)
+            );
+            if !delete.order_by.is_empty() {
+                let order_by: Vec<String> = delete.order_by.iter().map(|e| e.to_string()).collect();
+                preview.push_str(&format!(" ORDER BY {}", order_by.join(", ")));
+            }
+            if let Some(limit) = &delete.limit {
+                preview.push_str(&format!(" LIMIT {}", limit));
+            }
+            Some(preview)
+        }
+        Statement::Merge {
+            table, source, on, ..
+        } => Some(format!(
+            "SELECT * FROM {} LEFT JOIN {} ON {}",
+            source, table, on
+        )),
+        _ => None,
+    }
+}
+
+// INSERT や UPDATE などの中の SELECT を集める
+// 文自体のノードは呼び出し元で追加し、parent_id にしておく
+fn walk_dml(walker: &mut Walker, statement: &Statement) {
+    let clause = walker.clause;
+
+    match statement {
+        Statement::Insert(Insert {
+            source: Some(query),
+            ..
+        })
+        | Statement::CreateTable(CreateTable {
+            query: Some(query), ..
+        })
+        | Statement::CreateView { query, .. } => {
+            walker.clause = ClauseKind::Source;
+            walk_query(walker, query);
+        }
+        Statement::Update {
+            table,
+            assignments,
+            from,
+            selection,
+            ..
+        } => {
+            let tables: Vec<TableWithJoins> = std::iter::once(table).chain(from).cloned().collect();
+            push_table_scope(walker, table_aliases(&tables));
+
+            walker.clause = ClauseKind::From;
+            for table in &tables {
+                walk_table_with_joins(walker, table);
+            }
+
+            walker.clause = ClauseKind::Set;
+            for assignment in assignments {
+                walk_expr(walker, &assignment.value);
+            }
+
+            walker.clause = ClauseKind::Where;
+            if let Some(selection) = selection {
+                walk_expr(walker, selection);
+            }
+
+            walker.table_scopes.pop();
+        }
+        Statement::Delete(delete) => {
+            let (FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from)) = &delete.from;
+            let tables: Vec<TableWithJoins> = from
+                .iter()
+                .chain(delete.using.iter().flatten())
+                .cloned()
+                .collect();
+            push_table_scope(walker, table_aliases(&tables));
+
+            walker.clause = ClauseKind::From;
+            for table in &tables {
+                walk_table_with_joins(walker, table);
             }
-            return select_statement;
+
+            walker.clause = ClauseKind::Where;
+            if let Some(selection) = &delete.selection {
+                walk_expr(walker, selection);
+            }
+
+            walker.clause = ClauseKind::OrderBy;
+            for order_by_expr in &delete.order_by {
+                walk_order_by_expr(walker, order_by_expr);
+            }
+
+            walker.clause = ClauseKind::Limit;
+            if let Some(limit) = &delete.limit {
+                walk_expr(walker, limit);
+            }
+
+            walker.table_scopes.pop();
         }
-        _ => {
-            return (vec![], vec![]);
+        Statement::Merge {
+            table,
+            source,
+            on,
+            clauses,
+            ..
+        } => {
+            let mut aliases = relation_aliases(table);
+            aliases.extend(relation_aliases(source));
+            push_table_scope(walker, aliases);
+
+            walker.clause = ClauseKind::From;
+            walk_table_factor(walker, table);
+
+            walker.clause = ClauseKind::Source;
+            walk_table_factor(walker, source);
+
+            walker.clause = ClauseKind::Join;
+            walk_expr(walker, on);
+
+            walker.clause = ClauseKind::Other;
+            for merge_clause in clauses {
+                if let Some(predicate) = &merge_clause.predicate {
+                    walk_expr(walker, predicate);
+                }
+                match &merge_clause.action {
+                    MergeAction::Update { assignments } => {
+                        for assignment in assignments {
+                            walk_expr(walker, &assignment.value);
+                        }
+                    }
+                    MergeAction::Insert(insert) => {
+                        if let MergeInsertKind::Values(values) = &insert.kind {
+                            for expr in values.rows.iter().flatten() {
+                                walk_expr(walker, expr);
+                            }
+                        }
+                    }
+                    MergeAction::Delete => {}
+                }
+            }
+
+            walker.table_scopes.pop();
         }
+        _ => {}
     }
+
+    walker.clause = clause;
+}
+
+// 今いるノードで見えるテーブルの別名を積む。取り除くのは呼び出し元で行う
+fn push_table_scope(walker: &mut Walker, aliases: Vec<Ident>) {
+    let owner_id = walker.parent_id.unwrap_or_default();
+    walker.table_scopes.push((owner_id, aliases));
 }
 
-fn walk_with_fill(with_fill: &WithFill) -> (Vec<String>, Vec<String>) {
-    let mut select_statement = (vec![], vec![]);
+fn walk_distinct(walker: &mut Walker, distinct: &Distinct) {
+    if let Distinct::On(vecexpr) = distinct {
+        for expr in vecexpr {
+            walk_expr(walker, expr);
+        }
+    }
+}
+
+fn walk_quantity(walker: &mut Walker, quantity: &TopQuantity) {
+    if let TopQuantity::Expr(expr) = quantity {
+        walk_expr(walker, expr);
+    }
+}
+
+fn walk_top(walker: &mut Walker, top: &Top) {
+    if let Some(quantity) = &top.quantity {
+        walk_quantity(walker, quantity);
+    };
+}
+
+fn walk_select_item(walker: &mut Walker, select_item: &SelectItem) {
+    match select_item {
+        SelectItem::UnnamedExpr(expr) => {
+            walk_expr(walker, expr);
+        }
+        SelectItem::ExprWithAlias { expr, .. } => {
+            walk_expr(walker, expr);
+        }
+        _ => {}
+    }
+}
+
+fn walk_lateral_view(walker: &mut Walker, lateral_view: &LateralView) {
+    walk_expr(walker, &lateral_view.lateral_view);
+}
+
+fn walk_group_by_expr(walker: &mut Walker, group_by_expr: &GroupByExpr) {
+    if let GroupByExpr::Expressions(vecexpr, _) = group_by_expr {
+        for expr in vecexpr {
+            walk_expr(walker, expr);
+        }
+    }
+}
 
+fn walk_with_fill(walker: &mut Walker, with_fill: &WithFill) {
     if let Some(expr) = &with_fill.from {
-        let (w, s) = walk_expr(&expr);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, expr);
     };
 
     if let Some(expr) = &with_fill.to {
-        let (w, s) = walk_expr(&expr);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, expr);
     };
 
     if let Some(expr) = &with_fill.step {
-        let (w, s) = walk_expr(&expr);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, expr);
     };
-
-    select_statement
 }
 
-fn walk_order_by_expr(order_by_expr: &OrderByExpr) -> (Vec<String>, Vec<String>) {
-    let mut select_statement = (vec![], vec![]);
-
-    let (w, s) = walk_expr(&order_by_expr.expr);
-    select_statement.0.extend(w);
-    select_statement.1.extend(s);
+fn walk_order_by_expr(walker: &mut Walker, order_by_expr: &OrderByExpr) {
+    walk_expr(walker, &order_by_expr.expr);
 
     if let Some(with_fill) = &order_by_expr.with_fill {
-        let (w, s) = walk_with_fill(&with_fill);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_with_fill(walker, with_fill);
     };
-
-    select_statement
 }
 
-fn walk_window_spec(window_spec: &WindowSpec) -> (Vec<String>, Vec<String>) {
-    let mut select_statement = (vec![], vec![]);
-
+fn walk_window_spec(walker: &mut Walker, window_spec: &WindowSpec) {
     for expr in &window_spec.partition_by {
-        let (w, s) = walk_expr(expr);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, expr);
     }
 
     for order_by_expr in &window_spec.order_by {
-        let (w, s) = walk_order_by_expr(order_by_expr);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_order_by_expr(walker, order_by_expr);
     }
-
-    select_statement
 }
 
-fn walk_named_window_expr(named_window_expr: &NamedWindowExpr) -> (Vec<String>, Vec<String>) {
-    match named_window_expr {
-        NamedWindowExpr::WindowSpec(window_spec) => {
-            return walk_window_spec(window_spec);
-        }
-        _ => {
-            return (vec![], vec![]);
-        }
+fn walk_named_window_expr(walker: &mut Walker, named_window_expr: &NamedWindowExpr) {
+    if let NamedWindowExpr::WindowSpec(window_spec) = named_window_expr {
+        walk_window_spec(walker, window_spec);
     }
 }
 
 fn walk_named_window_definition(
+    walker: &mut Walker,
     named_window_definition: &NamedWindowDefinition,
-) -> (Vec<String>, Vec<String>) {
-    return walk_named_window_expr(&named_window_definition.1);
+) {
+    walk_named_window_expr(walker, &named_window_definition.1);
 }
 
-fn walk_connect_by(connect_by: &ConnectBy) -> (Vec<String>, Vec<String>) {
-    let mut select_statement = (vec![], vec![]);
-
-    let (w, s) = walk_expr(&connect_by.condition);
-    select_statement.0.extend(w);
-    select_statement.1.extend(s);
+fn walk_connect_by(walker: &mut Walker, connect_by: &ConnectBy) {
+    walk_expr(walker, &connect_by.condition);
 
     for relationship in &connect_by.relationships {
-        let (w, s) = walk_expr(&relationship);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, relationship);
     }
-
-    select_statement
 }
 
-fn walk_select(select: &Select) -> (Vec<String>, Vec<String>) {
-    //println!("Select: {:?}", select);
-    //println!("Select: {:?}", select.to_string());
-    let mut select_statement = (vec![], vec![select.to_string()]);
+fn walk_select(walker: &mut Walker, select: &Select) {
+    let clause = walker.clause;
 
+    walker.clause = ClauseKind::Other;
     if let Some(distinct) = &select.distinct {
-        let (w, s) = walk_distinct(&distinct);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_distinct(walker, distinct);
     };
 
     if let Some(top) = &select.top {
-        let (w, s) = walk_top(&top);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_top(walker, top);
     };
 
+    walker.clause = ClauseKind::SelectList;
     for select_item in &select.projection {
-        let (w, s) = walk_select_item(&select_item);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_select_item(walker, select_item);
     }
 
+    walker.clause = ClauseKind::From;
     for table_with_join in &select.from {
-        let (w, s) = walk_table_with_joins(table_with_join);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_table_with_joins(walker, table_with_join);
     }
 
+    walker.clause = ClauseKind::Other;
     for lateral_view in &select.lateral_views {
-        let (w, s) = walk_lateral_view(&lateral_view);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_lateral_view(walker, lateral_view);
     }
 
     if let Some(prewhere) = &select.prewhere {
-        let (w, s) = walk_expr(&prewhere);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, prewhere);
     };
 
+    walker.clause = ClauseKind::Where;
     if let Some(selection) = &select.selection {
-        let (w, s) = walk_expr(&selection);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, selection);
     };
 
-    let (w, s) = walk_group_by_expr(&select.group_by);
-    select_statement.0.extend(w);
-    select_statement.1.extend(s);
+    walker.clause = ClauseKind::GroupBy;
+    walk_group_by_expr(walker, &select.group_by);
 
+    walker.clause = ClauseKind::Other;
     for expr in &select.cluster_by {
-        let (w, s) = walk_expr(&expr);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, expr);
     }
 
     for expr in &select.distribute_by {
-        let (w, s) = walk_expr(&expr);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, expr);
     }
 
     for expr in &select.sort_by {
-        let (w, s) = walk_expr(&expr);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, expr);
     }
 
+    walker.clause = ClauseKind::Having;
     if let Some(having) = &select.having {
-        let (w, s) = walk_expr(&having);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, having);
     };
 
+    walker.clause = ClauseKind::Other;
     for named_window_definition in &select.named_window {
-        let (w, s) = walk_named_window_definition(&named_window_definition);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_named_window_definition(walker, named_window_definition);
     }
 
     if let Some(expr) = &select.qualify {
-        let (w, s) = walk_expr(&expr);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_expr(walker, expr);
     };
 
     if let Some(connect_by) = &select.connect_by {
-        let (w, s) = walk_connect_by(&connect_by);
-        select_statement.0.extend(w);
-        select_statement.1.extend(s);
+        walk_connect_by(walker, connect_by);
     };
 
-    return select_statement;
+    walker.clause = clause;
 }
 
-fn walk_setexpr(setexpr: &SetExpr) -> (Vec<String>, Vec<String>) {
+fn walk_setexpr(walker: &mut Walker, setexpr: &SetExpr) {
     match setexpr {
+        // parent_id は SELECT を持つノード
         SetExpr::Select(select) => {
-            return walk_select(select);
+            push_table_scope(walker, table_aliases(&select.from));
+            walk_select(walker, select);
+            walker.table_scopes.pop();
         }
         SetExpr::Query(query) => {
-            return walk_query(query);
+            walk_query(walker, query);
         }
         SetExpr::SetOperation { left, right, .. } => {
-            let mut select_statement = (vec![], vec![]);
-            let (with, select) = walk_setexpr(left);
-            select_statement.0.extend(with);
-            select_statement.1.extend(select);
-
-            let (with, select) = walk_setexpr(right);
-            select_statement.0.extend(with);
-            select_statement.1.extend(select);
+            let clause = walker.clause;
+            walker.clause = ClauseKind::SetOperation;
+
+            for setexpr in [left, right] {
+                match setexpr.as_ref() {
+                    // UNION などの各 SELECT や入れ子の集合演算も、それぞれ単独で実行できるようにする
+                    SetExpr::Select(_)
+                    | SetExpr::SetOperation { .. }
+                    | SetExpr::Values(_)
+                    | SetExpr::Table(_) => {
+                        walker.stops = SET_OPERATION_STOPS;
+                        let id = walker.push_fragment(setexpr.as_ref());
+                        walker.nodes[id].set_operation = set_operation(setexpr);
+                        let parent_id = walker.parent_id.replace(id);
+                        walk_setexpr(walker, setexpr);
+                        walker.parent_id = parent_id;
+                    }
+                    _ => walk_setexpr(walker, setexpr),
+                }
+            }
 
-            return select_statement;
+            walker.clause = clause;
         }
-        _ => {
-            return (vec![], vec![]);
+        SetExpr::Values(values) => {
+            for expr in values.rows.iter().flatten() {
+                walk_expr(walker, expr);
+            }
+        }
+        SetExpr::Table(table) => {
+            // TABLE t の t が CTE のこともある
+            if let (Some(table_name), None) = (&table.table_name, &table.schema_name) {
+                walker.use_table(&ObjectName(vec![Ident::new(table_name)]));
+            }
+        }
+        // WITH ... INSERT INTO ... のような文。プレビューのノードは walk_query で追加している
+        SetExpr::Insert(statement) | SetExpr::Update(statement) => {
+            walk_dml(walker, statement);
         }
     }
 }
 
-fn walk_cte(cte: &Cte) -> (Vec<String>, Vec<String>) {
-    return walk_query(&cte.query);
+fn set_operation(setexpr: &SetExpr) -> Option<SetOperation> {
+    match setexpr {
+        SetExpr::SetOperation {
+            op, set_quantifier, ..
+        } => Some(SetOperation {
+            operator: op.to_string(),
+            quantifier: match set_quantifier {
+                SetQuantifier::None => None,
+                quantifier => Some(quantifier.to_string()),
+            },
+        }),
+        _ => None,
+    }
 }
 
-fn walk_with(with: &With) -> (Vec<String>, Vec<String>) {
-    let mut select_statements = (vec![with.to_string()], vec![]);
+fn walk_cte(walker: &mut Walker, cte: &Cte) {
+    walker.alias = Some(cte.alias.name.value.clone());
+    walk_query(walker, &cte.query);
+}
+
+fn walk_with(walker: &mut Walker, with: &With) {
+    // walk_query から呼ばれるので、parent_id は WITH 句を持つクエリ
+    let Some(owner_id) = walker.parent_id else {
+        return;
+    };
 
     for cte in &with.cte_tables {
-        let (with, select) = walk_cte(&cte);
-        select_statements.0.extend(with);
-        select_statements.1.extend(select);
+        // CTE の本体は次に追加されるノードになる
+        let cte_id = walker.nodes.len();
+        walker.ctes.insert(cte_id, (cte.clone(), with.recursive));
+        let entry = (cte.alias.name.clone(), cte_id, owner_id);
+
+        // RECURSIVE なら CTE の中から自身を参照できる
+        if with.recursive {
+            push_cte_scope(walker, entry.clone());
+            walk_cte(walker, cte);
+        } else {
+            walk_cte(walker, cte);
+            push_cte_scope(walker, entry);
+        }
     }
+}
 
-    select_statements
+fn push_cte_scope(walker: &mut Walker, entry: (Ident, usize, usize)) {
+    if let Some(scope) = walker.cte_scopes.last_mut() {
+        scope.push(entry);
+    }
 }
 
-fn walk_interpolate_expr(interpolate_expr: &InterpolateExpr) -> (Vec<String>, Vec<String>) {
+fn walk_interpolate_expr(walker: &mut Walker, interpolate_expr: &InterpolateExpr) {
     if let Some(expr) = &interpolate_expr.expr {
-        return walk_expr(&expr);
-    } else {
-        return (vec![], vec![]);
+        walk_expr(walker, expr);
     }
 }
 
-fn walk_interpolate(interpolate: &Interpolate) -> (Vec<String>, Vec<String>) {
-    let mut select_statements = (vec![], vec![]);
-
+fn walk_interpolate(walker: &mut Walker, interpolate: &Interpolate) {
     if let Some(exprs) = &interpolate.exprs {
         for expr in exprs {
-            let (w, s) = walk_interpolate_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_interpolate_expr(walker, expr);
         }
     }
-
-    select_statements
 }
 
-fn walk_order_by(order_by: &OrderBy) -> (Vec<String>, Vec<String>) {
-    let mut select_statements = (vec![], vec![]);
-
+fn walk_order_by(walker: &mut Walker, order_by: &OrderBy) {
     for expr in &order_by.exprs {
-        let (w, s) = walk_order_by_expr(expr);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_order_by_expr(walker, expr);
     }
 
     if let Some(interpolate) = &order_by.interpolate {
-        let (w, s) = walk_interpolate(&interpolate);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_interpolate(walker, interpolate);
     }
-
-    select_statements
 }
 
-fn walk_offset(offset: &Offset) -> (Vec<String>, Vec<String>) {
-    return walk_expr(&offset.value);
+fn walk_offset(walker: &mut Walker, offset: &Offset) {
+    walk_expr(walker, &offset.value);
 }
 
-fn walk_fetch(fetch: &Fetch) -> (Vec<String>, Vec<String>) {
+fn walk_fetch(walker: &mut Walker, fetch: &Fetch) {
     if let Some(expr) = &fetch.quantity {
-        return walk_expr(&expr);
-    } else {
-        return (vec![], vec![]);
+        walk_expr(walker, expr);
     }
 }
 
-fn walk_query(query: &Query) -> (Vec<String>, Vec<String>) {
-    let mut select_statements = (vec![], vec![]);
+fn walk_query(walker: &mut Walker, query: &Query) {
+    // WITH ... UPDATE ... のような文は、実行せずに対象行のプレビューにする
+    // 位置は文全体にする
+    let id = match query.body.as_ref() {
+        SetExpr::Insert(statement) | SetExpr::Update(statement) => {
+            let preview = preview_select(statement).unwrap_or_default();
+            walker.preview_of = statement_kind(statement);
+            let sql = match &query.with {
+                Some(with) => format!("{} {}", with, preview),
+                None => preview,
+            };
+            let range = walker.statement.filter(|_| walker.parent_id.is_none());
+            walker.push_node(sql, range)
+        }
+        _ => walker.push_fragment(query),
+    };
+    walker.nodes[id].set_operation = set_operation(&query.body);
+    let parent_id = walker.parent_id.replace(id);
+    let clause = walker.clause;
 
+    walker.clause = ClauseKind::Cte;
     if let Some(with) = &query.with {
-        let (w, s) = walk_with(&with);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walker.cte_scopes.push(vec![]);
+        walk_with(walker, with);
     }
 
-    let (w, s) = walk_setexpr(&query.body);
-    select_statements.0.extend(w);
-    select_statements.1.extend(s);
+    // ORDER BY や LIMIT からも SELECT のテーブルを参照できるので、別名はクエリの終わりまで見せる
+    walker.clause = ClauseKind::Other;
+    let scoped = match query.body.as_ref() {
+        SetExpr::Select(select) => {
+            push_table_scope(walker, table_aliases(&select.from));
+            walk_select(walker, select);
+            true
+        }
+        body => {
+            walk_setexpr(walker, body);
+            false
+        }
+    };
 
+    walker.clause = ClauseKind::OrderBy;
     if let Some(order_by) = &query.order_by {
-        let (w, s) = walk_order_by(&order_by);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_order_by(walker, order_by);
     }
 
+    walker.clause = ClauseKind::Limit;
     if let Some(limit) = &query.limit {
-        let (w, s) = walk_expr(&limit);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_expr(walker, limit);
     }
 
     for limit_by_elem in &query.limit_by {
-        let (w, s) = walk_expr(&limit_by_elem);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_expr(walker, limit_by_elem);
     }
 
     if let Some(offset) = &query.offset {
-        let (w, s) = walk_offset(&offset);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_offset(walker, offset);
     }
 
     if let Some(fetch) = &query.fetch {
-        let (w, s) = walk_fetch(&fetch);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_fetch(walker, fetch);
     }
 
-    select_statements
+    if scoped {
+        walker.table_scopes.pop();
+    }
+
+    if query.with.is_some() {
+        walker.cte_scopes.pop();
+    }
+
+    walker.parent_id = parent_id;
+    walker.clause = clause;
 }
 
-fn walk_list_agg_on_overflow(list_agg_on_overflow: &ListAggOnOverflow) -> (Vec<String>, Vec<String>) {
-    match list_agg_on_overflow {
-        ListAggOnOverflow::Truncate { filler, .. } => {
-            if let Some(filler) = &filler {
-                return walk_expr(&filler);
-            } else {
-                return (vec![], vec![]);
-            }
-        }
-        _ => {
-            return (vec![], vec![]);
-        }
+fn walk_list_agg_on_overflow(walker: &mut Walker, list_agg_on_overflow: &ListAggOnOverflow) {
+    if let ListAggOnOverflow::Truncate {
+        filler: Some(filler),
+        ..
+    } = list_agg_on_overflow
+    {
+        walk_expr(walker, filler);
     }
 }
 
-fn walk_having_bound(having_bound: &HavingBound) -> (Vec<String>, Vec<String>) {
-    return walk_expr(&having_bound.1);
+fn walk_having_bound(walker: &mut Walker, having_bound: &HavingBound) {
+    walk_expr(walker, &having_bound.1);
 }
 
-fn walk_function_argument_clause(function_argument_clause: &FunctionArgumentClause) -> (Vec<String>, Vec<String>) {
+fn walk_function_argument_clause(
+    walker: &mut Walker,
+    function_argument_clause: &FunctionArgumentClause,
+) {
     match function_argument_clause {
         FunctionArgumentClause::OrderBy(order_by) => {
-            let mut select_statements = (vec![], vec![]);
-
             for order_by_expr in order_by {
-                let (w, s) = walk_order_by_expr(&order_by_expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_order_by_expr(walker, order_by_expr);
             }
-
-            select_statements
         }
         FunctionArgumentClause::Limit(limit) => {
-            return walk_expr(&limit);
+            walk_expr(walker, limit);
         }
         FunctionArgumentClause::OnOverflow(overflow) => {
-            return walk_list_agg_on_overflow(&overflow);
+            walk_list_agg_on_overflow(walker, overflow);
         }
         FunctionArgumentClause::Having(having) => {
-            return walk_having_bound(having);
-        }
-        _ => {
-            return (vec![], vec![]);
+            walk_having_bound(walker, having);
         }
+        _ => {}
     }
 }
 
-fn walk_function_argument_list(function_argument_list: &FunctionArgumentList) -> (Vec<String>, Vec<String>) {
-    let mut select_statements = (vec![], vec![]);
+fn walk_function_argument_list(walker: &mut Walker, function_argument_list: &FunctionArgumentList) {
     for function_arg in &function_argument_list.args {
-        let (w, s) = walk_function_arg(&function_arg);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_function_arg(walker, function_arg);
     }
 
     for function_argument_clause in &function_argument_list.clauses {
-        let (w, s) = walk_function_argument_clause(&function_argument_clause);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_function_argument_clause(walker, function_argument_clause);
     }
-
-    select_statements
 }
 
-fn walk_function_arguments(function_arguments: &FunctionArguments) -> (Vec<String>, Vec<String>) {
+fn walk_function_arguments(walker: &mut Walker, function_arguments: &FunctionArguments) {
     match function_arguments {
         FunctionArguments::Subquery(subquery) => {
-            return walk_query(&subquery);
+            walk_query(walker, subquery);
         }
         FunctionArguments::List(list) => {
-            return walk_function_argument_list(&list);
-        }
-        _ => {
-            return (vec![], vec![]);
+            walk_function_argument_list(walker, list);
         }
+        _ => {}
     }
 }
 
-fn walk_window_type(window_type: &WindowType) -> (Vec<String>, Vec<String>) {
-    match window_type {
-        WindowType::WindowSpec(window_spec) => {
-            return walk_window_spec(&window_spec);
-        }
-        _ => {
-            return (vec![], vec![]);
-        }
+fn walk_window_type(walker: &mut Walker, window_type: &WindowType) {
+    if let WindowType::WindowSpec(window_spec) = window_type {
+        walk_window_spec(walker, window_spec);
     }
 }
 
-fn walk_function(function: &Function) -> (Vec<String>, Vec<String>) {
-    let mut select_statements = (vec![], vec![]);
+fn walk_function(walker: &mut Walker, function: &Function) {
+    walk_function_arguments(walker, &function.parameters);
 
-    let (w, s) = walk_function_arguments(&function.parameters);
-    select_statements.0.extend(w);
-    select_statements.1.extend(s);
-
-    let (w, s) = walk_function_arguments(&function.args);
-    select_statements.0.extend(w);
-    select_statements.1.extend(s);
+    walk_function_arguments(walker, &function.args);
 
     if let Some(filter) = &function.filter {
-        let (w, s) = walk_expr(&filter);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_expr(walker, filter);
     };
 
     if let Some(window_type) = &function.over {
-        let (w, s) = walk_window_type(&window_type);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_window_type(walker, window_type);
     };
 
     for order_by_expr in &function.within_group {
-        let (w, s) = walk_order_by_expr(&order_by_expr);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_order_by_expr(walker, order_by_expr);
     }
-
-    select_statements
 }
 
-fn walk_dictionary_field(dictionary_field: &DictionaryField) -> (Vec<String>, Vec<String>) {
-    return walk_expr(&dictionary_field.value);
+fn walk_dictionary_field(walker: &mut Walker, dictionary_field: &DictionaryField) {
+    walk_expr(walker, &dictionary_field.value);
 }
 
-fn walk_map_entry(map_entry: &MapEntry) -> (Vec<String>, Vec<String>) {
-    let mut select_statements = (vec![], vec![]);
-
-    let (w, s) = walk_expr(&map_entry.key);
-    select_statements.0.extend(w);
-    select_statements.1.extend(s);
+fn walk_map_entry(walker: &mut Walker, map_entry: &MapEntry) {
+    walk_expr(walker, &map_entry.key);
 
-    let (w, s) = walk_expr(&map_entry.value);
-    select_statements.0.extend(w);
-    select_statements.1.extend(s);
-
-    select_statements
+    walk_expr(walker, &map_entry.value);
 }
 
-fn walk_map(map: &Map) -> (Vec<String>, Vec<String>) {
-    let mut select_statements = (vec![], vec![]);
-
+fn walk_map(walker: &mut Walker, map: &Map) {
     for entry in &map.entries {
-        let (w, s) = walk_map_entry(&entry);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_map_entry(walker, entry);
     }
-
-    select_statements
 }
 
-fn walk_array(array: &Array) -> (Vec<String>, Vec<String>) {
-    let mut select_statements = (vec![], vec![]);
-
+fn walk_array(walker: &mut Walker, array: &Array) {
     for e in &array.elem {
-        let (w, s) = walk_expr(&e);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_expr(walker, e);
     }
-
-    select_statements
 }
 
-fn walk_interval(interval: &Interval) -> (Vec<String>, Vec<String>) {
-    return walk_expr(&interval.value);
+fn walk_interval(walker: &mut Walker, interval: &Interval) {
+    walk_expr(walker, &interval.value);
 }
 
-fn walk_lambda_function(lambda_function: &LambdaFunction) -> (Vec<String>, Vec<String>) {
-    return walk_expr(&lambda_function.body);
+fn walk_lambda_function(walker: &mut Walker, lambda_function: &LambdaFunction) {
+    walk_expr(walker, &lambda_function.body);
 }
 
-fn walk_expr(expr: &Expr) -> (Vec<String>, Vec<String>) {
-    //println!("{:?}", expr);
+fn walk_expr(walker: &mut Walker, expr: &Expr) {
     match &expr {
         Expr::JsonAccess { value, .. } => {
-            return walk_expr(&value);
+            walk_expr(walker, value);
         }
         Expr::CompositeAccess { expr, .. } => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::IsFalse(expr) => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::IsNotFalse(expr) => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::IsTrue(expr) => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::IsNotTrue(expr) => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::IsNull(expr) => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::IsNotNull(expr) => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::IsUnknown(expr) => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::IsNotUnknown(expr) => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::IsDistinctFrom(expr1, expr2) => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr1);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr1);
 
-            let (w, s) = walk_expr(&expr2);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            select_statements
+            walk_expr(walker, expr2);
         }
         Expr::IsNotDistinctFrom(expr1, expr2) => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr1);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            let (w, s) = walk_expr(&expr2);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr1);
 
-            select_statements
+            walk_expr(walker, expr2);
         }
         Expr::InList { expr, list, .. } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
             for expr in list {
-                let (w, s) = walk_expr(&expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, expr);
             }
-
-            select_statements
         }
         Expr::InSubquery { expr, subquery, .. } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
-            let (w, s) = walk_query(&subquery);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            select_statements
+            walk_query(walker, subquery);
         }
         Expr::InUnnest {
             expr, array_expr, ..
         } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
-            let (w, s) = walk_expr(&array_expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            select_statements
+            walk_expr(walker, array_expr);
         }
         Expr::Between {
             expr, low, high, ..
         } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            let (w, s) = walk_expr(&low);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
-            let (w, s) = walk_expr(&high);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, low);
 
-            select_statements
+            walk_expr(walker, high);
         }
         Expr::BinaryOp { left, right, .. } => {
-            let mut select_statements = (vec![], vec![]);
+            walk_expr(walker, left);
 
-            let (w, s) = walk_expr(&left);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            let (w, s) = walk_expr(&right);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            select_statements
+            walk_expr(walker, right);
         }
         Expr::Like { expr, pattern, .. } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
-            let (w, s) = walk_expr(&pattern);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            select_statements
+            walk_expr(walker, pattern);
         }
         Expr::ILike { expr, pattern, .. } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            let (w, s) = walk_expr(&pattern);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
-            select_statements
+            walk_expr(walker, pattern);
         }
         Expr::SimilarTo { expr, pattern, .. } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            let (w, s) = walk_expr(&pattern);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
-            select_statements
+            walk_expr(walker, pattern);
         }
         Expr::RLike { expr, pattern, .. } => {
-            let mut select_statements = (vec![], vec![]);
+            walk_expr(walker, expr);
 
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            let (w, s) = walk_expr(&pattern);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            select_statements
+            walk_expr(walker, pattern);
         }
         Expr::AnyOp { left, right, .. } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&left);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, left);
 
-            let (w, s) = walk_expr(&right);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            select_statements
+            walk_expr(walker, right);
         }
         Expr::AllOp { left, right, .. } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&left);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            let (w, s) = walk_expr(&right);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, left);
 
-            select_statements
+            walk_expr(walker, right);
         }
         Expr::UnaryOp { expr, .. } => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::Convert { expr, styles, .. } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
             for style in styles {
-                let (w, s) = walk_expr(&style);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, style);
             }
-
-            select_statements
         }
         Expr::Cast { expr, .. } => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::AtTimeZone {
             timestamp,
             time_zone,
             ..
-        } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&timestamp);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            let (w, s) = walk_expr(&time_zone);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+        } => {
+            walk_expr(walker, timestamp);
 
-            select_statements
+            walk_expr(walker, time_zone);
         }
         Expr::Extract { expr, .. } => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::Ceil { expr, .. } => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::Floor { expr, .. } => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::Position { expr, r#in, .. } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
-            let (w, s) = walk_expr(&r#in);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
-
-            select_statements
+            walk_expr(walker, r#in);
         }
         Expr::Substring {
             expr,
@@ -903,25 +1997,15 @@ fn walk_expr(expr: &Expr) -> (Vec<String>, Vec<String>) {
             substring_for,
             ..
         } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
             if let Some(substring_from) = &substring_from {
-                let (w, s) = walk_expr(&substring_from);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, substring_from);
             };
 
             if let Some(substring_for) = &substring_for {
-                let (w, s) = walk_expr(&substring_for);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, substring_for);
             };
-
-            select_statements
         }
         Expr::Trim {
             expr,
@@ -929,27 +2013,17 @@ fn walk_expr(expr: &Expr) -> (Vec<String>, Vec<String>) {
             trim_characters,
             ..
         } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
             if let Some(trim_what) = &trim_what {
-                let (w, s) = walk_expr(&trim_what);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, trim_what);
             };
 
             if let Some(trim_characters) = &trim_characters {
                 for trim_character in trim_characters {
-                    let (w, s) = walk_expr(&trim_character);
-                    select_statements.0.extend(w);
-                    select_statements.1.extend(s);
+                    walk_expr(walker, trim_character);
                 }
             };
-
-            select_statements
         }
         Expr::Overlay {
             expr,
@@ -957,39 +2031,27 @@ fn walk_expr(expr: &Expr) -> (Vec<String>, Vec<String>) {
             overlay_from,
             overlay_for,
         } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_expr(&expr);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, expr);
 
-            let (w, s) = walk_expr(&overlay_what);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, overlay_what);
 
-            let (w, s) = walk_expr(&overlay_from);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_expr(walker, overlay_from);
 
             if let Some(overlay_for) = &overlay_for {
-                let (w, s) = walk_expr(&overlay_for);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, overlay_for);
             };
-
-            select_statements
         }
         Expr::Collate { expr, .. } => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::Nested(expr) => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::MapAccess { column, .. } => {
-            return walk_expr(&column);
+            walk_expr(walker, column);
         }
         Expr::Function(function) => {
-            return walk_function(&function);
+            walk_function(walker, function);
         }
         Expr::Case {
             operand,
@@ -997,286 +2059,208 @@ fn walk_expr(expr: &Expr) -> (Vec<String>, Vec<String>) {
             results,
             else_result,
         } => {
-            let mut select_statements = (vec![], vec![]);
-
             if let Some(operand) = &operand {
-                let (w, s) = walk_expr(&operand);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, operand);
             }
 
             for expr in conditions {
-                let (w, s) = walk_expr(&expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, expr);
             }
 
             for expr in results {
-                let (w, s) = walk_expr(&expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, expr);
             }
 
             if let Some(else_result) = &else_result {
-                let (w, s) = walk_expr(&else_result);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, else_result);
             }
-
-            select_statements
         }
         Expr::Exists { subquery, .. } => {
-            return walk_query(&subquery);
+            walk_query(walker, subquery);
+        }
+        Expr::CompoundIdentifier(idents) => {
+            walker.use_column(expr, idents);
         }
         Expr::Subquery(subquery) => {
-            return walk_query(subquery);
+            walk_query(walker, subquery);
         }
         Expr::GroupingSets(grouping_sets) => {
-            let mut select_statements = (vec![], vec![]);
-
             for exprs in grouping_sets {
                 for expr in exprs {
-                    let (w, s) = walk_expr(&expr);
-                    select_statements.0.extend(w);
-                    select_statements.1.extend(s);
+                    walk_expr(walker, expr);
                 }
             }
-
-            select_statements
         }
         Expr::Cube(cube) => {
-            let mut select_statements = (vec![], vec![]);
-
             for exprs in cube {
                 for expr in exprs {
-                    let (w, s) = walk_expr(&expr);
-                    select_statements.0.extend(w);
-                    select_statements.1.extend(s);
+                    walk_expr(walker, expr);
                 }
             }
-
-            select_statements
         }
         Expr::Rollup(rollup) => {
-            let mut select_statements = (vec![], vec![]);
-
             for exprs in rollup {
                 for expr in exprs {
-                    let (w, s) = walk_expr(&expr);
-                    select_statements.0.extend(w);
-                    select_statements.1.extend(s);
+                    walk_expr(walker, expr);
                 }
             }
-
-            select_statements
         }
         Expr::Tuple(tuple) => {
-            let mut select_statements = (vec![], vec![]);
-
             for expr in tuple {
-                let (w, s) = walk_expr(&expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, expr);
             }
-
-            select_statements
         }
         Expr::Struct { values, .. } => {
-            let mut select_statements = (vec![], vec![]);
-
             for expr in values {
-                let (w, s) = walk_expr(&expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, expr);
             }
-
-            select_statements
         }
         Expr::Named { expr, .. } => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         Expr::Dictionary(vec_dictionary_field) => {
-            let mut select_statements = (vec![], vec![]);
-
             for dictionary_field in vec_dictionary_field {
-                let (w, s) = walk_dictionary_field(&dictionary_field);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_dictionary_field(walker, dictionary_field);
             }
-
-            select_statements
         }
         Expr::Map(map) => {
-            return walk_map(&map);
+            walk_map(walker, map);
         }
         Expr::Array(array) => {
-            return walk_array(&array);
+            walk_array(walker, array);
         }
         Expr::Interval(interval) => {
-            return walk_interval(&interval);
+            walk_interval(walker, interval);
         }
         Expr::OuterJoin(outer_join) => {
-            return walk_expr(&outer_join);
+            walk_expr(walker, outer_join);
         }
         Expr::Prior(prior) => {
-            return walk_expr(&prior);
+            walk_expr(walker, prior);
         }
         Expr::Lambda(lambda) => {
-            return walk_lambda_function(&lambda);
-        }
-        _ => {
-            return (vec![], vec![]);
+            walk_lambda_function(walker, lambda);
         }
+        _ => {}
     }
 }
 
-fn walk_function_arg_expr(function_arg_expr: &FunctionArgExpr) -> (Vec<String>, Vec<String>) {
-    match function_arg_expr {
-        FunctionArgExpr::Expr(expr) => {
-            return walk_expr(&expr);
-        }
-        _ => {
-            return (vec![], vec![]);
-        }
+fn walk_function_arg_expr(walker: &mut Walker, function_arg_expr: &FunctionArgExpr) {
+    if let FunctionArgExpr::Expr(expr) = function_arg_expr {
+        walk_expr(walker, expr);
     }
 }
 
-fn walk_function_arg(function_arg: &FunctionArg) -> (Vec<String>, Vec<String>) {
+fn walk_function_arg(walker: &mut Walker, function_arg: &FunctionArg) {
     match function_arg {
         FunctionArg::Named { arg, .. } => {
-            return walk_function_arg_expr(&arg);
+            walk_function_arg_expr(walker, arg);
+        }
+        FunctionArg::ExprNamed { arg, .. } => {
+            walk_function_arg_expr(walker, arg);
         }
         FunctionArg::Unnamed(function_arg_expr) => {
-            return walk_function_arg_expr(&function_arg_expr);
+            walk_function_arg_expr(walker, function_arg_expr);
         }
     }
 }
 
-fn walk_table_function_args(table_function_args: &TableFunctionArgs) -> (Vec<String>, Vec<String>) {
-    let mut select_statements = (vec![], vec![]);
-
+fn walk_table_function_args(walker: &mut Walker, table_function_args: &TableFunctionArgs) {
     for arg in &table_function_args.args {
-        let (w, s) = walk_function_arg(&arg);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+        walk_function_arg(walker, arg);
     }
-
-    select_statements
 }
 
-fn walk_table_version(table_version: &TableVersion) -> (Vec<String>, Vec<String>) {
+fn walk_table_version(walker: &mut Walker, table_version: &TableVersion) {
     match table_version {
         TableVersion::ForSystemTimeAsOf(expr) => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
     }
 }
 
-fn walk_expr_with_alias(expr_with_alias: &ExprWithAlias) -> (Vec<String>, Vec<String>) {
-    return walk_expr(&expr_with_alias.expr);
+fn walk_expr_with_alias(walker: &mut Walker, expr_with_alias: &ExprWithAlias) {
+    walk_expr(walker, &expr_with_alias.expr);
 }
 
-fn walk_pivot_value_source(pivot_value_source: &PivotValueSource) -> (Vec<String>, Vec<String>) {
+fn walk_pivot_value_source(walker: &mut Walker, pivot_value_source: &PivotValueSource) {
     match pivot_value_source {
         PivotValueSource::List(vecexpr) => {
-            let mut select_statements = (vec![], vec![]);
-
             for expr in vecexpr {
-                let (w, s) = walk_expr_with_alias(&expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr_with_alias(walker, expr);
             }
-
-            select_statements
         }
         PivotValueSource::Any(vecexpr) => {
-            let mut select_statements = (vec![], vec![]);
-
             for expr in vecexpr {
-                let (w, s) = walk_order_by_expr(&expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_order_by_expr(walker, expr);
             }
-
-            select_statements
         }
         PivotValueSource::Subquery(query) => {
-            return walk_query(&query);
+            walk_query(walker, query);
         }
     }
 }
 
-fn walk_measure(measure: &Measure) -> (Vec<String>, Vec<String>) {
-    return walk_expr(&measure.expr);
+fn walk_measure(walker: &mut Walker, measure: &Measure) {
+    walk_expr(walker, &measure.expr);
 }
 
-fn walk_symbol_definition(symbol_definition: &SymbolDefinition) -> (Vec<String>, Vec<String>) {
-    return walk_expr(&symbol_definition.definition);
+fn walk_symbol_definition(walker: &mut Walker, symbol_definition: &SymbolDefinition) {
+    walk_expr(walker, &symbol_definition.definition);
 }
 
-fn walk_table_factor(table_factor: &TableFactor) -> (Vec<String>, Vec<String>) {
+fn walk_table_factor(walker: &mut Walker, table_factor: &TableFactor) {
     match table_factor {
         TableFactor::Table {
+            name,
             args,
             with_hints,
             version,
             ..
         } => {
-            let mut select_statements = (vec![], vec![]);
+            walker.use_table(name);
 
             if let Some(args) = args {
-                let (w, s) = walk_table_function_args(args);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_table_function_args(walker, args);
             }
 
             for expr in with_hints {
-                let (w, s) = walk_expr(expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, expr);
             }
 
             if let Some(version) = version {
-                let (w, s) = walk_table_version(version);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_table_version(walker, version);
             }
-
-            select_statements
         }
-        TableFactor::Derived { subquery, .. } => {
-            return walk_query(subquery);
+        TableFactor::Derived {
+            subquery, alias, ..
+        } => {
+            walker.alias = alias.as_ref().map(|alias| alias.name.value.clone());
+            walk_query(walker, subquery);
         }
         TableFactor::TableFunction { expr, .. } => {
-            return walk_expr(&expr);
+            walk_expr(walker, expr);
         }
         TableFactor::Function { args, .. } => {
-            let mut select_statements = (vec![], vec![]);
-
             for function_arg in args {
-                let (w, s) = walk_function_arg(&function_arg);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_function_arg(walker, function_arg);
             }
-
-            select_statements
         }
         TableFactor::UNNEST { array_exprs, .. } => {
-            let mut select_statements = (vec![], vec![]);
             for expr in array_exprs {
-                let (w, s) = walk_expr(&expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, expr);
             }
-            select_statements
         }
         TableFactor::JsonTable { json_expr, .. } => {
-            return walk_expr(&json_expr);
+            walk_expr(walker, json_expr);
+        }
+        TableFactor::OpenJsonTable { json_expr, .. } => {
+            walk_expr(walker, json_expr);
         }
         TableFactor::NestedJoin {
             table_with_joins, ..
         } => {
-            return walk_table_with_joins(table_with_joins);
+            walk_table_with_joins(walker, table_with_joins);
         }
         TableFactor::Pivot {
             table,
@@ -1285,32 +2269,20 @@ fn walk_table_factor(table_factor: &TableFactor) -> (Vec<String>, Vec<String>) {
             default_on_null,
             ..
         } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_table_factor(&table);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_table_factor(walker, table);
 
             for expr_with_alias in aggregate_functions {
-                let (w, s) = walk_expr_with_alias(&expr_with_alias);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr_with_alias(walker, expr_with_alias);
             }
 
-            let (w, s) = walk_pivot_value_source(&value_source);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_pivot_value_source(walker, value_source);
 
             if let Some(expr) = default_on_null {
-                let (w, s) = walk_expr(expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, expr);
             }
-
-            select_statements
         }
         TableFactor::Unpivot { table, .. } => {
-            return walk_table_factor(&table);
+            walk_table_factor(walker, table);
         }
         TableFactor::MatchRecognize {
             table,
@@ -1320,123 +2292,1339 @@ fn walk_table_factor(table_factor: &TableFactor) -> (Vec<String>, Vec<String>) {
             symbols,
             ..
         } => {
-            let mut select_statements = (vec![], vec![]);
-
-            let (w, s) = walk_table_factor(&table);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+            walk_table_factor(walker, table);
 
             for expr in partition_by {
-                let (w, s) = walk_expr(&expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_expr(walker, expr);
             }
 
             for expr in order_by {
-                let (w, s) = walk_order_by_expr(&expr);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_order_by_expr(walker, expr);
             }
 
             for measure in measures {
-                let (w, s) = walk_measure(&measure);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_measure(walker, measure);
             }
 
             for symbol_definition in symbols {
-                let (w, s) = walk_symbol_definition(&symbol_definition);
-                select_statements.0.extend(w);
-                select_statements.1.extend(s);
+                walk_symbol_definition(walker, symbol_definition);
             }
-
-            select_statements
         }
     }
 }
 
-fn walk_join_operator(join_operator: &JoinOperator) -> (Vec<String>, Vec<String>) {
+fn walk_join_operator(walker: &mut Walker, join_operator: &JoinOperator) {
     match join_operator {
         JoinOperator::Inner(join_constraint) => {
-            return walk_join_constraint(join_constraint);
+            walk_join_constraint(walker, join_constraint);
         }
         JoinOperator::LeftOuter(join_constraint) => {
-            return walk_join_constraint(join_constraint);
+            walk_join_constraint(walker, join_constraint);
         }
         JoinOperator::RightOuter(join_constraint) => {
-            return walk_join_constraint(join_constraint);
+            walk_join_constraint(walker, join_constraint);
         }
         JoinOperator::FullOuter(join_constraint) => {
-            return walk_join_constraint(join_constraint);
+            walk_join_constraint(walker, join_constraint);
         }
         JoinOperator::LeftSemi(join_constraint) => {
-            return walk_join_constraint(join_constraint);
+            walk_join_constraint(walker, join_constraint);
         }
         JoinOperator::RightSemi(join_constraint) => {
-            return walk_join_constraint(join_constraint);
+            walk_join_constraint(walker, join_constraint);
         }
         JoinOperator::LeftAnti(join_constraint) => {
-            return walk_join_constraint(join_constraint);
+            walk_join_constraint(walker, join_constraint);
         }
         JoinOperator::RightAnti(join_constraint) => {
-            return walk_join_constraint(join_constraint);
+            walk_join_constraint(walker, join_constraint);
         }
         JoinOperator::AsOf {
             match_condition,
             constraint,
         } => {
-            let mut select_statements = (vec![], vec![]);
+            walk_expr(walker, match_condition);
+
+            walk_join_constraint(walker, constraint);
+        }
+        _ => {}
+    }
+}
+
+fn walk_join(walker: &mut Walker, join: &Join) {
+    walk_table_factor(walker, &join.relation);
 
-            let (w, s) = walk_expr(match_condition);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+    let clause = walker.clause;
+    walker.clause = ClauseKind::Join;
+    walk_join_operator(walker, &join.join_operator);
+    walker.clause = clause;
+}
+
+fn walk_join_constraint(walker: &mut Walker, join_constraint: &JoinConstraint) {
+    if let JoinConstraint::On(on) = join_constraint {
+        walk_expr(walker, on)
+    }
+}
+
+fn walk_table_with_joins(walker: &mut Walker, twjs: &TableWithJoins) {
+    walk_table_factor(walker, &twjs.relation);
+
+    for join in &twjs.joins {
+        walk_join(walker, join);
+    }
+}
+
+// パラメーターの位置に置く識別子の接頭辞。find_parameter_types に渡す SQL は、
+// パラメーターを __sql_divider_parameter_1 のような識別子に置き換えておく
+pub const PARAMETER_PREFIX: &str = "__sql_divider_parameter_";
 
-            let (w, s) = walk_join_constraint(constraint);
-            select_statements.0.extend(w);
-            select_statements.1.extend(s);
+// パラメーターの型を、比較相手のカラムや値から推測する
+// 戻り値のキーはパラメーターの番号(PARAMETER_PREFIX の後ろの数字)
+pub fn find_parameter_types(
+    dialect: &dyn Dialect,
+    sql: &str,
+) -> Result<HashMap<usize, String>, ParserError> {
+    let ast = Parser::parse_sql(dialect, sql)?;
+
+    let mut types = HashMap::new();
+    for statement in ast.iter() {
+        guess_statement(statement, &mut types);
+    }
+    Ok(types)
+}
 
-            select_statements
+// UPDATE の SET や INSERT の VALUES は、代入先のカラム名から推測する
+fn guess_statement(statement: &Statement, types: &mut HashMap<usize, String>) {
+    match statement {
+        Statement::Query(query) => guess_query(query, types),
+        Statement::Insert(Insert {
+            columns,
+            source: Some(source),
+            ..
+        }) => {
+            if let SetExpr::Values(values) = source.body.as_ref() {
+                for row in &values.rows {
+                    for (column, expr) in columns.iter().zip(row) {
+                        set_parameter_type(expr, column_type(&column.value), types);
+                    }
+                }
+            }
+            guess_query(source, types);
+        }
+        Statement::Update {
+            table,
+            assignments,
+            from,
+            selection,
+            ..
+        } => {
+            for assignment in assignments {
+                if let AssignmentTarget::ColumnName(name) = &assignment.target {
+                    let guessed_type = name.0.last().and_then(|i| column_type(&i.value));
+                    set_parameter_type(&assignment.value, guessed_type, types);
+                }
+                guess_expr(&assignment.value, types);
+            }
+            for table_with_joins in std::iter::once(table).chain(from) {
+                guess_table_with_joins(table_with_joins, types);
+            }
+            guess_selection(selection.as_ref(), types);
         }
-        _ => {
-            return (vec![], vec![]);
+        Statement::Delete(delete) => {
+            let (FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from)) = &delete.from;
+            for table_with_joins in from.iter().chain(delete.using.iter().flatten()) {
+                guess_table_with_joins(table_with_joins, types);
+            }
+            guess_selection(delete.selection.as_ref(), types);
         }
+        _ => {}
     }
 }
 
-fn walk_join(join: &Join) -> (Vec<String>, Vec<String>) {
-    let mut select_statements = (vec![], vec![]);
+// WHERE 句などの条件は、それ自体が真偽値
+fn guess_selection(selection: Option<&Expr>, types: &mut HashMap<usize, String>) {
+    if let Some(expr) = selection {
+        set_parameter_type(expr, Some(String::from("boolean")), types);
+        guess_expr(expr, types);
+    }
+}
 
-    let (w, s) = walk_table_factor(&join.relation);
-    select_statements.0.extend(w);
-    select_statements.1.extend(s);
+fn parameter_index(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Identifier(ident) => ident.value.strip_prefix(PARAMETER_PREFIX)?.parse().ok(),
+        Expr::Nested(expr) => parameter_index(expr),
+        _ => None,
+    }
+}
 
-    let (w, s) = walk_join_operator(&join.join_operator);
-    select_statements.0.extend(w);
-    select_statements.1.extend(s);
+// 同じパラメーターが何度も現れる場合は、先に推測できた型を使う
+fn set_parameter_type(
+    expr: &Expr,
+    guessed_type: Option<String>,
+    types: &mut HashMap<usize, String>,
+) {
+    if let (Some(index), Some(guessed_type)) = (parameter_index(expr), guessed_type) {
+        types.entry(index).or_insert(guessed_type);
+    }
+}
 
-    select_statements
+fn data_type_category(data_type: &DataType) -> String {
+    let data_type = data_type.to_string().to_lowercase();
+    let base = data_type
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or("");
+
+    let category = match base {
+        "int" | "int2" | "int4" | "int8" | "integer" | "smallint" | "tinyint" | "mediumint"
+        | "bigint" | "numeric" | "decimal" | "dec" | "float" | "float4" | "float8" | "real"
+        | "double" | "unsigned" | "signed" => "number",
+        "char" | "character" | "varchar" | "nchar" | "nvarchar" | "text" | "string" | "clob" => {
+            "text"
+        }
+        "bool" | "boolean" => "boolean",
+        "date" => "date",
+        "time" => "time",
+        "timestamp" | "timestamptz" | "datetime" => "timestamp",
+        _ => return data_type,
+    };
+    category.to_string()
 }
 
-fn walk_join_constraint(join_constraint: &JoinConstraint) -> (Vec<String>, Vec<String>) {
-    match join_constraint {
-        JoinConstraint::On(on) => walk_expr(&on),
-        _ => (vec![], vec![]),
+const NUMBER_COLUMN_WORDS: [&str; 12] = [
+    "id", "no", "num", "count", "amount", "price", "qty", "quantity", "age", "total", "size",
+    "score",
+];
+const TEXT_COLUMN_WORDS: [&str; 8] = [
+    "name",
+    "code",
+    "email",
+    "title",
+    "status",
+    "type",
+    "text",
+    "description",
+];
+
+// カラム名を _ と camelCase の区切りで小文字の単語に分ける(createdAt なら ["created", "at"])
+fn name_words(name: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut after_lower = false;
+    for c in name.chars() {
+        if (c == '_' || (c.is_uppercase() && after_lower)) && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        if c != '_' {
+            word.extend(c.to_lowercase());
+        }
+        after_lower = c.is_lowercase() || c.is_ascii_digit();
+    }
+    if !word.is_empty() {
+        words.push(word);
     }
+    words
+}
+
+// カラム名の付け方から型を推測する(created_at なら timestamp など)
+// 単語ごとに比べるので、message や update は age や date とはみなさない
+fn column_type(name: &str) -> Option<String> {
+    let words = name_words(name);
+    let first = words.first()?.as_str();
+    let last = words.last()?.as_str();
+    let has = |word: &str| words.iter().any(|w| w == word);
+
+    let guessed_type = if ["at", "time"].contains(&last) || has("timestamp") || has("datetime") {
+        "timestamp"
+    } else if ["date", "on", "day"].contains(&last) {
+        "date"
+    } else if ["is", "has", "can"].contains(&first) || ["flag", "flg"].contains(&last) {
+        "boolean"
+    } else if NUMBER_COLUMN_WORDS.contains(&last) {
+        "number"
+    } else if TEXT_COLUMN_WORDS.contains(&last) {
+        "text"
+    } else {
+        return None;
+    };
+    Some(guessed_type.to_string())
+}
+
+fn function_type(name: &str) -> Option<String> {
+    let guessed_type = match name.to_lowercase().as_str() {
+        "current_date" | "curdate" => "date",
+        "current_time" | "curtime" | "localtime" => "time",
+        "now" | "current_timestamp" | "localtimestamp" | "sysdate" | "clock_timestamp" => {
+            "timestamp"
+        }
+        "count" | "sum" | "avg" | "length" | "char_length" | "abs" | "round" => "number",
+        "lower" | "upper" | "concat" | "substring" | "substr" | "trim" | "replace" => "text",
+        _ => return None,
+    };
+    Some(guessed_type.to_string())
 }
 
-fn walk_table_with_joins(twjs: &TableWithJoins) -> (Vec<String>, Vec<String>) {
-    //println!("{:?}", twjs);
-    let mut select_statements = (vec![], vec![]);
+fn type_of(expr: &Expr) -> Option<String> {
+    if parameter_index(expr).is_some() {
+        return None;
+    }
 
-    let (w, s) = walk_table_factor(&twjs.relation);
-    select_statements.0.extend(w);
-    select_statements.1.extend(s);
+    match expr {
+        Expr::Value(value) => match value {
+            Value::Number(..) => Some(String::from("number")),
+            Value::SingleQuotedString(_)
+            | Value::DoubleQuotedString(_)
+            | Value::NationalStringLiteral(_) => Some(String::from("text")),
+            Value::Boolean(_) => Some(String::from("boolean")),
+            _ => None,
+        },
+        Expr::TypedString { data_type, .. } => Some(data_type_category(data_type)),
+        Expr::Cast { data_type, .. } => Some(data_type_category(data_type)),
+        Expr::Identifier(ident) => column_type(&ident.value),
+        Expr::CompoundIdentifier(idents) => idents.last().and_then(|i| column_type(&i.value)),
+        Expr::Function(function) => function_type(&function.name.to_string()),
+        Expr::Nested(expr) => type_of(expr),
+        // created_at - INTERVAL '1 day' のような演算は、左右どちらかの型を使う
+        Expr::BinaryOp {
+            left,
+            op:
+                BinaryOperator::Plus
+                | BinaryOperator::Minus
+                | BinaryOperator::Multiply
+                | BinaryOperator::Divide
+                | BinaryOperator::Modulo,
+            right,
+        } => type_of(left).or_else(|| type_of(right)),
+        _ => None,
+    }
+}
 
-    for join in &twjs.joins {
-        let (w, s) = walk_join(&join);
-        select_statements.0.extend(w);
-        select_statements.1.extend(s);
+fn guess_query(query: &Query, types: &mut HashMap<usize, String>) {
+    if let Some(with) = &query.with {
+        for cte in &with.cte_tables {
+            guess_query(&cte.query, types);
+        }
+    }
+
+    guess_setexpr(&query.body, types);
+
+    if let Some(order_by) = &query.order_by {
+        for order_by_expr in &order_by.exprs {
+            guess_expr(&order_by_expr.expr, types);
+        }
+    }
+
+    let mut counts = vec![];
+    counts.extend(&query.limit);
+    counts.extend(query.offset.iter().map(|offset| &offset.value));
+    counts.extend(
+        query
+            .fetch
+            .iter()
+            .filter_map(|fetch| fetch.quantity.as_ref()),
+    );
+    for expr in counts {
+        set_parameter_type(expr, Some(String::from("number")), types);
+        guess_expr(expr, types);
+    }
+}
+
+fn guess_setexpr(setexpr: &SetExpr, types: &mut HashMap<usize, String>) {
+    match setexpr {
+        SetExpr::Select(select) => guess_select(select, types),
+        SetExpr::Query(query) => guess_query(query, types),
+        SetExpr::SetOperation { left, right, .. } => {
+            guess_setexpr(left, types);
+            guess_setexpr(right, types);
+        }
+        SetExpr::Values(values) => {
+            for row in &values.rows {
+                for expr in row {
+                    guess_expr(expr, types);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn guess_select(select: &Select, types: &mut HashMap<usize, String>) {
+    if let Some(Top {
+        quantity: Some(TopQuantity::Expr(expr)),
+        ..
+    }) = &select.top
+    {
+        set_parameter_type(expr, Some(String::from("number")), types);
+    }
+
+    for select_item in &select.projection {
+        match select_item {
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                guess_expr(expr, types)
+            }
+            _ => {}
+        }
+    }
+
+    for table_with_joins in &select.from {
+        guess_table_with_joins(table_with_joins, types);
+    }
+
+    for expr in [&select.selection, &select.having, &select.qualify] {
+        guess_selection(expr.as_ref(), types);
+    }
+
+    if let GroupByExpr::Expressions(exprs, _) = &select.group_by {
+        for expr in exprs {
+            guess_expr(expr, types);
+        }
+    }
+}
+
+fn guess_table_with_joins(table_with_joins: &TableWithJoins, types: &mut HashMap<usize, String>) {
+    guess_table_factor(&table_with_joins.relation, types);
+
+    for join in &table_with_joins.joins {
+        guess_table_factor(&join.relation, types);
+
+        let constraint = match &join.join_operator {
+            JoinOperator::Inner(constraint)
+            | JoinOperator::LeftOuter(constraint)
+            | JoinOperator::RightOuter(constraint)
+            | JoinOperator::FullOuter(constraint)
+            | JoinOperator::LeftSemi(constraint)
+            | JoinOperator::RightSemi(constraint)
+            | JoinOperator::LeftAnti(constraint)
+            | JoinOperator::RightAnti(constraint) => constraint,
+            _ => continue,
+        };
+        if let JoinConstraint::On(expr) = constraint {
+            guess_expr(expr, types);
+        }
+    }
+}
+
+fn guess_table_factor(table_factor: &TableFactor, types: &mut HashMap<usize, String>) {
+    match table_factor {
+        TableFactor::Derived { subquery, .. } => guess_query(subquery, types),
+        TableFactor::NestedJoin {
+            table_with_joins, ..
+        } => guess_table_with_joins(table_with_joins, types),
+        _ => {}
+    }
+}
+
+fn guess_function(function: &Function, types: &mut HashMap<usize, String>) {
+    match &function.args {
+        FunctionArguments::List(list) => {
+            for function_arg in &list.args {
+                match function_arg {
+                    FunctionArg::Named {
+                        arg: FunctionArgExpr::Expr(expr),
+                        ..
+                    }
+                    | FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => guess_expr(expr, types),
+                    _ => {}
+                }
+            }
+        }
+        FunctionArguments::Subquery(subquery) => guess_query(subquery, types),
+        _ => {}
+    }
+}
+
+fn guess_expr(expr: &Expr, types: &mut HashMap<usize, String>) {
+    match expr {
+        Expr::BinaryOp { left, op, right } => {
+            match op {
+                BinaryOperator::Eq
+                | BinaryOperator::NotEq
+                | BinaryOperator::Lt
+                | BinaryOperator::LtEq
+                | BinaryOperator::Gt
+                | BinaryOperator::GtEq
+                | BinaryOperator::Spaceship => {
+                    set_parameter_type(left, type_of(right), types);
+                    set_parameter_type(right, type_of(left), types);
+                }
+                BinaryOperator::Plus
+                | BinaryOperator::Minus
+                | BinaryOperator::Multiply
+                | BinaryOperator::Divide
+                | BinaryOperator::Modulo => {
+                    set_parameter_type(left, Some(String::from("number")), types);
+                    set_parameter_type(right, Some(String::from("number")), types);
+                }
+                BinaryOperator::StringConcat => {
+                    set_parameter_type(left, Some(String::from("text")), types);
+                    set_parameter_type(right, Some(String::from("text")), types);
+                }
+                BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Xor => {
+                    set_parameter_type(left, Some(String::from("boolean")), types);
+                    set_parameter_type(right, Some(String::from("boolean")), types);
+                }
+                _ => {}
+            }
+            guess_expr(left, types);
+            guess_expr(right, types);
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            let guessed_type = type_of(expr)
+                .or_else(|| type_of(low))
+                .or_else(|| type_of(high));
+            for e in [expr, low, high] {
+                set_parameter_type(e, guessed_type.clone(), types);
+                guess_expr(e, types);
+            }
+        }
+        Expr::InList { expr, list, .. } => {
+            let guessed_type = type_of(expr).or_else(|| list.iter().find_map(type_of));
+            set_parameter_type(expr, guessed_type.clone(), types);
+            guess_expr(expr, types);
+            for e in list {
+                set_parameter_type(e, guessed_type.clone(), types);
+                guess_expr(e, types);
+            }
+        }
+        Expr::InSubquery { expr, subquery, .. } => {
+            guess_expr(expr, types);
+            guess_query(subquery, types);
+        }
+        Expr::Like { expr, pattern, .. }
+        | Expr::ILike { expr, pattern, .. }
+        | Expr::SimilarTo { expr, pattern, .. }
+        | Expr::RLike { expr, pattern, .. } => {
+            for e in [expr, pattern] {
+                set_parameter_type(e, Some(String::from("text")), types);
+                guess_expr(e, types);
+            }
+        }
+        Expr::AnyOp { left, right, .. } | Expr::AllOp { left, right, .. } => {
+            guess_expr(left, types);
+            guess_expr(right, types);
+        }
+        Expr::Cast {
+            expr, data_type, ..
+        } => {
+            set_parameter_type(expr, Some(data_type_category(data_type)), types);
+            guess_expr(expr, types);
+        }
+        Expr::Nested(expr)
+        | Expr::UnaryOp { expr, .. }
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::IsTrue(expr)
+        | Expr::IsNotTrue(expr)
+        | Expr::IsFalse(expr)
+        | Expr::IsNotFalse(expr) => guess_expr(expr, types),
+        Expr::Function(function) => guess_function(function, types),
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            match operand {
+                // CASE x WHEN #{a} THEN ... は x と比較される
+                Some(operand) => {
+                    let guessed_type =
+                        type_of(operand).or_else(|| conditions.iter().find_map(type_of));
+                    set_parameter_type(operand, guessed_type.clone(), types);
+                    guess_expr(operand, types);
+                    for condition in conditions {
+                        set_parameter_type(condition, guessed_type.clone(), types);
+                    }
+                }
+                None => {
+                    for condition in conditions {
+                        set_parameter_type(condition, Some(String::from("boolean")), types);
+                    }
+                }
+            }
+            for condition in conditions {
+                guess_expr(condition, types);
+            }
+
+            let results: Vec<&Expr> = results.iter().chain(else_result.as_deref()).collect();
+            let guessed_type = results.iter().find_map(|e| type_of(e));
+            for result in results {
+                set_parameter_type(result, guessed_type.clone(), types);
+                guess_expr(result, types);
+            }
+        }
+        Expr::Exists { subquery, .. } | Expr::Subquery(subquery) => guess_query(subquery, types),
+        Expr::Tuple(exprs) => {
+            for expr in exprs {
+                guess_expr(expr, types);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+
+    use sqlparser::dialect::{MySqlDialect, PostgreSqlDialect};
+
+    use super::*;
+
+    // パラメーター(PARAMETER_PREFIX の識別子に置き換えたもの)の番号順に、推測した型を返す
+    fn parameter_types(sql: &str) -> Vec<(usize, String)> {
+        let sql = sql.replace("#", PARAMETER_PREFIX);
+        let mut types: Vec<(usize, String)> = find_parameter_types(&PostgreSqlDialect {}, &sql)
+            .unwrap()
+            .into_iter()
+            .collect();
+        types.sort();
+        types
+    }
+
+    fn types(expected: &[(usize, &str)]) -> Vec<(usize, String)> {
+        expected.iter().map(|(i, t)| (*i, t.to_string())).collect()
+    }
+
+    #[test]
+    fn column_type_matches_whole_words() {
+        for (name, expected) in [
+            ("created_at", Some("timestamp")),
+            ("createdAt", Some("timestamp")),
+            ("start_time", Some("timestamp")),
+            ("update_date", Some("date")),
+            ("birth_day", Some("date")),
+            ("is_active", Some("boolean")),
+            ("delete_flg", Some("boolean")),
+            ("id", Some("number")),
+            ("userID", Some("number")),
+            ("order_no", Some("number")),
+            ("user_age", Some("number")),
+            ("item_count", Some("number")),
+            ("user_name", Some("text")),
+            ("status", Some("text")),
+            // 単語の一部が一致しても推測しない
+            ("message", None),
+            ("language", None),
+            ("image", None),
+            ("page", None),
+            ("update", None),
+            ("candidate", None),
+            ("account", None),
+            ("discount", None),
+            ("format", None),
+        ] {
+            assert_eq!(column_type(name).as_deref(), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn find_parameter_types_from_comparisons() {
+        assert_eq!(
+            parameter_types(
+                "SELECT * FROM t WHERE created_at > #0 AND user_id = #1 AND message = #2 AND t.price <= #3"
+            ),
+            types(&[(0, "timestamp"), (1, "number"), (3, "number")])
+        );
+        // 左右どちらにあっても、相手の型を使う
+        assert_eq!(
+            parameter_types("SELECT * FROM t WHERE #0 = 'a' AND 10 < #1 AND #2 = true"),
+            types(&[(0, "text"), (1, "number"), (2, "boolean")])
+        );
+    }
+
+    #[test]
+    fn find_parameter_types_from_expressions() {
+        assert_eq!(
+            parameter_types(
+                "SELECT * FROM t WHERE d BETWEEN DATE '2024-01-01' AND #0 AND x IN (1, #1) AND name LIKE #2 AND #3::date < d"
+            ),
+            types(&[(0, "date"), (1, "number"), (2, "text"), (3, "date")])
+        );
+        assert_eq!(
+            parameter_types("SELECT * FROM t WHERE created_at > now() - #0 AND a = b || #1"),
+            types(&[(0, "number"), (1, "text")])
+        );
+        // サブクエリの中も推測し、同じパラメーターは先に推測できた型を使う
+        assert_eq!(
+            parameter_types(
+                "SELECT * FROM t WHERE EXISTS (SELECT 1 FROM u WHERE u.updated_at < #0) AND a = #0"
+            ),
+            types(&[(0, "timestamp")])
+        );
+    }
+
+    #[test]
+    fn find_parameter_types_from_dml() {
+        // 代入先のカラム名から推測する
+        assert_eq!(
+            parameter_types("UPDATE t SET price = #0 WHERE id = #1"),
+            types(&[(0, "number"), (1, "number")])
+        );
+        assert_eq!(
+            parameter_types("INSERT INTO t (name, created_at) VALUES (#0, #1), ('x', #2)"),
+            types(&[(0, "text"), (1, "timestamp"), (2, "timestamp")])
+        );
+        assert_eq!(
+            parameter_types("DELETE FROM t WHERE updated_on < #0 OR #1"),
+            types(&[(0, "date"), (1, "boolean")])
+        );
+    }
+
+    #[test]
+    fn find_parameter_types_fails_on_invalid_sql() {
+        assert!(
+            find_parameter_types(&PostgreSqlDialect {}, &String::from("SELECT FROM WHERE"))
+                .is_err()
+        );
+    }
+
+    fn nodes(sql: &str) -> Vec<SubqueryNode> {
+        find_select_statement(&PostgreSqlDialect {}, sql, |name: &str| {
+            format!(":{}", name)
+        })
+        .unwrap()
+    }
+
+    // (id, parent_id, clause, alias, sql)
+    type TreeNode<'a> = (usize, Option<usize>, ClauseKind, Option<&'a str>, &'a str);
+
+    fn tree(nodes: &[SubqueryNode]) -> Vec<TreeNode<'_>> {
+        nodes
+            .iter()
+            .map(|n| {
+                (
+                    n.id,
+                    n.parent_id,
+                    n.clause,
+                    n.alias.as_deref(),
+                    n.sql.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    fn span(node: &SubqueryNode) -> Option<(usize, usize, usize, usize)> {
+        node.span
+            .as_ref()
+            .map(|s| (s.start, s.end, s.line, s.column))
+    }
+
+    #[test]
+    fn nested_subqueries_with_clause_kinds() {
+        let sql = "SELECT a, (SELECT max(b) FROM u) AS m \
+            FROM (SELECT * FROM t WHERE x IN (SELECT x FROM w)) AS s \
+            JOIN (SELECT id FROM v) AS j ON j.id = s.id AND j.id IN (SELECT id FROM k) \
+            WHERE s.id IN (SELECT id FROM v) \
+            GROUP BY a HAVING count(*) > (SELECT 1)";
+        let nodes = nodes(sql);
+
+        assert_eq!(
+            tree(&nodes),
+            vec![
+                (0, None, ClauseKind::Statement, None, sql),
+                (
+                    1,
+                    Some(0),
+                    ClauseKind::SelectList,
+                    None,
+                    "SELECT max(b) FROM u"
+                ),
+                (
+                    2,
+                    Some(0),
+                    ClauseKind::From,
+                    Some("s"),
+                    "SELECT * FROM t WHERE x IN (SELECT x FROM w)"
+                ),
+                (3, Some(2), ClauseKind::Where, None, "SELECT x FROM w"),
+                // 結合するサブクエリは FROM 句、ON の中のサブクエリは JOIN
+                (4, Some(0), ClauseKind::From, Some("j"), "SELECT id FROM v"),
+                (5, Some(0), ClauseKind::Join, None, "SELECT id FROM k"),
+                (6, Some(0), ClauseKind::Where, None, "SELECT id FROM v"),
+                (7, Some(0), ClauseKind::Having, None, "SELECT 1"),
+            ]
+        );
+        // 同じ SQL のサブクエリも、それぞれの位置を指す
+        let start = |text: &str| sql.find(text).unwrap();
+        assert_eq!(span(&nodes[0]), Some((0, sql.len(), 1, 1)));
+        assert_eq!(
+            span(&nodes[4]).map(|s| s.0),
+            Some(start("SELECT id FROM v) AS j"))
+        );
+        assert_eq!(
+            span(&nodes[6]).map(|s| s.0),
+            Some(start("SELECT id FROM v) GROUP"))
+        );
+    }
+
+    #[test]
+    fn runnable_sql_prepends_ctes_transitively() {
+        let nodes = nodes(
+            "WITH a AS (SELECT 1 AS x), b AS (SELECT x FROM a), c AS (SELECT 2 AS y) \
+            SELECT * FROM (SELECT x FROM b) AS s, c",
+        );
+
+        let ctes: Vec<(Option<&str>, Vec<&str>, &str)> = nodes
+            .iter()
+            .skip(1)
+            .map(|n| {
+                (
+                    n.alias.as_deref(),
+                    n.ctes.iter().map(String::as_str).collect(),
+                    n.runnable_sql.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            ctes,
+            vec![
+                (Some("a"), vec![], "SELECT 1 AS x"),
+                (
+                    Some("b"),
+                    vec!["a"],
+                    "WITH a AS (SELECT 1 AS x) SELECT x FROM a"
+                ),
+                (Some("c"), vec![], "SELECT 2 AS y"),
+                // b を通して a も使う。使わない c は付けない
+                (
+                    Some("s"),
+                    vec!["a", "b"],
+                    "WITH a AS (SELECT 1 AS x), b AS (SELECT x FROM a) SELECT x FROM b"
+                ),
+            ]
+        );
+        assert_eq!(nodes[1].clause, ClauseKind::Cte);
+        assert_eq!(nodes[4].clause, ClauseKind::From);
+        // CTE を使わないノードは、そのまま実行できる
+        assert_eq!(nodes[0].runnable_sql, nodes[0].sql);
+        assert!(nodes[0].ctes.is_empty());
+    }
+
+    #[test]
+    fn correlated_exists_binds_outer_columns() {
+        let nodes = nodes(
+            "SELECT * FROM orders AS o WHERE EXISTS \
+            (SELECT 1 FROM items AS i WHERE i.order_id = o.id AND i.qty > 0)",
+        );
+
+        let exists = &nodes[1];
+        assert_eq!(exists.clause, ClauseKind::Where);
+        assert!(exists.correlated);
+        assert_eq!(
+            exists.sql,
+            "SELECT 1 FROM items AS i WHERE i.order_id = o.id AND i.qty > 0"
+        );
+        assert_eq!(
+            exists.runnable_sql,
+            "SELECT 1 FROM items AS i WHERE i.order_id = :o_id AND i.qty > 0"
+        );
+        let references: Vec<(&str, &str, &str, &str)> = exists
+            .outer_references
+            .iter()
+            .map(|r| {
+                (
+                    r.sql.as_str(),
+                    r.qualifier.as_str(),
+                    r.column.as_str(),
+                    r.parameter_name.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(references, vec![("o.id", "o", "id", "o_id")]);
+        assert!(!nodes[0].correlated);
+    }
+
+    #[test]
+    fn uncorrelated_subquery_shadowing_outer_alias() {
+        // 内側で同じ別名を定義していれば、外側への参照ではない
+        let nodes = nodes("SELECT * FROM t o WHERE o.id IN (SELECT o.id FROM u o)");
+
+        assert!(!nodes[1].correlated);
+        assert!(nodes[1].outer_references.is_empty());
+    }
+
+    #[test]
+    fn order_by_resolves_in_the_inner_scope() {
+        // ORDER BY や LIMIT の o も、内側の SELECT の別名
+        let nodes =
+            nodes("SELECT * FROM t o WHERE o.id IN (SELECT o.id FROM u o ORDER BY o.x LIMIT 1)");
+
+        assert!(!nodes[1].correlated);
+        assert_eq!(
+            nodes[1].runnable_sql,
+            "SELECT o.id FROM u o ORDER BY o.x LIMIT 1"
+        );
+    }
+
+    #[test]
+    fn outer_references_are_replaced_where_they_are_referenced() {
+        let nodes = nodes(
+            "SELECT * FROM t o WHERE EXISTS (SELECT 1 FROM u \
+            WHERE u.a = o . id AND u.b = 'o.id' AND u.c IN (SELECT o.id FROM v o) \
+            ORDER BY o.id)",
+        );
+
+        // 文字列リテラルの中や、内側で同じ別名を定義した参照は置き換えない
+        assert!(nodes[1].correlated);
+        assert_eq!(
+            nodes[1].runnable_sql,
+            "SELECT 1 FROM u WHERE u.a = :o_id AND u.b = 'o.id' \
+            AND u.c IN (SELECT o.id FROM v o) ORDER BY :o_id"
+        );
+        assert!(!nodes[2].correlated);
+    }
+
+    #[test]
+    fn spans_keep_comments_and_formatting() {
+        let sql = "-- 先頭のコメント\nselect *\n  from t /* c */ where id = __sql_divider_parameter_0\n  and x in (select  y\n    from u)";
+        let nodes = nodes(sql);
+
+        // 先頭のコメントは含めない
+        let statement = &nodes[0];
+        let start = sql.find("select *").unwrap();
+        assert_eq!(span(statement), Some((start, sql.len(), 2, 1)));
+        assert_eq!(statement.sql, &sql[start..]);
+
+        // sqlparser が正規化した SQL ではなく、入力した SQL をそのまま返す
+        let subquery = &nodes[1];
+        let start = sql.find("select  y").unwrap();
+        assert_eq!(span(subquery), Some((start, sql.len() - 1, 4, 13)));
+        assert_eq!(subquery.sql, "select  y\n    from u");
+    }
+
+    #[test]
+    fn identical_subqueries_have_their_own_spans() {
+        let sql = "SELECT 'SELECT id FROM u' AS s FROM t \
+            WHERE a IN (SELECT id FROM u) AND b IN (SELECT id FROM u WHERE x = 1)";
+        let nodes = nodes(sql);
+
+        // 文字列リテラルの中は飛ばし、同じ SQL のサブクエリもそれぞれの位置を指す
+        let first = sql.find("SELECT id FROM u)").unwrap();
+        let second = sql.rfind("SELECT id FROM u").unwrap();
+        assert_eq!(
+            span(&nodes[1]).map(|s| (s.0, s.1)),
+            Some((first, first + "SELECT id FROM u".len()))
+        );
+        // 末尾のリテラルも含める
+        assert_eq!(
+            span(&nodes[2]).map(|s| (s.0, s.1)),
+            Some((second, sql.len() - 1))
+        );
+        assert_eq!(nodes[2].sql, "SELECT id FROM u WHERE x = 1");
     }
 
-    return select_statements;
+    #[test]
+    fn previews_span_the_whole_statement() {
+        let sql = "SELECT 1;\nupdate t set a = 1 where id in (select id from u)";
+        let nodes = nodes(sql);
+
+        let start = sql.find("update").unwrap();
+        assert_eq!(span(&nodes[1]), Some((start, sql.len(), 2, 1)));
+        assert_eq!(
+            nodes[1].sql,
+            "SELECT * FROM t WHERE id IN (SELECT id FROM u)"
+        );
+        let start = sql.find("select id").unwrap();
+        assert_eq!(span(&nodes[2]).map(|s| s.0), Some(start));
+        assert_eq!(nodes[2].sql, "select id from u");
+    }
+
+    #[test]
+    fn set_operations_are_nodes() {
+        let sql = "SELECT a FROM t UNION ALL SELECT a FROM u EXCEPT SELECT a FROM v";
+        let nodes = nodes(sql);
+
+        assert_eq!(
+            tree(&nodes),
+            vec![
+                (0, None, ClauseKind::Statement, None, sql),
+                (
+                    1,
+                    Some(0),
+                    ClauseKind::SetOperation,
+                    None,
+                    "SELECT a FROM t UNION ALL SELECT a FROM u"
+                ),
+                (
+                    2,
+                    Some(1),
+                    ClauseKind::SetOperation,
+                    None,
+                    "SELECT a FROM t"
+                ),
+                (
+                    3,
+                    Some(1),
+                    ClauseKind::SetOperation,
+                    None,
+                    "SELECT a FROM u"
+                ),
+                (
+                    4,
+                    Some(0),
+                    ClauseKind::SetOperation,
+                    None,
+                    "SELECT a FROM v"
+                ),
+            ]
+        );
+        let operations: Vec<Option<(&str, Option<&str>)>> = nodes
+            .iter()
+            .map(|n| {
+                n.set_operation
+                    .as_ref()
+                    .map(|o| (o.operator.as_str(), o.quantifier.as_deref()))
+            })
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                Some(("EXCEPT", None)),
+                Some(("UNION", Some("ALL"))),
+                None,
+                None,
+                None
+            ]
+        );
+        assert_eq!(span(&nodes[1]), Some((0, 41, 1, 1)));
+    }
+
+    #[test]
+    fn dml_previews_with_subqueries() {
+        // (SQL, 文の種類, プレビューの SQL, 中のサブクエリの (句, 別名, SQL))
+        type Case<'a> = (
+            &'a str,
+            &'a str,
+            &'a str,
+            Vec<(ClauseKind, Option<&'a str>, &'a str)>,
+        );
+        let cases: Vec<Case> = vec![
+            (
+                "UPDATE t SET a = 1 WHERE x IN (SELECT x FROM u WHERE u.y = t.y)",
+                "UPDATE",
+                "SELECT * FROM t WHERE x IN (SELECT x FROM u WHERE u.y = t.y)",
+                vec![(ClauseKind::Where, None, "SELECT x FROM u WHERE u.y = t.y")],
+            ),
+            (
+                "DELETE FROM t USING u AS v WHERE t.id = v.id AND v.x IN (SELECT x FROM w)",
+                "DELETE",
+                "SELECT * FROM t, u AS v WHERE t.id = v.id AND v.x IN (SELECT x FROM w)",
+                vec![(ClauseKind::Where, None, "SELECT x FROM w")],
+            ),
+            (
+                "INSERT INTO t (a) SELECT a FROM u WHERE b IN (SELECT b FROM v) \
+                ON CONFLICT (a) DO NOTHING",
+                "INSERT",
+                "SELECT a FROM u WHERE b IN (SELECT b FROM v)",
+                vec![(ClauseKind::Where, None, "SELECT b FROM v")],
+            ),
+            (
+                "CREATE TABLE t2 AS SELECT * FROM t WHERE a IN (SELECT a FROM u)",
+                "CREATE TABLE",
+                "SELECT * FROM t WHERE a IN (SELECT a FROM u)",
+                vec![(ClauseKind::Where, None, "SELECT a FROM u")],
+            ),
+            (
+                "CREATE VIEW v AS SELECT a FROM t WHERE EXISTS (SELECT 1 FROM u WHERE u.a = t.a)",
+                "CREATE VIEW",
+                "SELECT a FROM t WHERE EXISTS (SELECT 1 FROM u WHERE u.a = t.a)",
+                vec![(ClauseKind::Where, None, "SELECT 1 FROM u WHERE u.a = t.a")],
+            ),
+            (
+                "MERGE INTO t USING (SELECT id, a FROM u) AS s ON t.id = s.id \
+                WHEN MATCHED AND s.a > (SELECT max(a) FROM w) THEN UPDATE SET a = s.a \
+                WHEN NOT MATCHED THEN INSERT (id, a) VALUES (s.id, s.a)",
+                "MERGE",
+                "SELECT * FROM (SELECT id, a FROM u) AS s LEFT JOIN t ON t.id = s.id",
+                vec![
+                    (ClauseKind::Source, Some("s"), "SELECT id, a FROM u"),
+                    (ClauseKind::Other, None, "SELECT max(a) FROM w"),
+                ],
+            ),
+        ];
+
+        for (sql, kind, preview, subqueries) in cases {
+            let nodes = nodes(sql);
+            assert_eq!(nodes[0].preview_of.as_deref(), Some(kind), "{}", sql);
+            assert_eq!(nodes[0].sql, preview, "{}", sql);
+            let children: Vec<(ClauseKind, Option<&str>, &str)> = tree(&nodes[1..])
+                .into_iter()
+                .map(|(_, parent_id, clause, alias, sql)| {
+                    assert_eq!(parent_id, Some(0));
+                    (clause, alias, sql)
+                })
+                .collect();
+            assert_eq!(children, subqueries, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn dml_subqueries_bind_the_target_table() {
+        let nodes = nodes("UPDATE t SET a = 1 WHERE x IN (SELECT x FROM u WHERE u.y = t.y)");
+
+        assert!(nodes[1].correlated);
+        assert_eq!(nodes[1].runnable_sql, "SELECT x FROM u WHERE u.y = :t_y");
+        // プレビューは実行できる SELECT なので、そのまま実行する
+        assert_eq!(nodes[0].runnable_sql, nodes[0].sql);
+    }
+
+    #[test]
+    fn dialect_from_name_knows_every_dialect() {
+        let cases = [
+            ("generic", TypeId::of::<GenericDialect>()),
+            ("ansi", TypeId::of::<AnsiDialect>()),
+            ("postgres", TypeId::of::<PostgreSqlDialect>()),
+            ("mysql", TypeId::of::<MySqlDialect>()),
+            ("sqlite", TypeId::of::<SQLiteDialect>()),
+            ("mssql", TypeId::of::<MsSqlDialect>()),
+            ("snowflake", TypeId::of::<SnowflakeDialect>()),
+            ("bigquery", TypeId::of::<BigQueryDialect>()),
+            ("hive", TypeId::of::<HiveDialect>()),
+            ("redshift", TypeId::of::<RedshiftSqlDialect>()),
+        ];
+        for (name, expected) in cases {
+            let dialect = dialect_from_name(name).map(|d| d.dialect());
+            assert_eq!(dialect, Some(expected), "{}", name);
+        }
+
+        // 知らない名前や大文字の名前は None(呼び出し側で Unknown dialect のエラーにする)
+        for name in ["oracle", "Postgres", ""] {
+            assert!(dialect_from_name(name).is_none(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn find_projection_tables_from_qualifiers() {
+        let tables = |sql: &str| find_projection_tables(&PostgreSqlDialect {}, sql);
+        let some = |table: &str| Some(table.to_string());
+
+        // テーブルが 1 つなら、修飾されていないカラムもそのテーブルのもの
+        assert_eq!(
+            tables("SELECT id, name AS n, 1 FROM users"),
+            vec![some("users"), some("users"), None]
+        );
+        // 別名はテーブル名に戻す。修飾されていないカラムは、どのテーブルか分からない
+        assert_eq!(
+            tables(
+                "SELECT u.id, O.total, x, s.t.c FROM public.users u \
+                JOIN orders AS o ON o.user_id = u.id"
+            ),
+            vec![some("public.users"), some("orders"), None, None]
+        );
+        // 別名の無いスキーマ付きのテーブルは、テーブル名で修飾できる
+        assert_eq!(
+            tables("SELECT users.id, p.id FROM public.users, (SELECT id FROM t) p"),
+            vec![some("public.users"), None]
+        );
+        // ワイルドカードがあると並びが分からない
+        assert_eq!(tables("SELECT * FROM users"), vec![]);
+        assert_eq!(
+            tables("SELECT u.*, o.id FROM users u JOIN orders o ON true"),
+            vec![]
+        );
+        assert_eq!(
+            tables("SELECT id FROM users UNION SELECT id FROM orders"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn projection_ranges_exclude_aliases() {
+        let dialect = PostgreSqlDialect {};
+        let texts = |sql: &str| {
+            projection_ranges(&dialect, sql).map(|ranges| {
+                ranges
+                    .into_iter()
+                    .map(|(start, end)| sql[start..end].to_string())
+                    .collect::<Vec<String>>()
+            })
+        };
+        let some = |texts: &[&str]| Some(texts.iter().map(|t| t.to_string()).collect());
+
+        assert_eq!(
+            texts("SELECT h, t.x AS \"X\", hstore(a, b) y, a || 'z' FROM t WHERE a = 1"),
+            some(&["h", "t.x", "hstore(a, b)", "a || 'z'"])
+        );
+        // キーワードやリテラルで始まる式、括弧の中の , を含む式
+        assert_eq!(
+            texts("SELECT DISTINCT ON (a, b) CAST(h AS text) AS c, ARRAY[1, 2], (h).f\nFROM t"),
+            some(&["CAST(h AS text)", "ARRAY[1, 2]", "(h).f"])
+        );
+        assert_eq!(texts("SELECT 1, 'a'::text"), some(&["1", "'a'::text"]));
+        assert_eq!(
+            texts("UPDATE t SET a = 1 RETURNING a, h AS x;"),
+            some(&["a", "h"])
+        );
+        assert_eq!(texts("SELECT *, h FROM t"), None);
+        assert_eq!(texts("SELECT h FROM t UNION SELECT h FROM u"), None);
+        assert_eq!(texts("UPDATE t SET a = 1"), None);
+    }
+
+    #[test]
+    fn limit_rows_appends_limit() {
+        let dialect = PostgreSqlDialect {};
+        assert_eq!(
+            limit_rows(&dialect, "SELECT * FROM t", 10).as_deref(),
+            Some("SELECT * FROM t\nLIMIT 11")
+        );
+        // 末尾の ; とコメントの前に足す
+        assert_eq!(
+            limit_rows(&dialect, "SELECT * FROM t; -- 全件", 10).as_deref(),
+            Some("SELECT * FROM t\nLIMIT 11; -- 全件")
+        );
+        assert_eq!(
+            limit_rows(&dialect, "SELECT a FROM t UNION SELECT a FROM u", 10).as_deref(),
+            Some("SELECT a FROM t UNION SELECT a FROM u\nLIMIT 11")
+        );
+    }
+
+    #[test]
+    fn limit_rows_keeps_smaller_limit() {
+        let dialect = PostgreSqlDialect {};
+        assert_eq!(limit_rows(&dialect, "SELECT * FROM t LIMIT 5", 10), None);
+        assert_eq!(limit_rows(&dialect, "SELECT * FROM t LIMIT 10", 10), None);
+        assert_eq!(
+            limit_rows(&dialect, "SELECT * FROM t FETCH FIRST 5 ROWS ONLY", 10),
+            None
+        );
+        assert_eq!(
+            limit_rows(&dialect, "SELECT * FROM t FETCH FIRST ROW ONLY", 10),
+            None
+        );
+        // 値が分からない LIMIT はそのままにする
+        assert_eq!(limit_rows(&dialect, "SELECT * FROM t LIMIT $1", 10), None);
+    }
+
+    #[test]
+    fn limit_rows_replaces_larger_limit() {
+        let dialect = PostgreSqlDialect {};
+        assert_eq!(
+            limit_rows(&dialect, "SELECT * FROM t LIMIT 100", 10).as_deref(),
+            Some("SELECT * FROM t LIMIT 11")
+        );
+        assert_eq!(
+            limit_rows(&dialect, "SELECT * FROM t FETCH FIRST 100 ROWS ONLY", 10).as_deref(),
+            Some("SELECT * FROM t FETCH FIRST 11 ROWS ONLY")
+        );
+        // OFFSET がある場合は作り直す
+        assert_eq!(
+            limit_rows(&dialect, "SELECT * FROM t OFFSET 5", 10).as_deref(),
+            Some("SELECT * FROM t LIMIT 11 OFFSET 5")
+        );
+    }
+
+    #[test]
+    fn limit_rows_skips_non_queries() {
+        let dialect = PostgreSqlDialect {};
+        assert_eq!(limit_rows(&dialect, "SELECT 1; SELECT 2", 10), None);
+        assert_eq!(limit_rows(&dialect, "DELETE FROM t", 10), None);
+        assert_eq!(
+            limit_rows(
+                &dialect,
+                "WITH s AS (SELECT * FROM u) INSERT INTO t SELECT * FROM s",
+                10
+            ),
+            None
+        );
+        assert_eq!(
+            limit_rows(
+                &dialect,
+                "WITH s AS (SELECT 1) UPDATE t SET a = 1 WHERE b IN (SELECT * FROM s)",
+                10
+            ),
+            None
+        );
+        assert_eq!(limit_rows(&dialect, "SELECT * INTO t2 FROM t", 10), None);
+        assert_eq!(
+            limit_rows(
+                &MySqlDialect {},
+                "WITH s AS (SELECT 1) UPDATE t SET a = 1",
+                10
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn find_non_query_accepts_queries() {
+        let dialect = PostgreSqlDialect {};
+        for sql in [
+            "SELECT * FROM t",
+            "SELECT 1; SELECT 2",
+            "WITH s AS (SELECT 1) SELECT * FROM s",
+            "SELECT a FROM t UNION SELECT a FROM u",
+            "VALUES (1), (2)",
+        ] {
+            assert_eq!(find_non_query(&dialect, sql).unwrap(), None, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn find_non_query_rejects_modifying_statements() {
+        let dialect = PostgreSqlDialect {};
+        for (sql, kind) in [
+            ("DROP TABLE t", "DROP TABLE"),
+            ("SELECT 1; DELETE FROM t", "DELETE"),
+            ("CREATE OR REPLACE VIEW v AS SELECT 1", "CREATE VIEW"),
+            (
+                "WITH s AS (SELECT * FROM u) INSERT INTO t SELECT * FROM s",
+                "INSERT",
+            ),
+            (
+                "WITH s AS (SELECT 1) UPDATE t SET a = 1 WHERE b IN (SELECT * FROM s)",
+                "UPDATE",
+            ),
+            (
+                "WITH s AS (INSERT INTO t VALUES (1) RETURNING *) SELECT * FROM s",
+                "INSERT",
+            ),
+            ("SELECT * INTO t2 FROM t", "SELECT INTO"),
+            ("SELECT 1 UNION (SELECT * INTO t2 FROM t)", "SELECT INTO"),
+        ] {
+            assert_eq!(
+                find_non_query(&dialect, sql).unwrap().as_deref(),
+                Some(kind),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn recovery_skips_statements_that_do_not_parse() {
+        let sql = "SELECT 1;\nSELEC 2;\nSELECT * FROM (SELECT 3) x;";
+        let parsed = find_select_statement(&PostgreSqlDialect {}, sql, |n: &str| n.to_string());
+        assert!(parsed.is_err());
+
+        let (nodes, errors) =
+            find_select_statement_each(&PostgreSqlDialect {}, sql, |n: &str| n.to_string());
+
+        // id と位置は SQL 全体のものにする
+        assert_eq!(
+            tree(&nodes),
+            vec![
+                (0, None, ClauseKind::Statement, None, "SELECT 1"),
+                (
+                    1,
+                    None,
+                    ClauseKind::Statement,
+                    None,
+                    "SELECT * FROM (SELECT 3) x"
+                ),
+                (2, Some(1), ClauseKind::From, Some("x"), "SELECT 3"),
+            ]
+        );
+        assert_eq!(span(&nodes[1]), Some((19, 45, 3, 1)));
+        assert_eq!(span(&nodes[2]), Some((34, 42, 3, 16)));
+
+        assert_eq!(errors.len(), 1);
+        let ((start, end), error) = &errors[0];
+        assert_eq!(&sql[*start..*end], "SELEC 2");
+        assert_eq!(
+            expected_found(error),
+            Some((String::from("an SQL statement"), String::from("SELEC")))
+        );
+    }
 }
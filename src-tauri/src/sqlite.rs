@@ -0,0 +1,426 @@
+use std::time::Instant;
+
+use async_trait::async_trait;
+use sqlparser::dialect::{Dialect, SQLiteDialect};
+
+use sqlx::query::Query;
+use sqlx::sqlite::{
+    SqliteArguments, SqliteColumn, SqliteConnectOptions, SqliteConnection, SqlitePoolOptions,
+    SqliteRow,
+};
+use sqlx::types::chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use sqlx::Column;
+use sqlx::Decode;
+use sqlx::Error;
+use sqlx::Executor;
+use sqlx::Row;
+use sqlx::Type;
+use sqlx::TypeInfo;
+use sqlx::ValueRef;
+use sqlx::{Pool, Sqlite};
+
+use crate::backend::{self, DatabaseBackend, OnRows};
+use crate::error::CommandError;
+use crate::model::{QueryResult, QuerySummary, StatementResult, Value};
+use crate::parameter;
+use crate::sql_parser;
+
+pub struct SqliteBackend {
+    pool: Pool<Sqlite>,
+    read_only: bool,
+}
+
+#[async_trait]
+impl DatabaseBackend for SqliteBackend {
+    async fn connect(
+        url: String,
+        _db: String,
+        _user: String,
+        _password: String,
+        read_only: bool,
+    ) -> Result<Self, CommandError> {
+        // SQLite はファイルパスを url として受け取る
+        // 読み取り専用の場合は、ファイルを読み取り専用で開く
+        let pool = create_sqlite_connection_pool(url, read_only).await?;
+
+        Ok(SqliteBackend { pool, read_only })
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    async fn close(&self) -> Result<(), CommandError> {
+        self.pool.close().await;
+
+        Ok(())
+    }
+
+    async fn query(
+        &self,
+        _query_id: &str,
+        query: String,
+        parameters: Vec<Value>,
+        max_rows: Option<usize>,
+        on_rows: OnRows<'_>,
+    ) -> Result<QuerySummary, Error> {
+        let mut conn = self.pool.acquire().await?;
+        stream_sqlite(&mut conn, query, parameters, max_rows, on_rows).await
+    }
+
+    async fn execute_script(
+        &self,
+        _query_id: &str,
+        statements: Vec<(String, Vec<Value>)>,
+        max_rows: Option<usize>,
+    ) -> Result<Vec<StatementResult>, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let mut results = vec![];
+        for (query, parameters) in statements {
+            let start = Instant::now();
+            let result = query_to_sqlite(&mut conn, query.clone(), parameters, max_rows).await;
+            let failed = result.is_err();
+            results.push(backend::to_statement_result(query, result, start));
+            if failed {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+
+    // sqlx は実行中の SQLite のクエリを止める手段を持たない
+    async fn cancel(&self, _query_id: &str) -> Result<(), CommandError> {
+        Err(CommandError::other(
+            "Cancelling a query is not supported on SQLite",
+        ))
+    }
+
+    fn take_cancelled(&self, _query_id: &str) -> bool {
+        false
+    }
+
+    fn placeholder(&self, _index: usize) -> String {
+        String::from("?")
+    }
+
+    fn dialect(&self) -> Box<dyn Dialect> {
+        Box::new(SQLiteDialect {})
+    }
+}
+
+pub async fn create_sqlite_connection_pool(
+    path: String,
+    read_only: bool,
+) -> Result<Pool<Sqlite>, CommandError> {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(false)
+        .read_only(read_only);
+
+    let result = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await;
+
+    let result = match result {
+        Ok(r) => r,
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(result)
+}
+
+// 結果の行は on_rows に少しずつ渡し、手元には残さない
+pub async fn stream_sqlite(
+    conn: &mut SqliteConnection,
+    query: String,
+    parameters: Vec<Value>,
+    max_rows: Option<usize>,
+    on_rows: OnRows<'_>,
+) -> Result<QuerySummary, Error> {
+    let describe = (&mut *conn).describe(&query).await;
+
+    let mut sql_query = sqlx::query(&query);
+    for value in parameters {
+        sql_query = bind_value(sql_query, value)?;
+    }
+
+    // 結果のカラムが無い文(INSERT や SET など)は、変更された行数を受け取る
+    if matches!(&describe, Ok(d) if d.columns().is_empty()) {
+        let done = sql_query.execute(&mut *conn).await?;
+        return Ok(QuerySummary {
+            columns: vec![],
+            total_rows: 0,
+            rows_affected: done.rows_affected(),
+            truncated: false,
+        });
+    }
+
+    let tables = sql_parser::find_projection_tables(&SQLiteDialect {}, &query);
+    let (columns, total_rows, truncated) = backend::stream_rows(
+        sql_query.fetch(&mut *conn),
+        describe,
+        tables,
+        to_value,
+        max_rows,
+        on_rows,
+    )
+    .await?;
+
+    Ok(QuerySummary {
+        columns,
+        total_rows,
+        rows_affected: 0,
+        truncated,
+    })
+}
+
+// スクリプトの実行など、結果をまとめて受け取る場合に使う
+pub async fn query_to_sqlite(
+    conn: &mut SqliteConnection,
+    query: String,
+    parameters: Vec<Value>,
+    max_rows: Option<usize>,
+) -> Result<QueryResult, Error> {
+    let mut rows = vec![];
+    let summary = stream_sqlite(conn, query, parameters, max_rows, &mut |_, batch| {
+        rows.extend(batch)
+    })
+    .await?;
+
+    Ok(QueryResult {
+        columns: summary.columns,
+        rows,
+        rows_affected: summary.rows_affected,
+        truncated: summary.truncated,
+    })
+}
+
+fn bind_value<'q>(
+    query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    value: Value,
+) -> Result<Query<'q, Sqlite, SqliteArguments<'q>>, Error> {
+    // SQLite は動的型付けなので、数値以外は文字列で渡す
+    let query = match value {
+        Value::Null => query.bind(None::<String>),
+        Value::Bool(v) => query.bind(v),
+        Value::Int(v) => query.bind(v),
+        Value::Float(v) => query.bind(v),
+        // SQLite には DECIMAL が無いので REAL として渡す
+        Value::Decimal(v) => query.bind(backend::parse_decimal::<f64>(&v)?),
+        Value::Bytes(v) => query.bind(v),
+        v => query.bind(parameter::to_text(&v)),
+    };
+
+    Ok(query)
+}
+
+// NULL は to_value で先に除いているので、ここでの失敗はデコードの失敗
+fn get<'r, T>(row: &'r SqliteRow, ordinal: usize) -> Result<T, String>
+where
+    T: Decode<'r, Sqlite> + Type<Sqlite>,
+{
+    row.try_get::<T, _>(ordinal).map_err(|e| e.to_string())
+}
+
+fn to_value(row: &SqliteRow, column: &SqliteColumn) -> Value {
+    let ordinal = column.ordinal();
+
+    let raw = match row.try_get_raw(ordinal) {
+        Ok(raw) if raw.is_null() => return Value::Null,
+        Ok(raw) => raw,
+        Err(e) => return backend::decoded(Err(e.to_string()), Vec::new),
+    };
+
+    // SQLite は動的型付けで、宣言型と違う型の値も入るので、値の型(ストレージクラス)で読む
+    // 宣言型は、真偽値や日付・時刻として表示するかを決めるのにだけ使う
+    let storage_class = raw.type_info().name().to_string();
+    let declared = column.type_info().name();
+
+    let value = match storage_class.as_str() {
+        "INTEGER" if declared == "BOOLEAN" => get::<bool>(row, ordinal).map(Value::Bool),
+        "INTEGER" => get::<i64>(row, ordinal).map(Value::Int),
+        "REAL" => get::<f64>(row, ordinal).map(Value::Float),
+        "TEXT" => text_value(row, ordinal, declared),
+        "BLOB" => get::<Vec<u8>>(row, ordinal).map(Value::Bytes),
+        _ => Err(format!("Unsupported storage class: {}", storage_class)),
+    };
+
+    // 生のバイト列は、値の型によらず BLOB として読む
+    backend::decoded(value, || {
+        row.try_get_unchecked::<Vec<u8>, _>(ordinal)
+            .unwrap_or_default()
+    })
+}
+
+// 宣言型が日付・時刻で、その形式として読める文字列は日付・時刻にし、それ以外は文字列のまま返す
+fn text_value(row: &SqliteRow, ordinal: usize, declared: &str) -> Result<Value, String> {
+    let date_time = match declared {
+        "DATE" => get::<NaiveDate>(row, ordinal).map(|v| Value::Date(v.to_string())),
+        "TIME" => get::<NaiveTime>(row, ordinal).map(|v| Value::Time(v.to_string())),
+        "DATETIME" => get::<NaiveDateTime>(row, ordinal).map(|v| Value::Timestamp(v.to_string())),
+        _ => Err(String::new()),
+    };
+
+    date_time.or_else(|_| get::<String>(row, ordinal).map(Value::Text))
+}
+
+// メモリー上のデータベースで確認するので、サーバーは要らない
+#[cfg(test)]
+mod tests {
+    use sqlx::Connection;
+
+    use super::*;
+
+    async fn select_all(create: &str, insert: &str) -> Vec<Vec<Value>> {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        conn.execute(create).await.unwrap();
+        conn.execute(insert).await.unwrap();
+
+        query_to_sqlite(&mut conn, String::from("SELECT * FROM t"), vec![], None)
+            .await
+            .unwrap()
+            .rows
+    }
+
+    #[tokio::test]
+    async fn values_follow_storage_class() {
+        let rows = select_all(
+            "CREATE TABLE t (i INTEGER, n NUMERIC, s TEXT)",
+            "INSERT INTO t VALUES ('abc', 1.5, 1), (2, 'x', x'00ff'), (NULL, 3, 'y')",
+        )
+        .await;
+
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    Value::Text(String::from("abc")),
+                    Value::Float(1.5),
+                    // TEXT 型のカラムに入れた数値は、SQLite が文字列に変換する
+                    Value::Text(String::from("1")),
+                ],
+                vec![
+                    Value::Int(2),
+                    Value::Text(String::from("x")),
+                    Value::Bytes(vec![0x00, 0xff]),
+                ],
+                vec![Value::Null, Value::Int(3), Value::Text(String::from("y"))],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn declared_types_refine_values() {
+        let rows = select_all(
+            "CREATE TABLE t (b BOOLEAN, d DATE, dt DATETIME)",
+            "INSERT INTO t VALUES (1, '2024-01-02', '2024-01-02 03:04:05'), ('yes', 'someday', 5)",
+        )
+        .await;
+
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    Value::Bool(true),
+                    Value::Date(String::from("2024-01-02")),
+                    Value::Timestamp(String::from("2024-01-02 03:04:05")),
+                ],
+                vec![
+                    Value::Text(String::from("yes")),
+                    Value::Text(String::from("someday")),
+                    Value::Int(5),
+                ],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_text_keeps_its_bytes() {
+        let rows = select_all(
+            "CREATE TABLE t (s TEXT)",
+            "INSERT INTO t VALUES (CAST(x'61ff80' AS TEXT)), ('ok')",
+        )
+        .await;
+
+        let [Value::DecodeError { message, bytes }] = rows[0].as_slice() else {
+            panic!("expected a decode error: {:?}", rows[0]);
+        };
+        assert!(!message.is_empty());
+        assert_eq!(bytes, &vec![0x61, 0xff, 0x80]);
+        assert_eq!(rows[1], vec![Value::Text(String::from("ok"))]);
+    }
+
+    #[tokio::test]
+    async fn execute_script_stops_at_the_first_failing_statement() {
+        // メモリー上のデータベースは接続ごとに別なので、接続を 1 本にする
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let backend = SqliteBackend {
+            pool,
+            read_only: false,
+        };
+
+        let script = "CREATE TABLE t (s TEXT);\n\
+            INSERT INTO t VALUES ('a;b'); -- ;\n\
+            INSERT INTO missing VALUES (1);\n\
+            INSERT INTO t VALUES (?)";
+        let statements = parameter::split_statements(&SQLiteDialect {}, script, "jpa")
+            .unwrap()
+            .into_iter()
+            .map(|(statement, _)| match statement.contains('?') {
+                true => (statement, vec![Value::Text(String::from("c"))]),
+                false => (statement, vec![]),
+            })
+            .collect();
+        let results = backend
+            .execute_script("q1", statements, None)
+            .await
+            .unwrap();
+
+        let errors: Vec<bool> = results.iter().map(|r| r.error.is_some()).collect();
+        assert_eq!(errors, vec![false, false, true]);
+
+        let mut conn = backend.pool.acquire().await.unwrap();
+        let rows = query_to_sqlite(&mut conn, String::from("SELECT s FROM t"), vec![], None)
+            .await
+            .unwrap()
+            .rows;
+        assert_eq!(rows, vec![vec![Value::Text(String::from("a;b"))]]);
+    }
+
+    #[tokio::test]
+    async fn decimal_parameters_bind_as_real() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        let decimal = |v: &str| Value::Decimal(String::from(v));
+
+        let rows = query_to_sqlite(
+            &mut conn,
+            String::from("SELECT ?, typeof(?)"),
+            vec![decimal("1.25"), decimal(" 3 ")],
+            None,
+        )
+        .await
+        .unwrap()
+        .rows;
+        assert_eq!(
+            rows,
+            vec![vec![Value::Float(1.25), Value::Text(String::from("real"))]]
+        );
+
+        // 数値として読めない値は NULL にせず、エラーにする
+        let result = query_to_sqlite(
+            &mut conn,
+            String::from("SELECT ?"),
+            vec![decimal("1.2.3")],
+            None,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Encode(_))));
+    }
+}
@@ -1,10 +1,10 @@
 import "./App.css";
 import notice from "../NOTICE.md?raw";
 
-import { AppBar, Box, Button, CssBaseline, Dialog, DialogContent, Divider, FormControlLabel, Radio, RadioGroup, Stack, TextField, Typography } from "@mui/material";
+import { AppBar, Box, Button, Checkbox, CssBaseline, Dialog, DialogContent, Divider, FormControlLabel, MenuItem, Radio, RadioGroup, Stack, TextField, Typography } from "@mui/material";
 import Tooltip from '@mui/material/Tooltip';
-import { useEffect, useState } from "react";
-import { Column, ConnectInfo, DbType, Parameter, ParameterPattern, QueryResult } from "./types";
+import { useEffect, useRef, useState } from "react";
+import { ConnectInfo, DbType, Dialect, Parameter, ParameterPattern, QueryResult, ResultRow, Span, StatementResult, SubqueryNode } from "./types";
 import { Service } from "./services/Service";
 import { TauriService } from "./services/TauriService";
 import { Parameters } from "./components/Parameters";
@@ -18,8 +18,9 @@ import InfoOutlinedIcon from '@mui/icons-material/InfoOutlined';
 import { MaterialUISwitch } from "./components/MaterialUISwitch";
 
 import { Statements } from "./components/Statements";
-import { replaceParameters } from "./utils";
+import { byteOffsetToIndex, errorPosition, formatError, replaceParameters } from "./utils";
 import { QueryResultView } from "./components/QueryResultView";
+import { ScriptResultView } from "./components/ScriptResultView";
 import { createStore, Store } from "@tauri-apps/plugin-store";
 
 import { theme } from "./theme";
@@ -35,25 +36,38 @@ function App() {
   const [currentDisplayMode, setCurrentDisplayMode] = useState<"light" | "dark">("light");
 
   const [showConnectInfo, setShowConnectInfo] = useState<boolean>(true);
-  const [connectInfo, setConnectInfo] = useState<ConnectInfo>({ dbType: "postgres", url: "", db: "", user: "", password: "" });
+  const [connectInfo, setConnectInfo] = useState<ConnectInfo>({ dbType: "postgres", url: "", db: "", user: "", password: "", readOnly: false });
   const [connectStatus, setConnectStatus] = useState<ConnectStatus>("disconnect");
   const [connectionError, setConnectionError] = useState<string>("");
 
   const [showStatements, setShowStatements] = useState<boolean>(false);
   const [showResult, setShowResult] = useState<boolean>(false);
+  const [showScriptResult, setShowScriptResult] = useState<boolean>(false);
 
   const [sql, setSql] = useState<string>("");
+  const sqlInput = useRef<HTMLTextAreaElement>(null);
 
   const [parameters, setParameters] = useState<Parameter[]>([{ name: "", value: "" }]);
 
   const [parameterPattern, setParameterPattern] = useState<ParameterPattern>("mybatis");
 
+  // 取得する行数の上限(null なら上限無し)
+  const [rowLimit, setRowLimit] = useState<number | null>(1000);
+  // SELECT 文抽出で、解析できない文を飛ばして他の文から抽出する
+  const [recoverParse, setRecoverParse] = useState<boolean>(true);
+  // SQL の解析に使う dialect(null なら接続先の dialect。接続していなければ選ぶ必要がある)
+  const [dialect, setDialect] = useState<Dialect | null>(null);
 
-  const [columns, setColumns] = useState<Column[]>([]);
-  const [queryResult, setQueryResult] = useState<QueryResult>([]);
 
-  const [withStatements, setWithStatements] = useState<string[]>([]);
-  const [selectStatements, setSelectStatements] = useState<string[]>([]);
+  const [queryResult, setQueryResult] = useState<QueryResult>({ columns: [], rows: [], rowsAffected: 0, truncated: false });
+
+  const [statementResults, setStatementResults] = useState<StatementResult[]>([]);
+
+  const [selectStatements, setSelectStatements] = useState<SubqueryNode[]>([]);
+  const [outerRow, setOuterRow] = useState<ResultRow | undefined>(undefined);
+
+  // 実行中のクエリの id。キャンセルに使う
+  const [runningQueryId, setRunningQueryId] = useState<string | null>(null);
 
   const [error, setError] = useState<string>("");
 
@@ -61,12 +75,30 @@ function App() {
 
   const replacesSql = replaceParameters(sql, parameterPattern, parameters);
 
+  // SQL 中の範囲(バイト位置)を選択する
+  const selectSpan = (span: Span) => {
+    if (sqlInput.current) {
+      sqlInput.current.focus();
+      sqlInput.current.setSelectionRange(byteOffsetToIndex(sql, span.start), byteOffsetToIndex(sql, span.end));
+    }
+  };
+
+  // エラーを表示する。SQL 中の位置が分かれば、その位置を選択する
+  const showError = (e: unknown) => {
+    setError(formatError(e));
+    const position = errorPosition(e);
+    if (position) {
+      selectSpan(position);
+    }
+  };
+
   useEffect(() => {
     (async () => {
       store = await createStore("store.dat");
       const initial_connectInfo = await store.get<ConnectInfo>("connectInfo");
       if (initial_connectInfo) {
-        setConnectInfo(initial_connectInfo);
+        // 保存した時点に無かった項目は既定値にする
+        setConnectInfo({ readOnly: false, ...initial_connectInfo });
       }
 
       const initial_displayMode = await store.get<"light" | "dark">("displayMode");
@@ -110,7 +142,7 @@ function App() {
         <AccordionSummary
           expandIcon={<ArrowDropDownIcon />}
         >
-          <Typography>{connectStatus === "connect" ? "接続中: " : "接続情報: "}{`${connectInfo.dbType}://${connectInfo.user}:****@${connectInfo.url}/${connectInfo.db}`}</Typography>
+          <Typography>{connectStatus === "connect" ? "接続中: " : "接続情報: "}{`${connectInfo.dbType}://${connectInfo.user}:****@${connectInfo.url}/${connectInfo.db}`}{connectInfo.readOnly ? " (読み取り専用)" : ""}</Typography>
         </AccordionSummary>
         <AccordionDetails>
           <Stack spacing={2}>
@@ -123,10 +155,11 @@ function App() {
             >
               <FormControlLabel value="postgres" control={<Radio />} label="postgres" />
               <FormControlLabel value="mysql" control={<Radio />} label="mysql(alpha)" />
+              <FormControlLabel value="sqlite" control={<Radio />} label="sqlite(alpha)" />
             </RadioGroup>
             <TextField
-              label="サーバーアドレス"
-              placeholder="localhost:5432"
+              label={connectInfo.dbType === "sqlite" ? "データベースファイルパス" : "サーバーアドレス"}
+              placeholder={connectInfo.dbType === "sqlite" ? "/path/to/database.sqlite" : "localhost:5432"}
               fullWidth
               disabled={connectStatus === "connect"}
               InputProps={{
@@ -189,6 +222,18 @@ function App() {
                 }
               }}
             />
+            <FormControlLabel
+              label="読み取り専用(SELECT 以外は実行しない)"
+              disabled={connectStatus === "connect"}
+              control={
+                <Checkbox
+                  checked={connectInfo.readOnly}
+                  onChange={(event) => {
+                    setConnectInfo({ ...connectInfo, readOnly: event.currentTarget.checked });
+                  }}
+                />
+              }
+            />
             {
               connectStatus === "connect"
                 ?
@@ -218,7 +263,7 @@ function App() {
                           store.set("connectInfo", connectInfo);
                         }
                       } catch (e) {
-                        setConnectionError(e as string);
+                        setConnectionError(formatError(e));
                         setConnectStatus("disconnect");
                       }
                     }}
@@ -244,6 +289,7 @@ function App() {
           label="SQL"
           placeholder="select * from user;"
           multiline
+          inputRef={sqlInput}
           value={sql}
           onChange={(e) => {
             setSql(e.target.value);
@@ -256,41 +302,151 @@ function App() {
             variant="outlined"
             onClick={async () => {
               setError("");
+              const queryId = crypto.randomUUID();
+              setRunningQueryId(queryId);
               try {
-                const [columns, rows] = await service.query(replacesSql);
+                // 最初の行が届いた時点から表示する
+                const queryResult = await service.query(queryId, sql, parameterPattern, parameters, rowLimit, null, (partial) => {
+                  setShowResult(true);
+                  setQueryResult(partial);
+                });
                 setShowResult(true);
-                setColumns(columns.sort((a, b) => a.ordinal - b.ordinal));
-                setQueryResult(rows);
+                setQueryResult(queryResult);
               } catch (e) {
                 console.log(e);
-                setError(e as string);
+                showError(e);
               }
+              setRunningQueryId(null);
             }}
           >
             SQL 発行
           </Button>
+          <Button
+            disabled={!connectStatus}
+            variant="outlined"
+            onClick={async () => {
+              setError("");
+              const queryId = crypto.randomUUID();
+              setRunningQueryId(queryId);
+              try {
+                const statementResults = await service.executeScript(queryId, sql, parameterPattern, parameters, rowLimit);
+                setShowScriptResult(true);
+                setStatementResults(statementResults);
+              } catch (e) {
+                console.log(e);
+                showError(e);
+              }
+              setRunningQueryId(null);
+            }}
+          >
+            スクリプト実行
+          </Button>
+          <Button
+            disabled={!runningQueryId}
+            variant="outlined"
+            color="error"
+            onClick={async () => {
+              try {
+                if (runningQueryId) {
+                  await service.cancelQuery(runningQueryId);
+                }
+              } catch (e) {
+                console.log(e);
+                showError(e);
+              }
+            }}
+          >
+            キャンセル
+          </Button>
           <Button
             variant="outlined"
             onClick={async () => {
               setError("");
               try {
-                const [withStatement, selectStatements] = await service.findSelectStatement(replacesSql);
-                setWithStatements(withStatement);
-                setSelectStatements(selectStatements);
+                const selectStatements = await service.findSelectStatement(sql, parameterPattern, recoverParse, dialect);
+                setSelectStatements(selectStatements.nodes);
+                // 飛ばした文があれば、最初の文の位置を選択してすべてのエラーを表示する
+                if (selectStatements.errors.length > 0) {
+                  showError(selectStatements.errors[0]);
+                  setError(selectStatements.errors.map(formatError).join("\n"));
+                }
               } catch (e) {
                 console.log(e);
-                setError(e as string);
+                showError(e);
               }
               setShowStatements(true)
             }}
           >
             SELECT 文抽出
           </Button>
+          <FormControlLabel
+            label="解析できない文を飛ばす"
+            control={
+              <Checkbox
+                checked={recoverParse}
+                onChange={(event) => {
+                  setRecoverParse(event.currentTarget.checked);
+                }}
+              />
+            }
+          />
+          <Button
+            variant="outlined"
+            onClick={async () => {
+              setError("");
+              try {
+                const parameterInfos = await service.findParameters(sql, parameterPattern, dialect);
+                // 入力済みの値は残す
+                setParameters(parameterInfos.map((info) => ({
+                  name: info.name,
+                  value: parameters.find((p) => p.name === info.name)?.value ?? "",
+                  guessedType: info.guessedType ?? undefined,
+                })));
+              } catch (e) {
+                console.log(e);
+                showError(e);
+              }
+            }}
+          >
+            パラメーター抽出
+          </Button>
+          <TextField
+            size="small"
+            type="number"
+            label="行数の上限"
+            placeholder="上限無し"
+            value={rowLimit ?? ""}
+            onChange={(e) => {
+              const value = parseInt(e.target.value, 10);
+              setRowLimit(Number.isNaN(value) || value < 0 ? null : value);
+            }}
+          />
+          <TextField
+            select
+            size="small"
+            label="SQL の方言"
+            value={dialect ?? "connection"}
+            onChange={(e) => {
+              setDialect(e.target.value === "connection" ? null : e.target.value as Dialect);
+            }}
+          >
+            <MenuItem value="connection">接続先に合わせる</MenuItem>
+            <MenuItem value="generic">Generic</MenuItem>
+            <MenuItem value="ansi">ANSI</MenuItem>
+            <MenuItem value="postgres">PostgreSQL</MenuItem>
+            <MenuItem value="mysql">MySQL</MenuItem>
+            <MenuItem value="sqlite">SQLite</MenuItem>
+            <MenuItem value="mssql">SQL Server</MenuItem>
+            <MenuItem value="snowflake">Snowflake</MenuItem>
+            <MenuItem value="bigquery">BigQuery</MenuItem>
+            <MenuItem value="hive">Hive</MenuItem>
+            <MenuItem value="redshift">Redshift</MenuItem>
+          </TextField>
         </Box>
         <Typography>Replaced SQL:</Typography>
         {replacesSql}
       </Box >
-      <p>{error}</p>
+      <p style={{ whiteSpace: "pre-wrap" }}>{error}</p>
       <Divider sx={{ marginTop: "1em" }} />
       <Parameters
         parameters={parameters}
@@ -308,23 +464,42 @@ function App() {
         show={showStatements}
         parameterPattern={parameterPattern}
         parameters={parameters}
-        withStatements={withStatements}
+        rowLimit={rowLimit}
         selectStatements={selectStatements}
-        onStatementClick={(_columns, _rows) => {
+        outerRow={outerRow}
+        sourceSql={sql}
+        onStatementLocate={(node) => {
+          // 元の SQL 中のサブクエリを選択する
+          if (node.span) {
+            selectSpan(node.span);
+          }
+        }}
+        onStatementClick={(_queryResult) => {
           setError("");
-          //setColumns(columns.sort((a, b) => a.ordinal - b.ordinal));
-          //setQueryResult(rows);
+          //setQueryResult(queryResult);
         }}
         onError={(e) => {
-          setError(e as string);
+          showError(e);
         }}
 
       />
       <Divider sx={{ marginTop: "1em" }} />
+      <ScriptResultView
+        show={showScriptResult}
+        statementResults={statementResults}
+        onStatementLocate={(statementResult) => {
+          // 元の SQL 中の文を選択する
+          if (statementResult.span) {
+            selectSpan(statementResult.span);
+          }
+        }}
+        onErrorLocate={selectSpan}
+      />
+      <Divider sx={{ marginTop: "1em" }} />
       <QueryResultView
         show={showResult}
-        columns={columns}
         queryResult={queryResult}
+        onRowClick={setOuterRow}
       />
       <Dialog
         open={showNoticeDialog}
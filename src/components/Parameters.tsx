@@ -24,7 +24,7 @@ export const Parameters: React.FC<ParametersProps> = ({
           xs={5}
         >
           <TextField
-            placeholder={parameter.name}
+            value={parameter.name}
             sx={{ width: "100%" }}
             onChange={(e) => {
               const newName = e.currentTarget.value;
@@ -40,7 +40,8 @@ export const Parameters: React.FC<ParametersProps> = ({
           xs={5}
         >
           <TextField
-            placeholder={parameter.value}
+            value={parameter.value}
+            placeholder={parameter.guessedType}
             sx={{ width: "100%" }}
             onChange={(e) => {
               const newValue = e.currentTarget.value;
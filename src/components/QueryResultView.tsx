@@ -1,16 +1,28 @@
-import { Paper, Table, TableBody, TableCell, TableContainer, TableHead, TableRow, Typography } from "@mui/material";
-import { Column, QueryResult } from "../types";
+import { Paper, Table, TableBody, TableCell, TableContainer, TableHead, TablePagination, TableRow, Typography } from "@mui/material";
+import { QueryResult, ResultRow } from "../types";
+import { ValueCell } from "./ValueCell";
+import { useState } from "react";
 
 import ArrowCircleUpIcon from '@mui/icons-material/ArrowCircleUp';
 
 type QueryResultProps = {
   show: boolean,
-  columns: Column[],
   queryResult: QueryResult,
+  // 行を選ぶと呼ばれる(相関サブクエリの外側の値に使う)
+  onRowClick?: (resultRow: ResultRow) => void,
 };
 
-export const QueryResultView: React.FC<QueryResultProps> = ({ show, columns, queryResult }) => {
+export const QueryResultView: React.FC<QueryResultProps> = ({ show, queryResult, onRowClick }) => {
 
+  const [selectedRow, setSelectedRow] = useState<number | null>(null);
+
+  // 大きな結果でも描画が止まらないよう、ページに分けて表示する
+  const [page, setPage] = useState<number>(0);
+  const [rowsPerPage, setRowsPerPage] = useState<number>(100);
+  // 結果が入れ替わって行数が減った場合は、最後のページにする
+  const lastPage = Math.max(0, Math.ceil(queryResult.rows.length / rowsPerPage) - 1);
+  const currentPage = Math.min(page, lastPage);
+  const start = currentPage * rowsPerPage;
 
   return (
     <>
@@ -22,18 +34,44 @@ export const QueryResultView: React.FC<QueryResultProps> = ({ show, columns, que
               <Table>
                 <TableHead>
                   <TableRow>
-                    {columns.map((c) => <TableCell>{c.name}</TableCell>)}
+                    {/* 同名カラムがあり得るので、name ではなく ordinal をキーにする */}
+                    {queryResult.columns.map((c) => <TableCell key={c.ordinal} title={`${c.table ? c.table + "." : ""}${c.name}: ${c.typeName}${c.nullable === false ? " NOT NULL" : ""}`}>{c.name}</TableCell>)}
                   </TableRow>
                 </TableHead>
                 <TableBody>
-                  {queryResult.map((e => {
-                    return (<TableRow>
-                      {columns.map((c) => <TableCell>{e[c.name]}</TableCell>)}
+                  {queryResult.rows.slice(start, start + rowsPerPage).map(((row, j) => {
+                    const i = start + j;
+                    return (<TableRow
+                      key={i}
+                      hover={!!onRowClick}
+                      selected={selectedRow === i}
+                      sx={onRowClick ? { cursor: "pointer" } : {}}
+                      onClick={() => {
+                        if (onRowClick) {
+                          setSelectedRow(i);
+                          onRowClick({ columns: queryResult.columns, row });
+                        }
+                      }}
+                    >
+                      {queryResult.columns.map((c) => <ValueCell key={c.ordinal} value={row[c.ordinal]} />)}
                     </TableRow>)
                   }))}
                 </TableBody>
               </Table>
             </TableContainer>
+            {queryResult.truncated ? <Typography variant="caption">行数の上限で打ち切りました</Typography> : <></>}
+            <TablePagination
+              component="div"
+              count={queryResult.rows.length}
+              page={currentPage}
+              rowsPerPage={rowsPerPage}
+              rowsPerPageOptions={[100, 500, 1000]}
+              onPageChange={(_event, newPage) => setPage(newPage)}
+              onRowsPerPageChange={(event) => {
+                setRowsPerPage(parseInt(event.target.value, 10));
+                setPage(0);
+              }}
+            />
           </>
           :
           <>結果無し</>
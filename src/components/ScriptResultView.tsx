@@ -0,0 +1,80 @@
+import { Link, Paper, Stack, Table, TableBody, TableCell, TableContainer, TableHead, TableRow, Typography } from "@mui/material";
+import { Span, StatementResult } from "../types";
+import { errorPosition, formatError } from "../utils";
+import { ValueCell } from "./ValueCell";
+
+type ScriptResultProps = {
+  show: boolean,
+  statementResults: StatementResult[],
+  // 元の SQL 中の位置を表示する
+  onStatementLocate?: (statementResult: StatementResult) => void,
+  // 元の SQL 中のエラーの位置を表示する
+  onErrorLocate?: (position: Span) => void,
+};
+
+export const ScriptResultView: React.FC<ScriptResultProps> = ({ show, statementResults, onStatementLocate, onErrorLocate }) => {
+
+  return (
+    <>
+      <Typography>Script result:</Typography>
+      {
+        show ?
+          <Stack spacing={2}>
+            {
+              statementResults.map((statementResult, i) => {
+                const result = statementResult.result;
+                const position = errorPosition(statementResult.error);
+                return <Stack key={i} spacing={1}>
+                  {
+                    onStatementLocate && statementResult.span ?
+                      <Link sx={{ cursor: "pointer" }} onClick={() => onStatementLocate(statementResult)}>
+                        {`${statementResult.span.line}:${statementResult.span.column} ${statementResult.sql}`}
+                      </Link>
+                      :
+                      <Typography>{statementResult.sql}</Typography>
+                  }
+                  <Typography variant="caption">
+                    {
+                      result
+                        ? `${statementResult.elapsedMs} ms, ${result.columns.length > 0 ? `${result.rows.length} 行${result.truncated ? "(上限で打ち切り)" : ""}` : `${result.rowsAffected} 行変更`}`
+                        : `${statementResult.elapsedMs} ms, Error: ${statementResult.error ? formatError(statementResult.error) : ""}`
+                    }
+                  </Typography>
+                  {
+                    onErrorLocate && position ?
+                      <Link variant="caption" color="error" sx={{ cursor: "pointer" }} onClick={() => onErrorLocate(position)}>
+                        {`エラーの位置 (${position.line}:${position.column})`}
+                      </Link>
+                      : <></>
+                  }
+                  {
+                    // 結果のカラムがある文のみ表を出す
+                    result && result.columns.length > 0 ?
+                      <TableContainer component={Paper}>
+                        <Table size="small">
+                          <TableHead>
+                            <TableRow>
+                              {result.columns.map((c) => <TableCell key={c.ordinal}>{c.name}</TableCell>)}
+                            </TableRow>
+                          </TableHead>
+                          <TableBody>
+                            {result.rows.map((row, j) =>
+                              <TableRow key={j}>
+                                {result.columns.map((c) => <ValueCell key={c.ordinal} value={row[c.ordinal]} />)}
+                              </TableRow>
+                            )}
+                          </TableBody>
+                        </Table>
+                      </TableContainer>
+                      : <></>
+                  }
+                </Stack>
+              })
+            }
+          </Stack>
+          :
+          <>結果無し</>
+      }
+    </>
+  )
+}
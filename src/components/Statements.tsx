@@ -1,30 +1,73 @@
-import { Divider, Link, Stack, Typography } from "@mui/material";
+import { Divider, Link, Stack, TextField, Typography } from "@mui/material";
+import { useEffect, useState } from "react";
 import { Service } from "../services/Service";
-import { Column, Parameter, ParameterPattern, QueryResult } from "../types";
-import { replaceParameters } from "../utils";
+import { Parameter, ParameterPattern, QueryResult, ResultRow, SubqueryNode } from "../types";
+import { toLiteral } from "../utils";
 
 type StatementsProps = {
   service: Service,
   show: boolean,
   parameterPattern: ParameterPattern,
   parameters: Parameter[],
-  withStatements: string[],
-  selectStatements: string[],
-  onStatementClick: (columns: Column[], statement: QueryResult) => void,
+  // 取得する行数の上限(null なら上限無し)
+  rowLimit: number | null,
+  selectStatements: SubqueryNode[],
+  // 結果から選んだ行。相関サブクエリの外側の値に使う
+  outerRow?: ResultRow,
+  // サブクエリを取り出した元の SQL(SQL を入力したウィンドウのみ)。エラーの位置を元の SQL の位置にする
+  sourceSql?: string,
+  // 元の SQL 中の位置を表示する(SQL を入力したウィンドウのみ)
+  onStatementLocate?: (node: SubqueryNode) => void,
+  onStatementClick: (queryResult: QueryResult) => void,
   onError: (e: unknown) => void,
 };
 
+// node とその子孫を返す
+function subtree(nodes: SubqueryNode[], node: SubqueryNode): SubqueryNode[] {
+  const children = nodes.filter((n) => n.parentId === node.id);
+  return [node, ...children.flatMap((child) => subtree(nodes, child))];
+}
+
+// 一覧の中での深さ(親が一覧に無いノードは 0)
+function depth(nodes: SubqueryNode[], node: SubqueryNode): number {
+  const parent = nodes.find((n) => n.id === node.parentId);
+  return parent ? depth(nodes, parent) + 1 : 0;
+}
+
 export const Statements: React.FC<StatementsProps> = ({
   service,
   show,
   parameterPattern,
   parameters,
-  withStatements,
+  rowLimit,
   selectStatements,
+  outerRow,
+  sourceSql,
+  onStatementLocate,
   onStatementClick,
   onError,
 }) => {
 
+  // 相関サブクエリの外側の値(パラメーター名 => 値)
+  const [outerValues, setOuterValues] = useState<Record<string, string>>({});
+
+  // 実行中のサブクエリ。キャンセルに使う
+  const [running, setRunning] = useState<{ queryId: string, nodeId: number } | null>(null);
+
+  // 行が選ばれたら、同じ名前のカラムの値を外側の値にする
+  useEffect(() => {
+    if (!outerRow) {
+      return;
+    }
+    const values: Record<string, string> = {};
+    selectStatements.flatMap((node) => node.outerReferences).forEach((reference) => {
+      const column = outerRow.columns.find((c) => c.name.toLowerCase() === reference.column.toLowerCase());
+      if (column) {
+        values[reference.parameterName] = toLiteral(outerRow.row[column.ordinal]);
+      }
+    });
+    setOuterValues((current) => ({ ...current, ...values }));
+  }, [outerRow]);
 
   return (
     <>
@@ -34,19 +77,73 @@ export const Statements: React.FC<StatementsProps> = ({
           <>
             <Stack spacing={2}>
               {
-                selectStatements.map((sql, i) => {
-                  return <Link key={i} sx={{ cursor: "pointer" }} onClick={async () => {
-                    onError("");
-                    try {
-                      // with 句を追加する
-                      const [columns, row] = await service.query(replaceParameters(withStatements + " " + sql, parameterPattern, parameters));
-                      onStatementClick(columns, row);
-                      service.openNewStatementWindow(parameterPattern, parameters, [sql], columns, row)
-                    } catch (e) {
-                      console.log(e);
-                      onError(e as string);
+                selectStatements.map((node) => {
+                  const clause = node.previewOf ? `${node.previewOf} preview` : node.clause;
+                  const operation = node.setOperation ? [node.setOperation.operator, node.setOperation.quantifier].filter((s) => s).join(" ") : "";
+                  const label = `[${[clause, operation, node.alias].filter((s) => s).join(" ")}]`;
+                  const paddingLeft = depth(selectStatements, node) * 2;
+                  return <Stack key={node.id} spacing={1} sx={{ paddingLeft }}>
+                    <Link sx={{ cursor: "pointer" }} onClick={async () => {
+                      onError("");
+                      const queryId = crypto.randomUUID();
+                      setRunning({ queryId, nodeId: node.id });
+                      try {
+                        // 外側のカラムへの参照は、パラメーターとして値を渡す
+                        const outerParameters = node.outerReferences
+                          .filter((reference) => outerValues[reference.parameterName] !== undefined)
+                          .map((reference) => ({ name: reference.parameterName, value: outerValues[reference.parameterName] }));
+                        const nodeParameters = [...parameters, ...outerParameters];
+                        const source = sourceSql !== undefined && node.span ? { sql: sourceSql, span: node.span } : null;
+                        const queryResult = await service.query(queryId, node.runnableSql, parameterPattern, nodeParameters, rowLimit, source);
+                        onStatementClick(queryResult);
+                        // 新しいウィンドウでは、クリックしたサブクエリの中をたどれるようにする
+                        // ウィンドウの準備を待たずに戻るが、データを送れなかった場合はエラーを表示する
+                        service.openNewStatementWindow(parameterPattern, nodeParameters, subtree(selectStatements, node), queryResult, rowLimit)
+                          .catch((e) => {
+                            console.log(e);
+                            onError(e);
+                          });
+                      } catch (e) {
+                        console.log(e);
+                        onError(e);
+                      }
+                      setRunning(null);
+                    }}>{label}{node.correlated ? " [correlated]" : ""} {node.sql}</Link>
+                    {
+                      running?.nodeId === node.id ?
+                        <Link variant="caption" color="error" sx={{ cursor: "pointer" }} onClick={async () => {
+                          try {
+                            await service.cancelQuery(running.queryId);
+                          } catch (e) {
+                            console.log(e);
+                            onError(e);
+                          }
+                        }}>
+                          キャンセル
+                        </Link>
+                        : <></>
+                    }
+                    {
+                      onStatementLocate && node.span ?
+                        <Link variant="caption" sx={{ cursor: "pointer" }} onClick={() => onStatementLocate(node)}>
+                          {`SQL 中の位置 (${node.span.line}:${node.span.column})`}
+                        </Link>
+                        : <></>
+                    }
+                    {
+                      node.outerReferences.map((reference) =>
+                        <TextField
+                          key={reference.parameterName}
+                          size="small"
+                          label={reference.sql}
+                          value={outerValues[reference.parameterName] ?? ""}
+                          onChange={(e) => {
+                            setOuterValues({ ...outerValues, [reference.parameterName]: e.currentTarget.value });
+                          }}
+                        />
+                      )
                     }
-                  }}>{sql}</Link>
+                  </Stack>
                 })}
             </Stack>
             <Divider />
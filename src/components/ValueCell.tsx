@@ -0,0 +1,18 @@
+import { TableCell } from "@mui/material";
+import { Value } from "../types";
+import { describeDecodeError, formatValue } from "../utils";
+
+type ValueCellProps = {
+  value: Value,
+};
+
+// デコードできなかった値は NULL と区別できるよう色を変え、詳細をツールチップに出す
+export const ValueCell: React.FC<ValueCellProps> = ({ value }) => {
+  const decodeError = describeDecodeError(value);
+
+  return (
+    <TableCell title={decodeError} sx={decodeError ? { color: "error.main", fontStyle: "italic" } : {}}>
+      {formatValue(value)}
+    </TableCell>
+  )
+}
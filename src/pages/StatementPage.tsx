@@ -3,7 +3,7 @@ import { Statements } from "../components/Statements";
 import { Service } from "../services/Service";
 import { TauriService } from "../services/TauriService";
 import { emit } from "@tauri-apps/api/event";
-import { Column, Parameter, ParameterPattern, QueryResult } from "../types";
+import { Parameter, ParameterPattern, QueryResult, ResultRow, SubqueryNode } from "../types";
 import { QueryResultView } from "../components/QueryResultView";
 import { CssBaseline, Divider, ThemeProvider } from "@mui/material";
 
@@ -25,19 +25,20 @@ export const StatementPage: React.FC<StatementPageProps> = ({ }) => {
 
   const [parameterPattern, setParameterPattern] = useState<ParameterPattern>("jpa");
   const [parameters, setParameters] = useState<Parameter[]>([]);
-  const [selectStatements, setSelectStatements] = useState<string[]>([]);
-  const [columns, setColumns] = useState<Column[]>([]);
-  const [queryResult, setQueryResult] = useState<QueryResult>([]);
+  const [rowLimit, setRowLimit] = useState<number | null>(null);
+  const [selectStatements, setSelectStatements] = useState<SubqueryNode[]>([]);
+  const [queryResult, setQueryResult] = useState<QueryResult>({ columns: [], rows: [], rowsAffected: 0, truncated: false });
+  const [outerRow, setOuterRow] = useState<ResultRow | undefined>(undefined);
 
   useEffect(() => {
     if (!initialized) {
       getCurrentWebviewWindow().listen("data", (event) => {
-        const [parameterPattern, parameters, selectStatements, columns, queryResult] = event.payload as any;
+        const [parameterPattern, parameters, selectStatements, queryResult, rowLimit] = event.payload as any;
         setParameterPattern(parameterPattern);
         setParameters(parameters);
         setSelectStatements(selectStatements);
-        setColumns(columns);
         setQueryResult(queryResult);
+        setRowLimit(rowLimit);
       });
       emit("done", {});
       initialized = true;
@@ -61,16 +62,17 @@ export const StatementPage: React.FC<StatementPageProps> = ({ }) => {
         show={true}
         parameterPattern={parameterPattern}
         parameters={parameters}
-        withStatements={[]}
+        rowLimit={rowLimit}
         selectStatements={selectStatements}
+        outerRow={outerRow}
         onStatementClick={() => { }}
         onError={() => { }}
       />
       <Divider sx={{ marginTop: "1em" }} />
       <QueryResultView
         show={true}
-        columns={columns}
         queryResult={queryResult}
+        onRowClick={setOuterRow}
       />
     </ThemeProvider>
   )
@@ -1,10 +1,19 @@
-import { Column, ConnectInfo, Parameter, ParameterPattern, QueryResult } from "../types";
+import { ConnectInfo, Dialect, Parameter, ParameterInfo, ParameterPattern, QueryResult, QuerySource, SelectStatements, StatementResult, SubqueryNode } from "../types";
 
 export interface Service {
   connect(connectInfo: ConnectInfo): Promise<void>;
   close(): Promise<void>;
-  query(query: string): Promise<[Column[], QueryResult]>;
-  findSelectStatement(query: string): Promise<[string[], string[]]>;
-  openNewStatementWindow(parameterPattern: ParameterPattern, parameters: Parameter[], selectStatements: string[], columns: Column[], queryResult: QueryResult): Promise<void>;
+  // queryId は cancelQuery で実行中のクエリを止めるのに使う
+  // rowLimit を超える行は取得しない(null なら上限無し)
+  // source は query を取り出した元の SQL(あればエラーの位置を元の SQL の位置にする)
+  // onRows には、行が届くたびにそこまでの結果を渡す
+  query(queryId: string, query: string, parameterPattern: ParameterPattern, parameters: Parameter[], rowLimit: number | null, source: QuerySource | null, onRows?: (queryResult: QueryResult) => void): Promise<QueryResult>;
+  executeScript(queryId: string, query: string, parameterPattern: ParameterPattern, parameters: Parameter[], rowLimit: number | null): Promise<StatementResult[]>;
+  cancelQuery(queryId: string): Promise<void>;
+  // recover なら、解析できない文があっても ; で区切った他の文からは抽出する
+  // dialect が null なら接続先の dialect で解析する
+  findSelectStatement(query: string, parameterPattern: ParameterPattern, recover: boolean, dialect: Dialect | null): Promise<SelectStatements>;
+  findParameters(query: string, parameterPattern: ParameterPattern, dialect: Dialect | null): Promise<ParameterInfo[]>;
+  openNewStatementWindow(parameterPattern: ParameterPattern, parameters: Parameter[], selectStatements: SubqueryNode[], queryResult: QueryResult, rowLimit: number | null): Promise<void>;
 }
 
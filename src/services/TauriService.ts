@@ -1,5 +1,6 @@
 import { invoke } from "@tauri-apps/api/core";
-import { Column, ConnectInfo, Parameter, ParameterPattern, QueryResult } from "../types";
+import { listen } from "@tauri-apps/api/event";
+import { ConnectInfo, Dialect, Parameter, ParameterInfo, ParameterPattern, QueryResult, QuerySource, QuerySummary, RowBatch, SelectStatements, StatementResult, SubqueryNode } from "../types";
 import { Service } from "./Service";
 
 export class TauriService implements Service {
@@ -9,13 +10,57 @@ export class TauriService implements Service {
   async close(): Promise<void> {
     return await invoke("close_command", {})
   }
-  async query(query: string): Promise<[Column[], QueryResult]> {
-    return await invoke("query_command", { query })
+  async query(queryId: string, query: string, parameterPattern: ParameterPattern, parameters: Parameter[], rowLimit: number | null, source: QuerySource | null, onRows?: (queryResult: QueryResult) => void): Promise<QueryResult> {
+    // 行は query_rows イベントで少しずつ届く。大きな結果を何度もコピーしないよう、rows の配列は使い回す
+    const queryResult: QueryResult = { columns: [], rows: [], rowsAffected: 0, truncated: false };
+    let totalRows: number | null = null;
+    let resolveDone = () => { };
+    const done = new Promise<void>((resolve) => { resolveDone = resolve; });
+
+    const unlisten = await listen<RowBatch>("query_rows", (event) => {
+      const batch = event.payload;
+      if (batch.queryId !== queryId) {
+        return;
+      }
+      queryResult.columns = batch.columns;
+      queryResult.rows.push(...batch.rows);
+      onRows?.({ ...queryResult });
+      if (totalRows !== null && queryResult.rows.length >= totalRows) {
+        resolveDone();
+      }
+    });
+
+    try {
+      const summary = await invoke<QuerySummary>("query_command", { queryId, query, parameterPattern, parameters, rowLimit, source });
+      queryResult.columns = summary.columns;
+      queryResult.rowsAffected = summary.rowsAffected;
+      queryResult.truncated = summary.truncated;
+      // 最後の行がコマンドの戻り値より後に届くこともあるので、全部そろうまで待つ
+      // (行を送れなかった場合はコマンドが失敗するので、届かない行を待ち続けることは無い)
+      totalRows = summary.totalRows;
+      if (queryResult.rows.length >= totalRows) {
+        resolveDone();
+      }
+      await done;
+    } finally {
+      unlisten();
+    }
+
+    return queryResult;
+  }
+  async executeScript(queryId: string, query: string, parameterPattern: ParameterPattern, parameters: Parameter[], rowLimit: number | null): Promise<StatementResult[]> {
+    return await invoke("execute_script_command", { queryId, query, parameterPattern, parameters, rowLimit })
+  }
+  async cancelQuery(queryId: string): Promise<void> {
+    return await invoke("cancel_query_command", { queryId })
+  }
+  async findSelectStatement(query: string, parameterPattern: ParameterPattern, recover: boolean, dialect: Dialect | null): Promise<SelectStatements> {
+    return await invoke("find_select_statement_command", { query, parameterPattern, recover, dialect })
   }
-  async findSelectStatement(query: string): Promise<[string[], string[]]> {
-    return await invoke("find_select_statement_command", { query })
+  async findParameters(query: string, parameterPattern: ParameterPattern, dialect: Dialect | null): Promise<ParameterInfo[]> {
+    return await invoke("find_parameters_command", { query, parameterPattern, dialect })
   }
-  async openNewStatementWindow(parameterPattern: ParameterPattern, parameters: Parameter[], selectStatements: string[], columns: Column[], queryResult: QueryResult): Promise<void> {
-    return await invoke("open_new_statement_window_command", { parameterPattern, parameters, selectStatements, columns, queryResult })
+  async openNewStatementWindow(parameterPattern: ParameterPattern, parameters: Parameter[], selectStatements: SubqueryNode[], queryResult: QueryResult, rowLimit: number | null): Promise<void> {
+    return await invoke("open_new_statement_window_command", { parameterPattern, parameters, selectStatements, queryResult, rowLimit })
   }
 }
@@ -1,4 +1,4 @@
-export type DbType = "postgres" | "mysql";
+export type DbType = "postgres" | "mysql" | "sqlite";
 
 export type ConnectInfo = {
   dbType: DbType,
@@ -6,19 +6,169 @@ export type ConnectInfo = {
   db: string,
   user: string,
   password: string,
+  // 問い合わせ以外の文を実行せず、READ ONLY トランザクションの中で実行する
+  readOnly: boolean,
 };
 
 export type Parameter = {
   name: string,
   value: string,
+  guessedType?: string,
 };
 
-export type ParameterPattern = "mybatis" | "jpa" | "dapper";
+export type Span = {
+  start: number,
+  end: number,
+  line: number,
+  column: number,
+};
+
+export type ClauseKind =
+  | "Statement"
+  | "Cte"
+  | "SelectList"
+  | "From"
+  | "Join"
+  | "Where"
+  | "GroupBy"
+  | "Having"
+  | "OrderBy"
+  | "Limit"
+  | "SetOperation"
+  | "Set"
+  | "Source"
+  | "Other";
+
+export type SubqueryNode = {
+  id: number,
+  parentId: number | null,
+  clause: ClauseKind,
+  alias: string | null,
+  sql: string,
+  span: Span | null,
+  // 参照している CTE を前に付けた、単独で実行できる SQL
+  runnableSql: string,
+  ctes: string[],
+  // INSERT や UPDATE などの対象行のプレビューなら、元の文の種類("UPDATE" など)
+  previewOf: string | null,
+  // 集合演算(UNION など)のノードなら、その演算子
+  setOperation: SetOperation | null,
+  correlated: boolean,
+  outerReferences: OuterReference[],
+};
+
+// サブクエリの抽出結果。errors は回復モードで解析できなかった文のエラー
+export type SelectStatements = {
+  nodes: SubqueryNode[],
+  errors: CommandError[],
+};
+
+export type SetOperation = {
+  operator: string,
+  quantifier: string | null,
+};
+
+// 相関サブクエリが参照している外側のカラム
+export type OuterReference = {
+  sql: string,
+  qualifier: string,
+  column: string,
+  // runnableSql の中でこの参照を置き換えたパラメーターの名前
+  parameterName: string,
+};
+
+// 結果から選んだ行。相関サブクエリの外側の値に使う
+export type ResultRow = {
+  columns: Column[],
+  row: Value[],
+};
+
+export type ParameterInfo = {
+  name: string,
+  spans: Span[],
+  guessedType: string | null,
+};
+
+export type ParameterPattern = "mybatis" | "jpa" | "dapper" | "log";
+
+// SQL の解析に使う dialect(sql_parser::dialect_from_name の名前)
+export type Dialect = "generic" | "ansi" | "postgres" | "mysql" | "sqlite" | "mssql" | "snowflake" | "bigquery" | "hive" | "redshift";
 
 export type Column = {
   ordinal: number,
   name: string,
+  typeName: string,
+  nullable: boolean | null,
+  // SQL の FROM 句から推測したテーブル名(データベースから受け取ったものではない)
+  table: string | null,
 };
 
-export type QueryResult = { [key: string]: string }[];
+export type Value =
+  | { type: "Null" }
+  | { type: "Bool", value: boolean }
+  // number で正確に表せない値(Number.MAX_SAFE_INTEGER を超えるもの)は文字列
+  | { type: "Int", value: number | string }
+  | { type: "Float", value: number }
+  | { type: "Decimal", value: string }
+  | { type: "Text", value: string }
+  | { type: "Date", value: string }
+  | { type: "Time", value: string }
+  | { type: "Timestamp", value: string }
+  | { type: "Json", value: unknown }
+  | { type: "Bytes", value: number[] }
+  // 取得(デコード)できなかった値。NULL とは別に扱う
+  | { type: "DecodeError", value: { message: string, bytes: number[] } };
+
+export type QueryResult = {
+  columns: Column[],
+  rows: Value[][],
+  // INSERT や UPDATE などで変更された行数
+  rowsAffected: number,
+  // 行数の上限で打ち切ったか
+  truncated: boolean,
+};
+
+// query_command が少しずつ送る結果の行。offset は rows の最初の行が何行目か
+export type RowBatch = {
+  queryId: string,
+  offset: number,
+  columns: Column[],
+  rows: Value[][],
+};
+
+// query_command の戻り値。行は RowBatch で送り終えている
+export type QuerySummary = {
+  columns: Column[],
+  totalRows: number,
+  rowsAffected: number,
+  truncated: boolean,
+};
+
+// 実行する SQL を取り出した元の SQL と、その中の位置。エラーの位置を元の SQL の位置にするのに使う
+export type QuerySource = {
+  sql: string,
+  span: Span,
+};
+
+// スクリプトの各文の実行結果。失敗した文は result が null で error を持つ
+export type StatementResult = {
+  sql: string,
+  span: Span | null,
+  result: QueryResult | null,
+  elapsedMs: number,
+  error: CommandError | null,
+};
 
+// コマンドのエラー。Rust の CommandError に合わせる
+export type CommandError =
+  | { kind: "NotConnected" }
+  // position は SQL 中のエラーの位置(分からない場合は null)
+  // expected と found は、期待したトークンと見つかったトークン(分かる場合)
+  | { kind: "Parse", message: string, position: Span | null, expected: string | null, found: string | null }
+  // code は SQLSTATE など。detail, hint, position は PostgreSQL のみ、number は MySQL のエラー番号
+  | { kind: "Database", message: string, code: string | null, detail: string | null, hint: string | null, position: Span | null, number: number | null }
+  | { kind: "Decode", message: string }
+  | { kind: "Cancelled" }
+  | { kind: "Timeout" }
+  | { kind: "ReadOnly", statement: string }
+  | { kind: "Other", message: string };
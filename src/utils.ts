@@ -1,4 +1,4 @@
-import { Parameter } from "./types";
+import { CommandError, Parameter, Span, Value } from "./types";
 
 export function replaceParameters(
   query: string,
@@ -36,3 +36,105 @@ export function replaceParameters(
   }
 }
 
+
+// Rust から返るバイト位置を、JavaScript の文字列の位置にする
+export function byteOffsetToIndex(text: string, byteOffset: number): number {
+  const encoder = new TextEncoder();
+  let bytes = 0;
+  let index = 0;
+  for (const c of text) {
+    if (bytes >= byteOffset) {
+      break;
+    }
+    bytes += encoder.encode(c).length;
+    index += c.length;
+  }
+  return index;
+}
+
+// 値を SQL のリテラルにする(パラメーターの値として使う)
+export function toLiteral(value: Value): string {
+  switch (value.type) {
+    case "Null":
+      return "NULL";
+    case "Bool":
+    case "Int":
+    case "Float":
+    case "Decimal":
+      // 大きな Int や Decimal は数字の文字列なので、そのまま数値のリテラルになる
+      return String(value.value);
+    case "DecodeError":
+      // デコードできなかった値は、生のバイト列を文字列として渡す
+      return "'" + new TextDecoder().decode(new Uint8Array(value.value.bytes)).replaceAll("'", "''") + "'";
+    default:
+      return "'" + formatValue(value).replaceAll("'", "''") + "'";
+  }
+}
+
+function formatBytes(bytes: number[]): string {
+  return "0x" + bytes.map((b) => b.toString(16).padStart(2, "0")).join("");
+}
+
+// デコードできなかった値の詳細(エラーと生のバイト列)
+export function describeDecodeError(value: Value): string | undefined {
+  if (value.type !== "DecodeError") {
+    return undefined;
+  }
+  return `${value.value.message}\n${formatBytes(value.value.bytes)}`;
+}
+
+export function formatValue(value: Value): string {
+  switch (value.type) {
+    case "Null":
+      return "NULL";
+    case "Json":
+      return JSON.stringify(value.value);
+    case "Bytes":
+      return formatBytes(value.value);
+    case "DecodeError":
+      return "(decode error)";
+    default:
+      return String(value.value);
+  }
+}
+
+// コマンドのエラーを表示する文字列にする。CommandError 以外(文字列や Error)もそのまま表示する
+export function formatError(e: unknown): string {
+  if (typeof e !== "object" || e === null || !("kind" in e)) {
+    return String(e);
+  }
+  const error = e as CommandError;
+  switch (error.kind) {
+    case "NotConnected":
+      return "データベースに接続していません";
+    case "Parse":
+      return error.position ? `${error.message} (${error.position.line}:${error.position.column})` : error.message;
+    case "Database": {
+      const code = [error.code, error.number].filter((c) => c !== null).join(", ");
+      return [
+        code ? `${error.message} (${code})` : error.message,
+        error.detail ? `DETAIL: ${error.detail}` : "",
+        error.hint ? `HINT: ${error.hint}` : "",
+        error.position ? `(${error.position.line}:${error.position.column})` : "",
+      ].filter((s) => s).join(" ");
+    }
+    case "Decode":
+    case "Other":
+      return error.message;
+    case "Cancelled":
+      return "キャンセルしました";
+    case "Timeout":
+      return "接続の待ち時間を超えました";
+    case "ReadOnly":
+      return `読み取り専用の接続では ${error.statement} は実行できません`;
+  }
+}
+
+// エラーの SQL 中の位置(分からなければ null)
+export function errorPosition(e: unknown): Span | null {
+  if (typeof e !== "object" || e === null || !("kind" in e)) {
+    return null;
+  }
+  const error = e as CommandError;
+  return error.kind === "Parse" || error.kind === "Database" ? error.position : null;
+}